@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::PathBuf;
+
+/** On-disk storage for the files derived from one version of the player.
+Entries live under `$XDG_CACHE_HOME/yt_download/player/<player id>/`*/
+pub struct PlayerCache {
+    dir: PathBuf,
+}
+
+impl PlayerCache {
+    /** Opens the cache of the given player version, or None if there is no cache directory*/
+    pub fn open(player_id: &str) -> Option<PlayerCache> {
+        let dir = dirs::cache_dir()?
+            .join("yt_download")
+            .join("player")
+            .join(player_id);
+        Some(PlayerCache { dir })
+    }
+
+    /** Reads a cached entry, None if it is missing or unreadable*/
+    pub fn read(&self, name: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(name)).ok()
+    }
+
+    /** Stores an entry. The cache is an optimisation, so failures are ignored*/
+    pub fn write(&self, name: &str, contents: &str) {
+        if fs::create_dir_all(&self.dir).is_ok() {
+            let _ = fs::write(self.dir.join(name), contents);
+        }
+    }
+
+    /** Removes the entries of every other player version*/
+    pub fn prune(&self) {
+        let parent = match self.dir.parent() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if let Ok(entries) = fs::read_dir(parent) {
+            for entry in entries.flatten() {
+                if entry.path() != self.dir {
+                    let _ = fs::remove_dir_all(entry.path());
+                }
+            }
+        }
+    }
+}
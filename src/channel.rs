@@ -0,0 +1,321 @@
+use crate::innertube::{self, find_continuation, find_renderers};
+use crate::link::ChannelRef;
+use crate::playlist::Entry;
+use anyhow::anyhow;
+use json::JsonValue;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/** A tab of a channel page listing its uploads*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Tab {
+    Videos,
+    Shorts,
+    /** Past and current live broadcasts*/
+    Streams,
+}
+
+impl Tab {
+    /** The browse params selecting the tab*/
+    fn params(&self) -> &'static str {
+        match self {
+            Tab::Videos => "EgZ2aWRlb3PyBgQKAjoA",
+            Tab::Shorts => "EgZzaG9ydHPyBgUKA5oBAA==",
+            Tab::Streams => "EgdzdHJlYW1z8gYECgJ6AA==",
+        }
+    }
+
+    /** The title of the tab on the channel page*/
+    fn title(&self) -> &'static str {
+        match self {
+            Tab::Videos => "Videos",
+            Tab::Shorts => "Shorts",
+            Tab::Streams => "Live",
+        }
+    }
+}
+
+impl Display for Tab {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Tab::Videos => "videos",
+            Tab::Shorts => "shorts",
+            Tab::Streams => "streams",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Tab {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "videos" => Ok(Tab::Videos),
+            "shorts" => Ok(Tab::Shorts),
+            "streams" | "live" => Ok(Tab::Streams),
+            _ => Err(anyhow!(
+                "Unknown channel tab \"{}\", expected videos, shorts or streams",
+                s
+            )),
+        }
+    }
+}
+
+/** The uploads of a channel listed by one of its tabs*/
+#[derive(Debug, Clone)]
+pub struct Channel {
+    id: String,
+    title: String,
+    tab: Tab,
+    entries: Vec<Entry>,
+}
+
+impl Channel {
+    /** The channel id, starting with "UC"*/
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_tab(&self) -> Tab {
+        self.tab
+    }
+
+    /** The videos from the newest to the oldest*/
+    pub fn get_entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/** Seconds of a length text like "1:02:03"*/
+fn parse_length(text: &str) -> Option<u64> {
+    text.split(':').try_fold(0, |seconds, part| {
+        Some(seconds * 60 + part.parse::<u64>().ok()?)
+    })
+}
+
+/** Appends the videos of a page, which are videoRenderers on the videos and streams tabs,
+and reelItemRenderers or shortsLockupViewModels on the shorts tab*/
+fn parse_entries(value: &JsonValue, entries: &mut Vec<Entry>) {
+    let mut items = Vec::new();
+    find_renderers(value, "richItemRenderer", &mut items);
+    for item in items {
+        let content = &item["content"];
+        let (video_id, title, length) = if content["videoRenderer"].is_object() {
+            let renderer = &content["videoRenderer"];
+            (
+                renderer["videoId"].as_str(),
+                innertube::text(&renderer["title"]),
+                innertube::text(&renderer["lengthText"]).and_then(|l| parse_length(&l)),
+            )
+        } else if content["reelItemRenderer"].is_object() {
+            let renderer = &content["reelItemRenderer"];
+            (
+                renderer["videoId"].as_str(),
+                innertube::text(&renderer["headline"]),
+                None,
+            )
+        } else if content["shortsLockupViewModel"].is_object() {
+            let model = &content["shortsLockupViewModel"];
+            (
+                model["onTap"]["innertubeCommand"]["reelWatchEndpoint"]["videoId"].as_str(),
+                model["overlayMetadata"]["primaryText"]["content"]
+                    .as_str()
+                    .map(str::to_owned),
+                None,
+            )
+        } else {
+            continue;
+        };
+        if let Some(video_id) = video_id {
+            entries.push(Entry::new(
+                entries.len() + 1,
+                video_id.to_owned(),
+                title.unwrap_or_default(),
+                length,
+            ));
+        }
+    }
+}
+
+/** The content of the selected tab, or None if the channel lacks the requested tab
+and YouTube fell back to another one*/
+fn selected_tab(value: &JsonValue, tab: Tab) -> Option<&JsonValue> {
+    let mut tabs = Vec::new();
+    find_renderers(value, "tabRenderer", &mut tabs);
+    tabs.into_iter()
+        .find(|t| t["selected"].as_bool() == Some(true))
+        .filter(|t| t["title"].as_str() == Some(tab.title()))
+        .map(|t| &t["content"])
+}
+
+/** The "UC..." id of a channel, handles and legacy names are resolved through their url*/
+pub async fn resolve_channel_id(channel: &ChannelRef) -> anyhow::Result<String> {
+    if let ChannelRef::Id(id) = channel {
+        return Ok(id.clone());
+    }
+    let client = reqwest::Client::new();
+    let url = channel.get_url();
+    let value = innertube::resolve_url(&client, &url).await?;
+    value["endpoint"]["browseEndpoint"]["browseId"]
+        .as_str()
+        .filter(|id| id.starts_with("UC"))
+        .map(str::to_owned)
+        .ok_or(anyhow!("{} is not a channel", url))
+}
+
+/** Requests a tab of a channel and all of its continuation pages*/
+pub async fn get_channel(channel_id: &str, tab: Tab) -> anyhow::Result<Channel> {
+    let client = reqwest::Client::new();
+    let value = innertube::browse(&client, channel_id, Some(tab.params()), None).await?;
+    let metadata = &value["metadata"]["channelMetadataRenderer"];
+    let title = metadata["title"]
+        .as_str()
+        .ok_or(anyhow!("Channel {} cannot be read", channel_id))?
+        .to_owned();
+    let content =
+        selected_tab(&value, tab).ok_or(anyhow!("Channel \"{}\" has no {} tab", title, tab))?;
+
+    let mut entries = Vec::new();
+    parse_entries(content, &mut entries);
+    let mut continuation = find_continuation(content);
+    while let Some(token) = continuation {
+        let page = innertube::browse(&client, channel_id, None, Some(&token)).await?;
+        let before = entries.len();
+        parse_entries(&page, &mut entries);
+        continuation = find_continuation(&page).filter(|_| entries.len() > before);
+    }
+
+    Ok(Channel {
+        id: metadata["externalId"]
+            .as_str()
+            .unwrap_or(channel_id)
+            .to_owned(),
+        title,
+        tab,
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** A videos tab page, trimmed to the fields that are read*/
+    const VIDEOS_PAGE: &str = r#"{
+        "contents": {"twoColumnBrowseResultsRenderer": {"tabs": [
+            {"tabRenderer": {"title": "Home", "selected": false, "content": {}}},
+            {"tabRenderer": {"title": "Videos", "selected": true, "content": {
+                "richGridRenderer": {"contents": [
+                    {"richItemRenderer": {"content": {"videoRenderer": {
+                        "videoId": "aaaaaaaaaaa",
+                        "title": {"runs": [{"text": "A long video"}]},
+                        "lengthText": {"simpleText": "1:02:03"}
+                    }}}},
+                    {"richItemRenderer": {"content": {"videoRenderer": {
+                        "videoId": "bbbbbbbbbbb",
+                        "title": {"runs": [{"text": "An upcoming premiere"}]}
+                    }}}},
+                    {"richItemRenderer": {"content": {"adSlotRenderer": {}}}},
+                    {"continuationItemRenderer": {"continuationEndpoint": {
+                        "continuationCommand": {"token": "next-page"}
+                    }}}
+                ]}
+            }}}
+        ]}}
+    }"#;
+
+    /** A shorts tab page with both the old and the new renderer of shorts*/
+    const SHORTS_PAGE: &str = r#"{
+        "contents": {"twoColumnBrowseResultsRenderer": {"tabs": [
+            {"tabRenderer": {"title": "Shorts", "selected": true, "content": {
+                "richGridRenderer": {"contents": [
+                    {"richItemRenderer": {"content": {"reelItemRenderer": {
+                        "videoId": "ccccccccccc",
+                        "headline": {"simpleText": "An old short"}
+                    }}}},
+                    {"richItemRenderer": {"content": {"shortsLockupViewModel": {
+                        "onTap": {"innertubeCommand": {"reelWatchEndpoint": {
+                            "videoId": "ddddddddddd"
+                        }}},
+                        "overlayMetadata": {"primaryText": {"content": "A new short"}}
+                    }}}}
+                ]}
+            }}}
+        ]}}
+    }"#;
+
+    fn entries(page: &JsonValue) -> Vec<(usize, String, String, Option<u64>)> {
+        let mut entries = Vec::new();
+        parse_entries(page, &mut entries);
+        entries
+            .iter()
+            .map(|e| {
+                (
+                    e.get_index(),
+                    e.get_video_id().to_owned(),
+                    e.get_title().to_owned(),
+                    e.get_length_seconds(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_lengths() {
+        assert_eq!(parse_length("1:02:03"), Some(3723));
+        assert_eq!(parse_length("4:05"), Some(245));
+        assert_eq!(parse_length("59"), Some(59));
+        assert_eq!(parse_length("LIVE"), None);
+        assert_eq!(parse_length(""), None);
+    }
+
+    #[test]
+    fn parses_video_renderers() {
+        let page = json::parse(VIDEOS_PAGE).unwrap();
+        let content = selected_tab(&page, Tab::Videos).unwrap();
+        assert_eq!(
+            entries(content),
+            [
+                (1, "aaaaaaaaaaa".into(), "A long video".into(), Some(3723)),
+                (2, "bbbbbbbbbbb".into(), "An upcoming premiere".into(), None),
+            ]
+        );
+        assert_eq!(find_continuation(content).as_deref(), Some("next-page"));
+    }
+
+    #[test]
+    fn parses_shorts() {
+        let page = json::parse(SHORTS_PAGE).unwrap();
+        let content = selected_tab(&page, Tab::Shorts).unwrap();
+        assert_eq!(
+            entries(content),
+            [
+                (1, "ccccccccccc".into(), "An old short".into(), None),
+                (2, "ddddddddddd".into(), "A new short".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_missing_tabs() {
+        // Channels without the requested tab get their home or videos tab selected
+        let page = json::parse(VIDEOS_PAGE).unwrap();
+        assert!(selected_tab(&page, Tab::Shorts).is_none());
+        assert!(selected_tab(&page, Tab::Streams).is_none());
+        let page = json::parse(SHORTS_PAGE).unwrap();
+        assert!(selected_tab(&page, Tab::Videos).is_none());
+    }
+
+    #[test]
+    fn parses_tabs() {
+        assert_eq!("Shorts".parse::<Tab>().unwrap(), Tab::Shorts);
+        assert_eq!("live".parse::<Tab>().unwrap(), Tab::Streams);
+        assert_eq!(Tab::Streams.to_string(), "streams");
+        assert!("community".parse::<Tab>().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::anyhow;
+use regex::Regex;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+lazy_static! {
+    static ref DECIPHER_FUNCTION: Regex = Regex::new(
+        "=function\\(([a-zA-Z0-9_$]+)\\)\\{[a-zA-Z0-9_$]+=[a-zA-Z0-9_$]+\\.split\\(\"\"\\);([^}]+?);return [a-zA-Z0-9_$]+\\.join\\(\"\"\\)\\}"
+    )
+    .unwrap();
+    static ref DECIPHER_CALL: Regex = Regex::new(
+        "([a-zA-Z0-9_$]+)(?:\\.([a-zA-Z0-9_$]+)|\\[\"([a-zA-Z0-9_$]+)\"\\])\\([a-zA-Z0-9_$]+,(\\d+)\\)"
+    )
+    .unwrap();
+    static ref HELPER_METHOD: Regex =
+        Regex::new("([a-zA-Z0-9_$]+|\"[a-zA-Z0-9_$]+\"):function\\([a-zA-Z0-9_$,]*\\)\\{([^}]*)\\}")
+            .unwrap();
+}
+
+/** A single scramble step applied by the player to a signature*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CipherOp {
+    Reverse,
+    Splice(usize),
+    Swap(usize),
+}
+
+/** The sequence of scramble steps extracted from the player's base.js*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cipher {
+    ops: Vec<CipherOp>,
+}
+
+impl Cipher {
+    /** Locates the decipher function and its helper object in the player script*/
+    pub fn from_player_js(js: &str) -> anyhow::Result<Cipher> {
+        let body = DECIPHER_FUNCTION
+            .captures(js)
+            .ok_or(anyhow!("Decipher function was not found in player script"))?
+            .get(2)
+            .ok_or(anyhow!("Decipher function was not found in player script"))?
+            .as_str();
+
+        let calls: Vec<(&str, &str, usize)> = DECIPHER_CALL
+            .captures_iter(body)
+            .map(|c| {
+                let object = c.get(1).unwrap().as_str();
+                let method = c.get(2).or_else(|| c.get(3)).unwrap().as_str();
+                let arg = c[4].parse::<usize>().unwrap_or(0);
+                (object, method, arg)
+            })
+            .collect();
+        let object = calls
+            .first()
+            .ok_or(anyhow!("Decipher function has no operations"))?
+            .0;
+
+        let helper = Regex::new(&format!(
+            "var {}=\\{{((?s:.)*?)\\}};",
+            regex::escape(object)
+        ))?;
+        let helper = helper
+            .captures(js)
+            .ok_or(anyhow!(
+                "Decipher helper object \"{}\" was not found",
+                object
+            ))?
+            .get(1)
+            .ok_or(anyhow!(
+                "Decipher helper object \"{}\" was not found",
+                object
+            ))?
+            .as_str();
+
+        let methods: Vec<(&str, &str)> = HELPER_METHOD
+            .captures_iter(helper)
+            .map(|c| {
+                let name = c.get(1).unwrap().as_str().trim_matches('"');
+                (name, c.get(2).unwrap().as_str())
+            })
+            .collect();
+
+        let mut ops = Vec::with_capacity(calls.len());
+        for (_, method, arg) in calls {
+            let (_, code) = methods
+                .iter()
+                .find(|(name, _)| *name == method)
+                .ok_or(anyhow!("Decipher method \"{}\" was not found", method))?;
+            let op = if code.contains("reverse") {
+                CipherOp::Reverse
+            } else if code.contains("splice") {
+                CipherOp::Splice(arg)
+            } else {
+                CipherOp::Swap(arg)
+            };
+            ops.push(op);
+        }
+        Ok(Cipher { ops })
+    }
+
+    /** Applies the scramble steps to an encrypted signature*/
+    pub fn decipher(&self, signature: &str) -> String {
+        let mut chars: Vec<char> = signature.chars().collect();
+        for op in &self.ops {
+            match *op {
+                CipherOp::Reverse => chars.reverse(),
+                CipherOp::Splice(n) => {
+                    chars.drain(..n.min(chars.len()));
+                }
+                CipherOp::Swap(n) => {
+                    if !chars.is_empty() {
+                        let len = chars.len();
+                        chars.swap(0, n % len);
+                    }
+                }
+            }
+        }
+        chars.into_iter().collect()
+    }
+}
+
+/** Serializes the operations as a compact program like "r s3 w12", used by the player cache*/
+impl Display for Cipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ops: Vec<String> = self
+            .ops
+            .iter()
+            .map(|op| match op {
+                CipherOp::Reverse => "r".to_owned(),
+                CipherOp::Splice(n) => format!("s{}", n),
+                CipherOp::Swap(n) => format!("w{}", n),
+            })
+            .collect();
+        write!(f, "{}", ops.join(" "))
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ops = Vec::new();
+        for op in s.split_whitespace() {
+            // Cache entries are untrusted, so the first character may span several bytes
+            let mut chars = op.chars();
+            let kind = chars.next();
+            let arg = chars.as_str();
+            ops.push(match kind {
+                Some('r') if arg.is_empty() => CipherOp::Reverse,
+                Some('s') => CipherOp::Splice(arg.parse()?),
+                Some('w') => CipherOp::Swap(arg.parse()?),
+                _ => return Err(anyhow!("Unknown cipher operation \"{}\"", op)),
+            });
+        }
+        Ok(Cipher { ops })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** The relevant parts of a player script, with the helper object ahead of the function*/
+    const PLAYER_JS: &str = concat!(
+        "var x=1;var Xy={aB:function(a){a.reverse()},",
+        "cD:function(a,b){a.splice(0,b)},",
+        "\"eF\":function(a,b){var c=a[0];a[0]=a[b%a.length];a[b%a.length]=c}};",
+        "var y=2;Zz=function(a){a=a.split(\"\");Xy.cD(a,2);Xy[\"eF\"](a,3);Xy.aB(a,1);",
+        "return a.join(\"\")};var z=3;"
+    );
+
+    #[test]
+    fn extracts_operations() {
+        let cipher = Cipher::from_player_js(PLAYER_JS).unwrap();
+        assert_eq!(
+            cipher.ops,
+            vec![CipherOp::Splice(2), CipherOp::Swap(3), CipherOp::Reverse]
+        );
+    }
+
+    #[test]
+    fn deciphers_signatures() {
+        let cipher = Cipher::from_player_js(PLAYER_JS).unwrap();
+        // "cdefghij" after the splice, "fdecghij" after the swap
+        assert_eq!(cipher.decipher("abcdefghij"), "jihgcedf");
+        assert_eq!(cipher.decipher(""), "");
+        // Swaps wrap around the length
+        let swap = Cipher {
+            ops: vec![CipherOp::Swap(5)],
+        };
+        assert_eq!(swap.decipher("abc"), "cba");
+    }
+
+    #[test]
+    fn rejects_unknown_players() {
+        let error = Cipher::from_player_js("var a=1;").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Decipher function was not found in player script"
+        );
+        let missing = PLAYER_JS.replace("var Xy=", "var Yx=");
+        let error = Cipher::from_player_js(&missing).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Decipher helper object \"Xy\" was not found"
+        );
+    }
+
+    #[test]
+    fn round_trips_cached_programs() {
+        let cipher: Cipher = "s2 w3 r".parse().unwrap();
+        assert_eq!(cipher, Cipher::from_player_js(PLAYER_JS).unwrap());
+        assert_eq!(cipher.to_string(), "s2 w3 r");
+    }
+
+    #[test]
+    fn rejects_corrupted_programs() {
+        for program in ["é3 r", "s x", "w", "r1", "q2"] {
+            assert!(program.parse::<Cipher>().is_err(), "{}", program);
+        }
+        assert_eq!(
+            "s2 ü".parse::<Cipher>().unwrap_err().to_string(),
+            "Unknown cipher operation \"ü\""
+        );
+    }
+}
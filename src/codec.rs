@@ -0,0 +1,430 @@
+use std::fmt::{Display, Formatter};
+
+/** Transfer characteristics (ITU-T H.273) of SMPTE ST 2084 (PQ) and ARIB STD-B67 (HLG)*/
+const HDR_TRANSFERS: [u32; 2] = [16, 18];
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum VCodec {
+    H263,
+    H264,
+    H265,
+    MPEG4,
+    VP8,
+    VP9,
+    AV1,
+    NONE,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ACodec {
+    MP3,
+    AAC,
+    VORBIS,
+    OPUS,
+    AC3,
+    EAC3,
+    NONE,
+}
+
+impl Display for VCodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            VCodec::H263 => "H.263",
+            VCodec::H264 => "H.264",
+            VCodec::H265 => "H.265",
+            VCodec::MPEG4 => "MPEG-4",
+            VCodec::VP8 => "VP8",
+            VCodec::VP9 => "VP9",
+            VCodec::AV1 => "AV1",
+            VCodec::NONE => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for ACodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ACodec::MP3 => "MP3",
+            ACodec::AAC => "AAC",
+            ACodec::VORBIS => "Vorbis",
+            ACodec::OPUS => "Opus",
+            ACodec::AC3 => "AC-3",
+            ACodec::EAC3 => "E-AC-3",
+            ACodec::NONE => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/** A video codec together with the parameters encoded in its RFC 6381 string*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VideoCodec {
+    kind: VCodec,
+    profile: Option<u32>,
+    level: Option<u32>,
+    bit_depth: u8,
+    hdr: bool,
+}
+
+impl From<VCodec> for VideoCodec {
+    fn from(kind: VCodec) -> Self {
+        VideoCodec {
+            kind,
+            profile: None,
+            level: None,
+            bit_depth: 8,
+            hdr: false,
+        }
+    }
+}
+
+impl VideoCodec {
+    /** Parses codec strings like "avc1.640028", "vp09.02.51.10" or "av01.0.08M.08",
+    None if it is not a video codec*/
+    pub fn parse(codec: &str) -> Option<VideoCodec> {
+        let parts: Vec<&str> = codec.trim().split('.').collect();
+        let number = |i: usize| parts.get(i).and_then(|p| p.parse::<u32>().ok());
+        let hdr = |i: usize| number(i).is_some_and(|tc| HDR_TRANSFERS.contains(&tc));
+        let family = parts[0].to_lowercase();
+        let codec = match family.as_str() {
+            "avc1" | "avc3" => {
+                let params = parts.get(1).filter(|p| p.len() == 6 && p.is_ascii());
+                let profile = params.and_then(|p| u32::from_str_radix(&p[0..2], 16).ok());
+                VideoCodec {
+                    kind: VCodec::H264,
+                    profile,
+                    level: params.and_then(|p| u32::from_str_radix(&p[4..6], 16).ok()),
+                    // High 10, High 4:2:2 and High 4:4:4 Predictive profiles
+                    bit_depth: if matches!(profile, Some(110 | 122 | 244)) {
+                        10
+                    } else {
+                        8
+                    },
+                    hdr: false,
+                }
+            }
+            "hev1" | "hvc1" => {
+                let profile = parts.get(1).and_then(|p| {
+                    p.trim_start_matches(|c: char| c.is_ascii_alphabetic())
+                        .parse()
+                        .ok()
+                });
+                VideoCodec {
+                    kind: VCodec::H265,
+                    profile,
+                    level: parts
+                        .get(3)
+                        .and_then(|p| p.get(1..))
+                        .and_then(|p| p.parse().ok()),
+                    bit_depth: if profile == Some(2) { 10 } else { 8 },
+                    hdr: false,
+                }
+            }
+            "vp09" => VideoCodec {
+                kind: VCodec::VP9,
+                profile: number(1),
+                level: number(2),
+                bit_depth: number(3).map_or(8, |d| d as u8),
+                hdr: hdr(6),
+            },
+            "vp9" => VCodec::VP9.into(),
+            "vp8" => VCodec::VP8.into(),
+            "av01" => VideoCodec {
+                kind: VCodec::AV1,
+                profile: number(1),
+                level: parts.get(2).and_then(|p| {
+                    p.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+                        .parse()
+                        .ok()
+                }),
+                bit_depth: number(3).map_or(8, |d| d as u8),
+                hdr: hdr(7),
+            },
+            "mp4v" => VCodec::MPEG4.into(),
+            "h263" | "s263" => VCodec::H263.into(),
+            _ => return None,
+        };
+        Some(codec)
+    }
+
+    /** The codec family*/
+    pub fn get_kind(&self) -> &VCodec {
+        &self.kind
+    }
+
+    /** The profile number, e.g. profile_idc for H.264 or seq_profile for AV1*/
+    pub fn get_profile(&self) -> Option<u32> {
+        self.profile
+    }
+
+    /** The level number as written in the codec string, e.g. 40 for H.264 level 4.0*/
+    pub fn get_level(&self) -> Option<u32> {
+        self.level
+    }
+
+    /** Bits per color sample*/
+    pub fn get_bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    /** Whether the codec string declares a PQ or HLG transfer function*/
+    pub fn is_hdr(&self) -> bool {
+        self.hdr
+    }
+
+    fn profile_name(&self) -> Option<String> {
+        let profile = self.profile?;
+        let name = match (&self.kind, profile) {
+            (VCodec::H264, 66) => "Baseline",
+            (VCodec::H264, 77) => "Main",
+            (VCodec::H264, 88) => "Extended",
+            (VCodec::H264, 100) => "High",
+            (VCodec::H264, 110) => "High 10",
+            (VCodec::H264, 122) => "High 4:2:2",
+            (VCodec::H264, 244) => "High 4:4:4",
+            (VCodec::H265, 1) => "Main",
+            (VCodec::H265, 2) => "Main 10",
+            (VCodec::AV1, 0) => "Main",
+            (VCodec::AV1, 1) => "High",
+            (VCodec::AV1, 2) => "Professional",
+            _ => return Some(format!("Profile {}", profile)),
+        };
+        Some(name.to_owned())
+    }
+
+    fn level_name(&self) -> Option<String> {
+        let level = self.level?;
+        Some(match self.kind {
+            VCodec::H264 | VCodec::VP9 => format!("{}.{}", level / 10, level % 10),
+            VCodec::H265 => format!("{}.{}", level / 30, level % 30 / 3),
+            // seq_level_idx enumerates levels 2.0 to 7.3, four minor versions each
+            VCodec::AV1 => format!("{}.{}", 2 + level / 4, level % 4),
+            _ => level.to_string(),
+        })
+    }
+}
+
+impl Display for VideoCodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        match (self.profile_name(), self.level_name()) {
+            (Some(profile), Some(level)) => write!(f, " {}@{}", profile, level)?,
+            (Some(profile), None) => write!(f, " {}", profile)?,
+            _ => {}
+        }
+        if self.bit_depth != 8 {
+            write!(f, " {}-bit", self.bit_depth)?;
+        }
+        if self.hdr {
+            write!(f, " HDR")?;
+        }
+        Ok(())
+    }
+}
+
+/** An audio codec together with the parameters encoded in its RFC 6381 string*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AudioCodec {
+    kind: ACodec,
+    object_type: Option<u32>,
+}
+
+impl From<ACodec> for AudioCodec {
+    fn from(kind: ACodec) -> Self {
+        AudioCodec {
+            kind,
+            object_type: None,
+        }
+    }
+}
+
+impl AudioCodec {
+    /** Parses codec strings like "mp4a.40.2", "opus" or "ec-3", None if it is not an audio codec*/
+    pub fn parse(codec: &str) -> Option<AudioCodec> {
+        let codec = codec.trim().to_lowercase();
+        let parts: Vec<&str> = codec.split('.').collect();
+        let codec = match parts[0] {
+            "mp4a" => match parts.get(1).copied() {
+                // MPEG-4 audio, the third part is the audio object type
+                Some("40") => match parts.get(2).and_then(|p| p.parse::<u32>().ok()) {
+                    Some(34) => ACodec::MP3.into(),
+                    object_type => AudioCodec {
+                        kind: ACodec::AAC,
+                        object_type,
+                    },
+                },
+                Some("69") | Some("6b") => ACodec::MP3.into(),
+                Some("a5") => ACodec::AC3.into(),
+                Some("a6") => ACodec::EAC3.into(),
+                _ => ACodec::AAC.into(),
+            },
+            "mp3" => ACodec::MP3.into(),
+            "opus" => ACodec::OPUS.into(),
+            "vorbis" => ACodec::VORBIS.into(),
+            "ac-3" => ACodec::AC3.into(),
+            "ec-3" => ACodec::EAC3.into(),
+            _ => return None,
+        };
+        Some(codec)
+    }
+
+    /** The codec family*/
+    pub fn get_kind(&self) -> &ACodec {
+        &self.kind
+    }
+
+    /** The MPEG-4 audio object type, e.g. 2 for AAC-LC*/
+    pub fn get_object_type(&self) -> Option<u32> {
+        self.object_type
+    }
+}
+
+impl Display for AudioCodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.kind, self.object_type) {
+            (ACodec::AAC, Some(2)) => write!(f, "AAC-LC"),
+            (ACodec::AAC, Some(5)) => write!(f, "HE-AAC"),
+            (ACodec::AAC, Some(29)) => write!(f, "HE-AACv2"),
+            (ACodec::AAC, Some(object_type)) => write!(f, "AAC (object type {})", object_type),
+            (kind, _) => write!(f, "{}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(codec: &str) -> VideoCodec {
+        VideoCodec::parse(codec).unwrap()
+    }
+
+    fn audio(codec: &str) -> AudioCodec {
+        AudioCodec::parse(codec).unwrap()
+    }
+
+    #[test]
+    fn parses_h264() {
+        let codec = video("avc1.640028");
+        assert_eq!(*codec.get_kind(), VCodec::H264);
+        assert_eq!(codec.get_profile(), Some(100));
+        assert_eq!(codec.get_level(), Some(40));
+        assert_eq!(codec.get_bit_depth(), 8);
+        assert!(!codec.is_hdr());
+        assert_eq!(codec.to_string(), "H.264 High@4.0");
+
+        assert_eq!(video("avc1.4d401e").to_string(), "H.264 Main@3.0");
+        assert_eq!(video("AVC1.42001E").to_string(), "H.264 Baseline@3.0");
+        assert_eq!(video("avc3.6e0033").to_string(), "H.264 High 10@5.1 10-bit");
+    }
+
+    #[test]
+    fn parses_h264_without_parameters() {
+        let codec = video("avc1");
+        assert_eq!(*codec.get_kind(), VCodec::H264);
+        assert_eq!(codec.get_profile(), None);
+        assert_eq!(codec.get_level(), None);
+        assert_eq!(codec.to_string(), "H.264");
+        // Parameters must be six hex digits
+        assert_eq!(video("avc1.64002").get_profile(), None);
+    }
+
+    #[test]
+    fn parses_h265() {
+        let codec = video("hvc1.2.4.L153.B0");
+        assert_eq!(*codec.get_kind(), VCodec::H265);
+        assert_eq!(codec.get_profile(), Some(2));
+        assert_eq!(codec.get_level(), Some(153));
+        assert_eq!(codec.get_bit_depth(), 10);
+        assert_eq!(codec.to_string(), "H.265 Main 10@5.1 10-bit");
+        assert_eq!(video("hev1.1.6.L93.B0").to_string(), "H.265 Main@3.1");
+    }
+
+    #[test]
+    fn parses_vp9() {
+        let codec = video("vp09.02.51.10.01.09.16.09.00");
+        assert_eq!(*codec.get_kind(), VCodec::VP9);
+        assert_eq!(codec.get_profile(), Some(2));
+        assert_eq!(codec.get_level(), Some(51));
+        assert_eq!(codec.get_bit_depth(), 10);
+        assert!(codec.is_hdr());
+        assert_eq!(codec.to_string(), "VP9 Profile 2@5.1 10-bit HDR");
+
+        assert_eq!(video("vp09.00.40.08").to_string(), "VP9 Profile 0@4.0");
+        assert_eq!(video("vp9").to_string(), "VP9");
+        assert_eq!(video("vp8").to_string(), "VP8");
+    }
+
+    #[test]
+    fn parses_av1() {
+        let codec = video("av01.0.08M.08");
+        assert_eq!(*codec.get_kind(), VCodec::AV1);
+        assert_eq!(codec.get_profile(), Some(0));
+        assert_eq!(codec.get_level(), Some(8));
+        assert_eq!(codec.get_bit_depth(), 8);
+        assert!(!codec.is_hdr());
+        assert_eq!(codec.to_string(), "AV1 Main@4.0");
+
+        let hdr = video("av01.0.13M.10.0.110.09.16.09.0");
+        assert!(hdr.is_hdr());
+        assert_eq!(hdr.to_string(), "AV1 Main@5.1 10-bit HDR");
+        // HLG is HDR as well, BT.709 is not
+        assert!(video("av01.0.09M.10.0.110.09.18.09.0").is_hdr());
+        assert!(!video("av01.0.09M.08.0.110.01.01.01.0").is_hdr());
+    }
+
+    #[test]
+    fn parses_other_video_codecs() {
+        assert_eq!(*video("mp4v.20.3").get_kind(), VCodec::MPEG4);
+        assert_eq!(*video("s263").get_kind(), VCodec::H263);
+    }
+
+    #[test]
+    fn rejects_non_video_codecs() {
+        assert_eq!(VideoCodec::parse("mp4a.40.2"), None);
+        assert_eq!(VideoCodec::parse("opus"), None);
+        assert_eq!(VideoCodec::parse(""), None);
+    }
+
+    #[test]
+    fn parses_mpeg4_audio() {
+        let codec = audio("mp4a.40.2");
+        assert_eq!(*codec.get_kind(), ACodec::AAC);
+        assert_eq!(codec.get_object_type(), Some(2));
+        assert_eq!(codec.to_string(), "AAC-LC");
+
+        assert_eq!(audio("mp4a.40.5").to_string(), "HE-AAC");
+        assert_eq!(audio("mp4a.40.29").to_string(), "HE-AACv2");
+        assert_eq!(audio("mp4a.40.42").to_string(), "AAC (object type 42)");
+        assert_eq!(audio("mp4a").to_string(), "AAC");
+        assert_eq!(audio("mp4a").get_object_type(), None);
+    }
+
+    #[test]
+    fn parses_other_audio_codecs() {
+        assert_eq!(*audio("mp4a.40.34").get_kind(), ACodec::MP3);
+        assert_eq!(*audio("mp4a.6B").get_kind(), ACodec::MP3);
+        assert_eq!(*audio("mp3").get_kind(), ACodec::MP3);
+        assert_eq!(audio("mp4a.a5").to_string(), "AC-3");
+        assert_eq!(audio("ec-3").to_string(), "E-AC-3");
+        assert_eq!(audio("Opus").to_string(), "Opus");
+        assert_eq!(audio(" vorbis ").to_string(), "Vorbis");
+    }
+
+    #[test]
+    fn rejects_non_audio_codecs() {
+        assert_eq!(AudioCodec::parse("avc1.640028"), None);
+        assert_eq!(AudioCodec::parse("flac"), None);
+    }
+
+    #[test]
+    fn names_codec_kinds() {
+        assert_eq!(VCodec::NONE.to_string(), "none");
+        assert_eq!(ACodec::EAC3.to_string(), "E-AC-3");
+        assert_eq!(VideoCodec::from(VCodec::AV1).to_string(), "AV1");
+        assert_eq!(AudioCodec::from(ACodec::OPUS).to_string(), "Opus");
+    }
+}
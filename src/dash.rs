@@ -0,0 +1,516 @@
+use crate::download::Progress;
+use crate::video::Stream;
+use anyhow::anyhow;
+use reqwest::Url;
+use roxmltree::{Document, Node};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/** A Representation of an MPD together with the urls of its segments*/
+#[derive(Debug, Clone)]
+pub(crate) struct Representation {
+    pub(crate) id: String,
+    pub(crate) mime_type: String,
+    pub(crate) codecs: Vec<String>,
+    pub(crate) bandwidth: Option<u64>,
+    pub(crate) width: Option<i32>,
+    pub(crate) height: Option<i32>,
+    pub(crate) frame_rate: Option<f64>,
+    pub(crate) audio_sampling_rate: Option<u32>,
+    init: Option<String>,
+    segments: Vec<String>,
+}
+
+impl Representation {
+    /** YouTube names representations after the itag of their format*/
+    pub(crate) fn get_itag(&self) -> Option<i32> {
+        self.id.parse().ok()
+    }
+}
+
+/** A parsed MPD, which for live streams is refreshed to learn about new segments*/
+#[derive(Debug, Clone)]
+struct Manifest {
+    dynamic: bool,
+    update_period: Option<f64>,
+    representations: Vec<Representation>,
+}
+
+/** Seconds of an ISO 8601 duration like "PT1H2M3.5S"*/
+fn parse_duration(duration: &str) -> Option<f64> {
+    let duration = duration.strip_prefix('P')?;
+    let (days, time) = duration.split_once('T').unwrap_or((duration, ""));
+    let mut seconds = 0.0;
+    if let Some(days) = days.strip_suffix('D') {
+        seconds += days.parse::<f64>().ok()? * 86400.0;
+    }
+    let mut number = String::new();
+    for c in time.chars() {
+        let unit = match c {
+            'H' => 3600.0,
+            'M' => 60.0,
+            'S' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    Some(seconds)
+}
+
+/** Frame rates are either a number or a fraction like "30000/1001"*/
+fn parse_frame_rate(rate: &str) -> Option<f64> {
+    match rate.split_once('/') {
+        Some((a, b)) => Some(a.parse::<f64>().ok()? / b.parse::<f64>().ok()?),
+        None => rate.parse().ok(),
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+/** Resolves the BaseURL child of a node against the base of its parent*/
+fn base_url(node: Node, base: &Url) -> anyhow::Result<Url> {
+    match child(node, "BaseURL").and_then(|b| b.text()) {
+        Some(url) => Ok(base.join(url.trim())?),
+        None => Ok(base.clone()),
+    }
+}
+
+/** Replaces the identifiers of a SegmentTemplate url*/
+fn fill_template(template: &str, id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut result = String::new();
+    let mut parts = template.split('$');
+    if let Some(first) = parts.next() {
+        result.push_str(first);
+    }
+    let mut inside = true;
+    for part in parts {
+        if !inside {
+            result.push_str(part);
+            inside = true;
+            continue;
+        }
+        inside = false;
+        let (name, width) = match part.split_once('%') {
+            Some((name, format)) => (
+                name,
+                format
+                    .trim_start_matches('0')
+                    .trim_end_matches('d')
+                    .parse()
+                    .unwrap_or(0),
+            ),
+            None => (part, 0),
+        };
+        let value = match name {
+            "" => "$".to_owned(),
+            "RepresentationID" => id.to_owned(),
+            "Number" => format!("{:0width$}", number, width = width),
+            "Bandwidth" => format!("{:0width$}", bandwidth, width = width),
+            "Time" => format!("{:0width$}", time, width = width),
+            _ => format!("${}$", part),
+        };
+        result.push_str(&value);
+    }
+    result
+}
+
+/** Segment urls of a SegmentTemplate, from its SegmentTimeline or fixed segment duration*/
+fn template_segments(
+    template: Node,
+    id: &str,
+    bandwidth: u64,
+    base: &Url,
+    period_duration: Option<f64>,
+) -> anyhow::Result<(Option<String>, Vec<String>)> {
+    let attribute = |name: &str| template.attribute(name);
+    let number = |name: &str| attribute(name).and_then(|v| v.parse::<u64>().ok());
+    let init = match attribute("initialization") {
+        Some(init) => Some(
+            base.join(&fill_template(init, id, bandwidth, 0, 0))?
+                .to_string(),
+        ),
+        None => None,
+    };
+    let media = attribute("media").ok_or(anyhow!("SegmentTemplate without media"))?;
+    let timescale = number("timescale").unwrap_or(1).max(1);
+    let start = number("startNumber").unwrap_or(1);
+
+    let mut segments = Vec::new();
+    if let Some(timeline) = child(template, "SegmentTimeline") {
+        let entries: Vec<Node> = timeline
+            .children()
+            .filter(|n| n.has_tag_name("S"))
+            .collect();
+        let value = |s: Node, name: &str| s.attribute(name).and_then(|v| v.parse::<i64>().ok());
+        let mut time = 0;
+        let mut index = start;
+        for (i, s) in entries.iter().enumerate() {
+            if let Some(t) = value(*s, "t") {
+                time = t as u64;
+            }
+            let duration = value(*s, "d").ok_or(anyhow!("SegmentTimeline entry without d"))? as u64;
+            let repeat = match value(*s, "r").unwrap_or(0) {
+                // A negative count repeats up to the next entry or the end of the period
+                r if r < 0 => {
+                    let end = match entries.get(i + 1) {
+                        Some(next) => value(*next, "t").map(|t| t as f64),
+                        None => period_duration.map(|d| d * timescale as f64),
+                    };
+                    end.map(|end| ((end - time as f64) / duration.max(1) as f64).ceil() - 1.0)
+                        .unwrap_or(0.0)
+                        .max(0.0) as i64
+                }
+                r => r,
+            };
+            for _ in 0..=repeat {
+                segments.push(
+                    base.join(&fill_template(media, id, bandwidth, index, time))?
+                        .to_string(),
+                );
+                time += duration;
+                index += 1;
+            }
+        }
+    } else {
+        let duration = number("duration").ok_or(anyhow!("SegmentTemplate without duration"))?;
+        let seconds = period_duration.ok_or(anyhow!("Unknown duration of the period"))?;
+        let count = (seconds * timescale as f64 / duration as f64).ceil() as u64;
+        for index in start..start + count {
+            let time = (index - start) * duration;
+            segments.push(
+                base.join(&fill_template(media, id, bandwidth, index, time))?
+                    .to_string(),
+            );
+        }
+    }
+    Ok((init, segments))
+}
+
+fn list_segments(list: Node, base: &Url) -> anyhow::Result<(Option<String>, Vec<String>)> {
+    let init = match child(list, "Initialization").and_then(|i| i.attribute("sourceURL")) {
+        Some(init) => Some(base.join(init)?.to_string()),
+        None => None,
+    };
+    let segments = list
+        .children()
+        .filter(|n| n.has_tag_name("SegmentURL"))
+        .filter_map(|n| n.attribute("media"))
+        .map(|media| Ok(base.join(media)?.to_string()))
+        .collect::<anyhow::Result<Vec<String>>>()?;
+    Ok((init, segments))
+}
+
+fn parse_manifest(text: &str, url: &Url) -> anyhow::Result<Manifest> {
+    let document = Document::parse(text)?;
+    let mpd = document.root_element();
+    if !mpd.has_tag_name("MPD") {
+        return Err(anyhow!("Not a DASH manifest"));
+    }
+    let mpd_base = base_url(mpd, url)?;
+    let presentation_duration = mpd
+        .attribute("mediaPresentationDuration")
+        .and_then(parse_duration);
+
+    let mut representations = Vec::new();
+    for period in mpd.children().filter(|n| n.has_tag_name("Period")) {
+        let period_base = base_url(period, &mpd_base)?;
+        let period_duration = period
+            .attribute("duration")
+            .and_then(parse_duration)
+            .or(presentation_duration);
+        for set in period
+            .children()
+            .filter(|n| n.has_tag_name("AdaptationSet"))
+        {
+            let set_base = base_url(set, &period_base)?;
+            for node in set.children().filter(|n| n.has_tag_name("Representation")) {
+                let base = base_url(node, &set_base)?;
+                // Attributes and segment information are inherited from the AdaptationSet
+                let attribute = |name: &str| node.attribute(name).or_else(|| set.attribute(name));
+                let id = node.attribute("id").unwrap_or("").to_owned();
+                let bandwidth = attribute("bandwidth").and_then(|b| b.parse().ok());
+
+                let (init, segments) = if let Some(template) =
+                    child(node, "SegmentTemplate").or_else(|| child(set, "SegmentTemplate"))
+                {
+                    template_segments(
+                        template,
+                        &id,
+                        bandwidth.unwrap_or(0),
+                        &base,
+                        period_duration,
+                    )?
+                } else if let Some(list) =
+                    child(node, "SegmentList").or_else(|| child(set, "SegmentList"))
+                {
+                    list_segments(list, &base)?
+                } else {
+                    // SegmentBase or a bare BaseURL, the whole media is a single file
+                    (None, vec![base.to_string()])
+                };
+
+                representations.push(Representation {
+                    mime_type: attribute("mimeType").unwrap_or("").to_owned(),
+                    codecs: attribute("codecs")
+                        .map(|c| c.split(',').map(|c| c.trim().to_owned()).collect())
+                        .unwrap_or_default(),
+                    bandwidth,
+                    width: attribute("width").and_then(|w| w.parse().ok()),
+                    height: attribute("height").and_then(|h| h.parse().ok()),
+                    frame_rate: attribute("frameRate").and_then(parse_frame_rate),
+                    audio_sampling_rate: attribute("audioSamplingRate")
+                        .and_then(|r| r.parse().ok()),
+                    id,
+                    init,
+                    segments,
+                });
+            }
+        }
+    }
+    Ok(Manifest {
+        dynamic: mpd.attribute("type") == Some("dynamic"),
+        update_period: mpd
+            .attribute("minimumUpdatePeriod")
+            .and_then(parse_duration),
+        representations,
+    })
+}
+
+async fn get_manifest(client: &reqwest::Client, url: &str) -> anyhow::Result<Manifest> {
+    let text = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_manifest(&text, &Url::parse(url)?)
+}
+
+/** Requests and parses the manifest of `dashManifestUrl`*/
+pub(crate) async fn get_representations(
+    client: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<Vec<Representation>> {
+    Ok(get_manifest(client, url).await?.representations)
+}
+
+/** Concatenates the init segment and all segments of the stream's representation, whose url
+is the manifest. Dynamic manifests are refreshed for new segments until they become static.
+The total of the reported progress is 0 as it is unknown*/
+pub(crate) async fn download(
+    stream: &Stream,
+    path: impl AsRef<Path>,
+    mut progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let itag = stream.get_format().get_itag();
+    let mut file = File::create(path)?;
+    let mut downloaded = 0;
+    let mut fetched = HashSet::new();
+    progress(Progress::new(0, 0));
+
+    loop {
+        let manifest = get_manifest(&client, stream.get_url()).await?;
+        let representation = manifest
+            .representations
+            .iter()
+            .find(|r| r.get_itag() == Some(itag))
+            .ok_or(anyhow!("The manifest has no representation {}", itag))?;
+
+        let urls = representation.init.iter().chain(&representation.segments);
+        for url in urls {
+            if !fetched.insert(url.clone()) {
+                continue;
+            }
+            let data = client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            file.write_all(&data)?;
+            downloaded += data.len() as u64;
+            progress(Progress::new(downloaded, 0));
+        }
+
+        if !manifest.dynamic {
+            return Ok(());
+        }
+        let period = manifest.update_period.unwrap_or(5.0).max(1.0);
+        tokio::time::sleep(Duration::from_secs_f64(period)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_templates() {
+        let template = "$RepresentationID$/$Bandwidth$/seg-$Number%05d$.m4s?t=$Time$&p=$$";
+        assert_eq!(
+            fill_template(template, "137", 4000, 42, 90000),
+            "137/4000/seg-00042.m4s?t=90000&p=$"
+        );
+        assert_eq!(fill_template("$Time%03d$", "", 0, 0, 123456), "123456");
+        // Unknown identifiers are left as they are
+        assert_eq!(fill_template("a$Foo$b", "", 0, 0, 0), "a$Foo$b");
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_duration("P1DT1S"), Some(86401.0));
+        assert_eq!(parse_duration("1S"), None);
+        assert_eq!(parse_frame_rate("30000/1001"), Some(30000.0 / 1001.0));
+        assert_eq!(parse_frame_rate("25"), Some(25.0));
+    }
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT10S">
+  <BaseURL>https://example.com/dash/</BaseURL>
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="4" startNumber="3"
+          initialization="$RepresentationID$/init-$Bandwidth$.mp4"
+          media="$RepresentationID$/seg-$Number%05d$.m4s?cost=$$"/>
+      <Representation id="137" bandwidth="4000" codecs="avc1.640028" width="1920"
+          height="1080" frameRate="30000/1001"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" codecs="mp4a.40.2" audioSamplingRate="44100">
+      <Representation id="140" bandwidth="128000">
+        <SegmentTemplate timescale="1000" media="a/$Time$-$Number$.m4s">
+          <SegmentTimeline>
+            <S t="0" d="2000" r="-1"/>
+            <S t="6000" d="1000" r="1"/>
+            <S d="1500" r="-1"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/webm" codecs="opus">
+      <Representation id="251">
+        <BaseURL>list/</BaseURL>
+        <SegmentList>
+          <Initialization sourceURL="init.webm"/>
+          <SegmentURL media="1.webm"/>
+          <SegmentURL media="2.webm"/>
+        </SegmentList>
+      </Representation>
+      <Representation id="250">
+        <BaseURL>https://cdn.example.com/full.webm</BaseURL>
+        <SegmentBase indexRange="700-1000">
+          <Initialization range="0-699"/>
+        </SegmentBase>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    fn representation<'a>(manifest: &'a Manifest, id: &str) -> &'a Representation {
+        manifest
+            .representations
+            .iter()
+            .find(|r| r.id == id)
+            .unwrap()
+    }
+
+    #[test]
+    fn lists_segments_of_templates() {
+        let url = Url::parse("https://example.com/manifest/dash/id/abc").unwrap();
+        let manifest = parse_manifest(MANIFEST, &url).unwrap();
+        assert!(!manifest.dynamic);
+        assert_eq!(manifest.representations.len(), 4);
+
+        let video = representation(&manifest, "137");
+        assert_eq!(video.get_itag(), Some(137));
+        assert_eq!(video.mime_type, "video/mp4");
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert_eq!(
+            video.init.as_deref(),
+            Some("https://example.com/dash/137/init-4000.mp4")
+        );
+        // Ten seconds of four second segments, numbered from startNumber
+        assert_eq!(
+            video.segments,
+            [
+                "https://example.com/dash/137/seg-00003.m4s?cost=$",
+                "https://example.com/dash/137/seg-00004.m4s?cost=$",
+                "https://example.com/dash/137/seg-00005.m4s?cost=$",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_timeline_entries() {
+        let url = Url::parse("https://example.com/manifest").unwrap();
+        let manifest = parse_manifest(MANIFEST, &url).unwrap();
+        let audio = representation(&manifest, "140");
+        assert_eq!(audio.codecs, ["mp4a.40.2"]);
+        assert_eq!(audio.audio_sampling_rate, Some(44100));
+        assert_eq!(audio.init, None);
+        let segments: Vec<&str> = audio
+            .segments
+            .iter()
+            .map(|s| s.trim_start_matches("https://example.com/dash/a/"))
+            .collect();
+        // r="-1" repeats up to the next t, and for the last entry up to the end of the period
+        assert_eq!(
+            segments,
+            [
+                "0-1.m4s",
+                "2000-2.m4s",
+                "4000-3.m4s",
+                "6000-4.m4s",
+                "7000-5.m4s",
+                "8000-6.m4s",
+                "9500-7.m4s",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_segments_of_lists_and_bases() {
+        let url = Url::parse("https://example.com/manifest").unwrap();
+        let manifest = parse_manifest(MANIFEST, &url).unwrap();
+        let list = representation(&manifest, "251");
+        assert_eq!(
+            list.init.as_deref(),
+            Some("https://example.com/dash/list/init.webm")
+        );
+        assert_eq!(
+            list.segments,
+            [
+                "https://example.com/dash/list/1.webm",
+                "https://example.com/dash/list/2.webm",
+            ]
+        );
+        // The index range of a SegmentBase lies within the single file
+        let base = representation(&manifest, "250");
+        assert_eq!(base.init, None);
+        assert_eq!(base.segments, ["https://cdn.example.com/full.webm"]);
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        let url = Url::parse("https://example.com/manifest").unwrap();
+        assert!(parse_manifest("<html></html>", &url).is_err());
+        assert!(parse_manifest("#EXTM3U", &url).is_err());
+        let template = MANIFEST.replace(" media=\"a/$Time$-$Number$.m4s\"", "");
+        assert_eq!(
+            parse_manifest(&template, &url).unwrap_err().to_string(),
+            "SegmentTemplate without media"
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use crate::mux::{self, Embeds};
+use crate::video::{Protocol, Stream};
+use crate::{dash, hls, otf};
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use reqwest::StatusCode;
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/** The state of a running download, passed to the progress callback*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Progress {
+    downloaded: u64,
+    total: u64,
+}
+
+impl Progress {
+    pub(crate) fn new(downloaded: u64, total: u64) -> Progress {
+        Progress { downloaded, total }
+    }
+
+    /** Bytes written so far*/
+    pub fn get_downloaded(&self) -> u64 {
+        self.downloaded
+    }
+
+    /** Size of the whole stream in bytes, 0 if it is not known like for live or segmented streams*/
+    pub fn get_total(&self) -> u64 {
+        self.total
+    }
+}
+
+/** The answer of a stream url to a probing request*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Probe {
+    status: Option<u16>,
+    reason: Option<String>,
+    content_length: Option<u64>,
+}
+
+impl Probe {
+    /** Whether the url answered with 200 OK*/
+    pub fn is_available(&self) -> bool {
+        self.status == Some(200)
+    }
+
+    /** The HTTP status code, None if the request failed*/
+    pub fn get_status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /** Why the stream is unavailable, None if it is available*/
+    pub fn get_reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /** Size of the media in bytes as told by the server, None for segmented streams*/
+    pub fn get_content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+}
+
+fn probe_request(
+    client: &reqwest::Client,
+    stream: &Stream,
+) -> anyhow::Result<reqwest::RequestBuilder> {
+    Ok(match stream.get_protocol() {
+        Protocol::Https => client.head(stream.get_url()),
+        Protocol::Otf => client.head(otf::segment_url(stream, 0)?),
+        // Playlist servers do not always answer HEAD requests
+        Protocol::Hls | Protocol::Dash => client.get(stream.get_url()),
+    })
+}
+
+/** Requests the stream url, turning network errors into an unavailable probe*/
+pub async fn probe(client: &reqwest::Client, stream: &Stream) -> Probe {
+    let response = match probe_request(client, stream) {
+        Ok(request) => request.send().await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            return Probe {
+                status: None,
+                reason: Some(e.to_string()),
+                content_length: None,
+            }
+        }
+    };
+    let status = response.status();
+    Probe {
+        status: Some(status.as_u16()),
+        reason: (status != StatusCode::OK).then(|| status.to_string()),
+        content_length: response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|l| l.to_str().ok()?.parse().ok())
+            .filter(|_| stream.get_protocol() == Protocol::Https),
+    }
+}
+
+/** Probes all streams with at most `parallelism` requests at a time, keeping their order*/
+pub async fn probe_all<'a>(
+    client: &reqwest::Client,
+    streams: &[&'a Stream],
+    parallelism: usize,
+) -> Vec<(&'a Stream, Probe)> {
+    futures_util::stream::iter(streams.iter().copied())
+        .map(|stream| async move { (stream, probe(client, stream).await) })
+        .buffered(parallelism.max(1))
+        .collect()
+        .await
+}
+
+/** Size of the ranges a parallel download is split into*/
+const RANGE_SIZE: u64 = 10 * 1024 * 1024;
+
+/** How often a failed range is requested again*/
+const RANGE_RETRIES: u32 = 3;
+
+/** The file a download is written to until it is complete*/
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/** Downloads a stream into a file, calling `progress` after every received chunk.
+The data is written to a `.part` file first, which a later call resumes with a Range request.
+Live HLS streams are written to the file directly, as they have no length to complete*/
+pub async fn download(
+    stream: &Stream,
+    path: impl AsRef<Path>,
+    mut progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let part = part_path(path);
+    match stream.get_protocol() {
+        Protocol::Https => {}
+        Protocol::Hls => return hls::download(stream, path, progress).await,
+        Protocol::Otf => {
+            otf::download(stream, &part, progress).await?;
+            return Ok(fs::rename(&part, path)?);
+        }
+        Protocol::Dash => {
+            dash::download(stream, &part, progress).await?;
+            return Ok(fs::rename(&part, path)?);
+        }
+    }
+
+    let mut offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    let client = reqwest::Client::new();
+    let mut request = client.get(stream.get_url());
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request.send().await?;
+
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The part file may already hold everything
+        if stream.get_format().get_content_length() == Some(offset) {
+            progress(Progress {
+                downloaded: offset,
+                total: offset,
+            });
+            return Ok(fs::rename(&part, path)?);
+        }
+        // Otherwise it is longer than the stream, so it is truncated and downloaded again
+        File::create(&part)?;
+        offset = 0;
+        response = client.get(stream.get_url()).send().await?;
+    }
+    let response = response.error_for_status()?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        offset = 0;
+    }
+
+    let total = offset
+        + response
+            .content_length()
+            .ok_or(anyhow!("No content length"))?;
+    progress(Progress {
+        downloaded: offset,
+        total,
+    });
+
+    let mut body = response.bytes_stream();
+
+    let mut file = if offset > 0 {
+        OpenOptions::new().append(true).open(&part)?
+    } else {
+        File::create(&part)?
+    };
+    let mut downloaded = offset;
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk)?;
+        downloaded += chunk.len() as u64;
+        progress(Progress { downloaded, total });
+    }
+
+    if downloaded != total {
+        return Err(anyhow!(
+            "Connection closed after {} of {} bytes",
+            downloaded,
+            total
+        ));
+    }
+    fs::rename(&part, path)?;
+    Ok(())
+}
+
+/** The file a single track is downloaded to before being merged into `path`*/
+fn track_path(path: &Path, stream: &Stream) -> PathBuf {
+    let format = stream.get_format();
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".f{}.{}",
+        format.get_itag(),
+        format.get_extension()
+    ));
+    path.with_file_name(name)
+}
+
+/** Downloads a video and an audio stream concurrently and muxes them into one file together with
+the embeds, each over up to `connections` connections. `progress` receives the summed progress
+of both downloads*/
+pub async fn download_merged(
+    video: &Stream,
+    audio: &Stream,
+    path: impl AsRef<Path>,
+    connections: usize,
+    embeds: &Embeds,
+    progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let video_path = track_path(path, video);
+    let audio_path = track_path(path, audio);
+
+    let state = RefCell::new((
+        [Progress {
+            downloaded: 0,
+            total: 0,
+        }; 2],
+        progress,
+    ));
+    let report = |index: usize, track: Progress| {
+        let (tracks, progress) = &mut *state.borrow_mut();
+        tracks[index] = track;
+        progress(Progress {
+            downloaded: tracks.iter().map(|p| p.downloaded).sum(),
+            total: tracks.iter().map(|p| p.total).sum(),
+        });
+    };
+    // A track file only exists once its download completed, so a retry can skip it
+    let (video_result, audio_result) = tokio::join!(
+        async {
+            if video_path.exists() {
+                Ok(())
+            } else {
+                download_parallel(video, &video_path, connections, |p| report(0, p)).await
+            }
+        },
+        async {
+            if audio_path.exists() {
+                Ok(())
+            } else {
+                download_parallel(audio, &audio_path, connections, |p| report(1, p)).await
+            }
+        }
+    );
+    video_result?;
+    audio_result?;
+
+    mux::merge_with(&[&video_path, &audio_path], path, embeds)?;
+    let _ = fs::remove_file(&video_path);
+    let _ = fs::remove_file(&audio_path);
+    Ok(())
+}
+
+/** Downloads a stream like [`download_parallel`] and remuxes it into `path` with the embeds*/
+pub async fn download_embedded(
+    stream: &Stream,
+    path: impl AsRef<Path>,
+    connections: usize,
+    embeds: &Embeds,
+    progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let track = track_path(path, stream);
+    if !track.exists() {
+        download_parallel(stream, &track, connections, progress).await?;
+    }
+    mux::merge_with(&[&track], path, embeds)?;
+    let _ = fs::remove_file(&track);
+    Ok(())
+}
+
+/** The inclusive byte ranges of at most [`RANGE_SIZE`] bytes covering `offset..total`*/
+fn ranges(offset: u64, total: u64) -> impl Iterator<Item = (u64, u64)> {
+    (offset..total)
+        .step_by(RANGE_SIZE as usize)
+        .map(move |start| (start, (start + RANGE_SIZE).min(total) - 1))
+}
+
+/** Fetches the bytes `start..=end` of the stream*/
+async fn fetch_range(
+    client: &reqwest::Client,
+    stream: &Stream,
+    (start, end): (u64, u64),
+    report: &dyn Fn(i64),
+) -> anyhow::Result<Vec<u8>> {
+    let response = client
+        .get(stream.get_url())
+        .header(RANGE, format!("bytes={}-{}", start, end))
+        .send()
+        .await?
+        .error_for_status()?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow!("Server ignored the range {}-{}", start, end));
+    }
+    let mut body = response.bytes_stream();
+    let mut data = Vec::with_capacity((end - start + 1) as usize);
+    let result = async {
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            data.extend_from_slice(&chunk);
+            report(chunk.len() as i64);
+        }
+        if data.len() as u64 != end - start + 1 {
+            return Err(anyhow!(
+                "Range {}-{} ended after {} bytes",
+                start,
+                end,
+                data.len()
+            ));
+        }
+        Ok(())
+    }
+    .await;
+    // A retried range starts over, so its bytes no longer count
+    if let Err(e) = result {
+        report(-(data.len() as i64));
+        return Err(e);
+    }
+    Ok(data)
+}
+
+/** Downloads a stream over up to `connections` concurrent connections, each fetching a range
+and retrying it individually. Streams of unknown length and other protocols are downloaded with
+[`download`]. Ranges are appended to the `.part` file in order, so it only ever holds the
+completed beginning of the stream and a later call resumes from its length. Up to
+`connections` × [`RANGE_SIZE`] bytes are held in memory until they can be written*/
+pub async fn download_parallel(
+    stream: &Stream,
+    path: impl AsRef<Path>,
+    connections: usize,
+    progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let total = match stream.get_format().get_content_length() {
+        Some(total) if connections > 1 && stream.get_protocol() == Protocol::Https => total,
+        _ => return download(stream, path, progress).await,
+    };
+    let part = part_path(path);
+    let mut offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    if offset > total {
+        offset = 0;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&part)?;
+    file.set_len(offset)?;
+    file.seek(SeekFrom::End(0))?;
+
+    let progress = RefCell::new((offset, progress));
+    let report = |delta: i64| {
+        let (downloaded, progress) = &mut *progress.borrow_mut();
+        *downloaded = (*downloaded as i64 + delta) as u64;
+        progress(Progress {
+            downloaded: *downloaded,
+            total,
+        });
+    };
+    report(0);
+
+    let client = reqwest::Client::new();
+    // Ranges are fetched concurrently but yielded in order, holding at most `connections`
+    // of them in memory
+    let mut results = futures_util::stream::iter(ranges(offset, total))
+        .map(|range| {
+            let (client, report) = (&client, &report);
+            async move {
+                let mut attempt = 0;
+                loop {
+                    match fetch_range(client, stream, range, report).await {
+                        Ok(data) => return Ok(data),
+                        Err(e) if attempt == RANGE_RETRIES => return Err(e),
+                        Err(_) => attempt += 1,
+                    }
+                }
+            }
+        })
+        .buffered(connections);
+
+    while let Some(data) = results.next().await {
+        file.write_all(&data?)?;
+    }
+    drop(results);
+    drop(file);
+    fs::rename(&part, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_into_whole_ranges() {
+        let split: Vec<_> = ranges(0, 3 * RANGE_SIZE).collect();
+        assert_eq!(
+            split,
+            vec![
+                (0, RANGE_SIZE - 1),
+                (RANGE_SIZE, 2 * RANGE_SIZE - 1),
+                (2 * RANGE_SIZE, 3 * RANGE_SIZE - 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_last_range_at_total() {
+        let split: Vec<_> = ranges(0, 2 * RANGE_SIZE + 1).collect();
+        assert_eq!(
+            split,
+            vec![
+                (0, RANGE_SIZE - 1),
+                (RANGE_SIZE, 2 * RANGE_SIZE - 1),
+                (2 * RANGE_SIZE, 2 * RANGE_SIZE),
+            ]
+        );
+        assert_eq!(ranges(0, 5).collect::<Vec<_>>(), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn resumes_from_offset() {
+        let split: Vec<_> = ranges(7, 2 * RANGE_SIZE + 1).collect();
+        assert_eq!(
+            split,
+            vec![(7, RANGE_SIZE + 6), (RANGE_SIZE + 7, 2 * RANGE_SIZE)]
+        );
+        assert_eq!(ranges(10, 10).count(), 0);
+    }
+}
@@ -1,21 +1,36 @@
 #![allow(dead_code)]
 
+pub use crate::codec::{ACodec, AudioCodec, VCodec, VideoCodec};
+use crate::dash::Representation;
+use crate::hls::Variant;
+use anyhow::anyhow;
+use json::JsonValue;
 use std::fmt::{Display, Formatter};
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct Format<'a> {
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Format {
     itag: i32,
-    ext: &'a str,
+    ext: String,
+    mime_type: String,
+    codecs: String,
+    width: i32,
     height: i32,
     fps: u32,
-    v_codec: VCodec,
-    a_codec: ACodec,
+    v_codec: VideoCodec,
+    a_codec: AudioCodec,
     audio_bitrate: i32,
+    bitrate: Option<u64>,
+    average_bitrate: Option<u64>,
+    content_length: Option<u64>,
+    quality_label: Option<String>,
+    audio_sample_rate: Option<u32>,
+    audio_channels: Option<u32>,
     is_dash_container: bool,
     is_hls_content: bool,
 }
 
-impl Format<'_> {
+impl Format {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         itag: i32,
         ext: &str,
@@ -29,17 +44,219 @@ impl Format<'_> {
     ) -> Format {
         Format {
             itag,
-            ext,
+            ext: ext.to_owned(),
+            mime_type: String::new(),
+            codecs: String::new(),
+            width: -1,
             height,
             fps,
-            v_codec,
-            a_codec,
+            v_codec: v_codec.into(),
+            a_codec: a_codec.into(),
             audio_bitrate,
+            bitrate: None,
+            average_bitrate: None,
+            content_length: None,
+            quality_label: None,
+            audio_sample_rate: None,
+            audio_channels: None,
             is_dash_container,
             is_hls_content,
         }
     }
 
+    /** Builds a format from an entry of streamingData's formats or adaptiveFormats.
+    Fields the entry lacks are taken from `fallback`, the statically known format of its itag*/
+    pub fn from_json(
+        value: &JsonValue,
+        adaptive: bool,
+        fallback: Option<&Format>,
+    ) -> anyhow::Result<Format> {
+        let itag = value["itag"]
+            .as_i32()
+            .ok_or(anyhow!("Cannot convert JsonValue to an int"))?;
+        let mime = value["mimeType"].as_str().unwrap_or("");
+        let (mime_type, codecs) = parse_mime_type(mime);
+        if mime_type.is_empty() && fallback.is_none() {
+            return Err(anyhow!(
+                "Format {} has neither mimeType nor a known itag",
+                itag
+            ));
+        }
+
+        let v_codec = codecs
+            .iter()
+            .find_map(|c| VideoCodec::parse(c))
+            .or_else(|| fallback.map(|f| f.v_codec.clone()))
+            .unwrap_or_else(|| VCodec::NONE.into());
+        let a_codec = codecs
+            .iter()
+            .find_map(|c| AudioCodec::parse(c))
+            .or_else(|| fallback.map(|f| f.a_codec.clone()))
+            .unwrap_or_else(|| ACodec::NONE.into());
+
+        let ext = extension(&mime_type, fallback);
+
+        let bitrate = value["bitrate"].as_u64();
+        let average_bitrate = value["averageBitrate"].as_u64();
+        let height = if mime_type.starts_with("audio/") {
+            -1
+        } else {
+            value["height"]
+                .as_i32()
+                .or_else(|| fallback.map(|f| f.height))
+                .unwrap_or(-1)
+        };
+        // The bitrates of muxed formats cover the video as well
+        let audio_bitrate = if *a_codec.get_kind() == ACodec::NONE {
+            -1
+        } else if *v_codec.get_kind() == VCodec::NONE {
+            average_bitrate
+                .or(bitrate)
+                .map(|b| (b / 1000) as i32)
+                .or_else(|| fallback.map(|f| f.audio_bitrate))
+                .unwrap_or(-1)
+        } else {
+            fallback.map(|f| f.audio_bitrate).unwrap_or(-1)
+        };
+
+        Ok(Format {
+            itag,
+            ext: ext.to_owned(),
+            mime_type,
+            codecs: codecs.join(", "),
+            width: value["width"].as_i32().unwrap_or(-1),
+            height,
+            fps: value["fps"]
+                .as_u32()
+                .or_else(|| fallback.map(|f| f.fps))
+                .unwrap_or(30),
+            v_codec,
+            a_codec,
+            audio_bitrate,
+            bitrate,
+            average_bitrate,
+            content_length: value["contentLength"]
+                .as_str()
+                .and_then(|l| l.parse().ok())
+                .or_else(|| value["contentLength"].as_u64()),
+            quality_label: value["qualityLabel"].as_str().map(str::to_owned),
+            audio_sample_rate: value["audioSampleRate"]
+                .as_str()
+                .and_then(|r| r.parse().ok())
+                .or_else(|| value["audioSampleRate"].as_u32()),
+            audio_channels: value["audioChannels"].as_u32(),
+            is_dash_container: adaptive,
+            is_hls_content: fallback.map(|f| f.is_hls_content).unwrap_or(false),
+        })
+    }
+
+    /** Builds a format from a variant of an HLS master playlist, whose segments are MPEG-TS*/
+    pub(crate) fn from_hls_variant(
+        itag: i32,
+        variant: &Variant,
+        fallback: Option<&Format>,
+    ) -> Format {
+        let v_codec = variant
+            .codecs
+            .iter()
+            .find_map(|c| VideoCodec::parse(c))
+            .or_else(|| fallback.map(|f| f.v_codec.clone()))
+            .unwrap_or_else(|| VCodec::NONE.into());
+        let a_codec = variant
+            .codecs
+            .iter()
+            .find_map(|c| AudioCodec::parse(c))
+            .or_else(|| fallback.map(|f| f.a_codec.clone()))
+            .unwrap_or_else(|| ACodec::NONE.into());
+        let (width, height) = variant
+            .resolution
+            .unwrap_or((-1, fallback.map(|f| f.height).unwrap_or(-1)));
+        Format {
+            itag,
+            ext: "ts".to_owned(),
+            mime_type: "video/mp2t".to_owned(),
+            codecs: variant.codecs.join(", "),
+            width,
+            height,
+            fps: variant
+                .frame_rate
+                .map(|f| f.round() as u32)
+                .or_else(|| fallback.map(|f| f.fps))
+                .unwrap_or(30),
+            v_codec,
+            a_codec,
+            audio_bitrate: fallback.map(|f| f.audio_bitrate).unwrap_or(-1),
+            bitrate: variant.bandwidth,
+            average_bitrate: None,
+            content_length: None,
+            quality_label: None,
+            audio_sample_rate: None,
+            audio_channels: None,
+            is_dash_container: false,
+            is_hls_content: true,
+        }
+    }
+
+    /** Builds a format from a Representation of a DASH manifest*/
+    pub(crate) fn from_dash_representation(
+        itag: i32,
+        representation: &Representation,
+        fallback: Option<&Format>,
+    ) -> Format {
+        let mime_type = representation.mime_type.to_lowercase();
+        let v_codec = representation
+            .codecs
+            .iter()
+            .find_map(|c| VideoCodec::parse(c))
+            .or_else(|| fallback.map(|f| f.v_codec.clone()))
+            .unwrap_or_else(|| VCodec::NONE.into());
+        let a_codec = representation
+            .codecs
+            .iter()
+            .find_map(|c| AudioCodec::parse(c))
+            .or_else(|| fallback.map(|f| f.a_codec.clone()))
+            .unwrap_or_else(|| ACodec::NONE.into());
+        let height = if mime_type.starts_with("audio/") {
+            -1
+        } else {
+            representation
+                .height
+                .or_else(|| fallback.map(|f| f.height))
+                .unwrap_or(-1)
+        };
+        // The bandwidth of a video representation is not its audio bitrate
+        let audio_bitrate = representation
+            .bandwidth
+            .filter(|_| height == -1)
+            .map(|b| (b / 1000) as i32)
+            .or_else(|| fallback.map(|f| f.audio_bitrate))
+            .unwrap_or(-1);
+        Format {
+            itag,
+            ext: extension(&mime_type, fallback).to_owned(),
+            codecs: representation.codecs.join(", "),
+            mime_type,
+            width: representation.width.unwrap_or(-1),
+            height,
+            fps: representation
+                .frame_rate
+                .map(|f| f.round() as u32)
+                .or_else(|| fallback.map(|f| f.fps))
+                .unwrap_or(30),
+            v_codec,
+            a_codec,
+            audio_bitrate,
+            bitrate: representation.bandwidth,
+            average_bitrate: None,
+            content_length: None,
+            quality_label: None,
+            audio_sample_rate: representation.audio_sampling_rate,
+            audio_channels: None,
+            is_dash_container: true,
+            is_hls_content: false,
+        }
+    }
+
     /** Get the frames per second */
     pub fn get_fps(&self) -> u32 {
         self.fps
@@ -57,7 +274,22 @@ impl Format<'_> {
 
     /**The file extension and conainer format like "mp4"*/
     pub fn get_extension(&self) -> &str {
-        self.ext
+        &self.ext
+    }
+
+    /** The mime type without parameters like "video/webm", empty if unknown*/
+    pub fn get_mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /** The codecs parameter of the mime type like "avc1.4d401e, mp4a.40.2"*/
+    pub fn get_codecs(&self) -> &str {
+        &self.codecs
+    }
+
+    /** The pixel width of the video stream or -1 if unknown or for audio files.*/
+    pub fn get_width(&self) -> i32 {
+        self.width
     }
 
     /** The pixel height of the video stream or -1 for audio files.*/
@@ -65,20 +297,86 @@ impl Format<'_> {
         self.height
     }
 
+    /** Peak bitrate of all tracks in bit/s*/
+    pub fn get_bitrate(&self) -> Option<u64> {
+        self.bitrate
+    }
+
+    /** Average bitrate of all tracks in bit/s*/
+    pub fn get_average_bitrate(&self) -> Option<u64> {
+        self.average_bitrate
+    }
+
+    /** Size of the media in bytes*/
+    pub fn get_content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /** The label shown by the YouTube player like "1080p60 HDR"*/
+    pub fn get_quality_label(&self) -> Option<&str> {
+        self.quality_label.as_deref()
+    }
+
+    /** Audio sample rate in Hz*/
+    pub fn get_audio_sample_rate(&self) -> Option<u32> {
+        self.audio_sample_rate
+    }
+
+    /** Number of audio channels*/
+    pub fn get_audio_channels(&self) -> Option<u32> {
+        self.audio_channels
+    }
+
     /** The audio codec of format*/
-    pub fn get_audio_codec(&self) -> &ACodec {
+    pub fn get_audio_codec(&self) -> &AudioCodec {
         &self.a_codec
     }
 
     /** The video codec of format*/
-    pub fn get_video_codec(&self) -> &VCodec {
+    pub fn get_video_codec(&self) -> &VideoCodec {
         &self.v_codec
     }
+
+    /** Whether the format is a separate DASH video or audio track*/
+    pub fn is_dash_container(&self) -> bool {
+        self.is_dash_container
+    }
+
+    /** Whether the format is served through an HLS manifest*/
+    pub fn is_hls_content(&self) -> bool {
+        self.is_hls_content
+    }
 }
 
-impl ToString for Format<'_> {
-    fn to_string(&self) -> String {
-        return format!(
+/** The file extension of a mime type, from the fallback format if the type is unknown*/
+fn extension<'a>(mime_type: &str, fallback: Option<&'a Format>) -> &'a str {
+    match mime_type {
+        "video/mp4" => "mp4",
+        "audio/mp4" => "m4a",
+        "video/webm" | "audio/webm" => "webm",
+        "video/3gpp" => "3gp",
+        "video/x-flv" => "flv",
+        _ => fallback.map(|f| f.ext.as_str()).unwrap_or("mp4"),
+    }
+}
+
+/** Splits `video/mp4; codecs="avc1.4d401e, mp4a.40.2"` into the type and its codecs*/
+fn parse_mime_type(mime: &str) -> (String, Vec<String>) {
+    let mut parts = mime.split(';');
+    let mime_type = parts.next().unwrap_or("").trim().to_lowercase();
+    let codecs = parts
+        .filter_map(|p| p.trim().strip_prefix("codecs="))
+        .flat_map(|c| c.trim_matches('"').split(','))
+        .map(|c| c.trim().to_owned())
+        .filter(|c| !c.is_empty())
+        .collect();
+    (mime_type, codecs)
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
             "Format (\
             itag={}, \
             ext=\'{}\', \
@@ -98,37 +396,6 @@ impl ToString for Format<'_> {
             self.audio_bitrate,
             self.is_dash_container,
             self.is_hls_content
-        );
-    }
-}
-
-#[derive(Debug, Eq, PartialEq)]
-pub enum VCodec {
-    H263,
-    H264,
-    MPEG4,
-    VP8,
-    VP9,
-    NONE,
-}
-
-#[derive(Debug, Eq, PartialEq)]
-pub enum ACodec {
-    MP3,
-    AAC,
-    VORBIS,
-    OPUS,
-    NONE,
-}
-
-impl Display for ACodec {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
-    }
-}
-
-impl Display for VCodec {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        )
     }
 }
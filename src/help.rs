@@ -4,10 +4,14 @@ use std::collections::HashMap;
 use crate::format::ACodec::*;
 use crate::format::VCodec::*;
 use crate::format::*;
-use regex::Regex;
+use crate::innertube::{self, Client};
+use crate::player::Player;
+use crate::video::{Protocol, Stream};
+use crate::{dash, hls};
+use json::JsonValue;
 
 lazy_static! {
-    static ref FORMAT_MAP: HashMap<i32, Format<'static>> = {
+    static ref FORMAT_MAP: HashMap<i32, Format> = {
         let mut m = HashMap::new();
         // Video and Audio
         m.insert(17, Format::new(17, "3gp", 144, 30, MPEG4, AAC, 24, false, false));
@@ -70,131 +74,231 @@ lazy_static! {
     };
 }
 
-pub async fn get_stream_urls(uri: &str) -> anyhow::Result<HashMap<i32, (String, &Format<'_>)>> {
+fn parse_query(query: &str) -> anyhow::Result<HashMap<String, String>> {
     let mut result = HashMap::new();
+    for pair in query.split('&') {
+        if let Some((key, value)) = pair.split_once('=') {
+            result.insert(key.to_owned(), urlencoding::decode(value)?.to_string());
+        }
+    }
+    Ok(result)
+}
 
-    let player_response = Regex::new("var ytInitialPlayerResponse\\s*=\\s*(\\{.+?\\})\\s*;")?;
-    let sig_enc_url = Regex::new("url=(.+?)(\\u0026|$)")?;
-    let signature = Regex::new("s=(.+?)(\\u0026|$)")?;
+/** Replaces the value of a query parameter, keeping the order of the others*/
+fn set_query_param(url: &str, key: &str, value: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some(parts) => parts,
+        None => return url.to_owned(),
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((k, _)) if k == key => format!("{}={}", k, urlencoding::encode(value)),
+            _ => pair.to_owned(),
+        })
+        .collect();
+    format!("{}?{}", base, query.join("&"))
+}
 
-    let client = reqwest::Client::new();
-    let text = client.get(uri).send().await?.text().await?;
-
-    let text = player_response
-        .captures(&text)
-        .ok_or(anyhow!("No captures were found on html page"))?
-        .get(1)
-        .ok_or(anyhow!("No captures were found on html page"))?
-        .as_str();
-
-    let value = json::parse(text)?;
-
-    let formats = &value["streamingData"]["formats"];
-    for i in 0..formats.len() {
-        let format = &formats[i];
-
-        let _type = &format["type"];
-
-        if !_type.is_null()
-            && _type
-                .as_str()
-                .ok_or(anyhow!("Cannot convert JsonValue to a string"))?
-                .eq("FORMAT_STREAM_TYPE_OTF")
-        {
-            continue;
+/** Rewrites the `n` parameter so the stream is not throttled*/
+fn unthrottle(url: String, player: &mut Player) -> anyhow::Result<String> {
+    let n = url
+        .split_once('?')
+        .and_then(|(_, query)| parse_query(query).ok())
+        .and_then(|mut params| params.remove("n"));
+    match n {
+        Some(n) => {
+            let n = player.transform_n(&n)?;
+            Ok(set_query_param(&url, "n", &n))
+        }
+        None => Ok(url),
+    }
+}
+
+/** Returns a playable url of the format or None if it has neither url nor signatureCipher*/
+fn get_format_url(
+    format: &JsonValue,
+    player: Option<&mut Player>,
+) -> anyhow::Result<Option<String>> {
+    if !format["url"].is_null() {
+        let url = format["url"]
+            .as_str()
+            .ok_or(anyhow!("Cannot convert JsonValue to a string"))?
+            .replace("\\u0026", "&");
+        return Ok(Some(url));
+    }
+    if format["signatureCipher"].is_null() {
+        return Ok(None);
+    }
+    let params = parse_query(
+        format["signatureCipher"]
+            .as_str()
+            .ok_or(anyhow!("Cannot convert JsonValue to a string"))?,
+    )?;
+    match (params.get("url"), params.get("s")) {
+        (Some(url), Some(signature)) => {
+            let signature = player
+                .ok_or(anyhow!("Player script is required to decipher signatures"))?
+                .decipher(signature)?;
+            let sp = params.get("sp").map(String::as_str).unwrap_or("signature");
+            Ok(Some(format!(
+                "{}&{}={}",
+                url,
+                sp,
+                urlencoding::encode(&signature)
+            )))
         }
+        _ => Ok(None),
+    }
+}
+
+/** Collects the known formats of a player response's streamingData*/
+fn parse_streaming_data(
+    value: &JsonValue,
+    mut player: Option<&mut Player>,
+) -> anyhow::Result<HashMap<i32, Stream>> {
+    let mut result = HashMap::new();
+
+    let formats = value["streamingData"]["formats"]
+        .members()
+        .map(|f| (f, false))
+        .chain(
+            value["streamingData"]["adaptiveFormats"]
+                .members()
+                .map(|f| (f, true)),
+        );
+
+    for (format, adaptive) in formats {
+        let protocol = match format["type"].as_str() {
+            Some("FORMAT_STREAM_TYPE_OTF") => Protocol::Otf,
+            _ => Protocol::Https,
+        };
 
         let itag = format["itag"]
             .as_i32()
             .ok_or(anyhow!("Cannot convert JsonValue to an int"))?;
 
-        if FORMAT_MAP.contains_key(&itag) {
-            if !format["url"].is_null() {
-                let url = format["url"]
-                    .as_str()
-                    .ok_or(anyhow!("Cannot convert JsonValue to a string"))?
-                    .replace("\\u0026", "&");
-                result.insert(itag, (url, FORMAT_MAP.get(&itag).unwrap()));
-            } else if !format["signatureCipher"].is_null() {
-                let cipher = format["signatureCipher"]
-                    .as_str()
-                    .ok_or(anyhow!("Cannot convert JsonValue to a string"))?;
-
-                if sig_enc_url.is_match(cipher) && signature.is_match(cipher) {
-                    let url = sig_enc_url
-                        .captures(&cipher)
-                        .ok_or(anyhow!("No captures were found on cipher"))?
-                        .get(1)
-                        .ok_or(anyhow!("No captures were found on html cipher"))?
-                        .as_str();
-                    let url = urlencoding::decode(url)?.to_string();
-
-                    let signature = signature
-                        .captures(&cipher)
-                        .ok_or(anyhow!("No captures were found on cipher"))?
-                        .get(1)
-                        .ok_or(anyhow!("No captures were found on html cipher"))?
-                        .as_str();
-                    let _signature = urlencoding::decode(signature)?.to_string(); //todo
-
-                    result.insert(itag, (url, FORMAT_MAP.get(&itag).unwrap()));
-                }
-            }
+        // Formats lacking both a mimeType and a table entry are of no use
+        let parsed = match Format::from_json(format, adaptive, FORMAT_MAP.get(&itag)) {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+        if let Some(url) = get_format_url(format, player.as_deref_mut())? {
+            // A stream with an untransformed n still plays, only throttled
+            let url = match player.as_deref_mut() {
+                Some(player) => unthrottle(url.clone(), player).unwrap_or(url),
+                None => url,
+            };
+            result.insert(itag, Stream::with_protocol(url, parsed, protocol));
         }
     }
+    Ok(result)
+}
 
-    let adaptive_formats = &value["streamingData"]["adaptiveFormats"];
-
-    for i in 0..adaptive_formats.len() {
-        let format = &adaptive_formats[i];
-        let _type = &format["type"];
+/** Turns the variants of an HLS master playlist into streams keyed by itag*/
+async fn get_hls_streams(
+    client: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<HashMap<i32, Stream>> {
+    let mut result = HashMap::new();
+    for variant in hls::get_variants(client, url).await? {
+        let itag = match variant.itag {
+            Some(itag) => itag,
+            None => continue,
+        };
+        let format = Format::from_hls_variant(itag, &variant, FORMAT_MAP.get(&itag));
+        result.insert(
+            itag,
+            Stream::with_protocol(variant.url, format, Protocol::Hls),
+        );
+    }
+    Ok(result)
+}
 
-        if !_type.is_null()
-            && _type
-                .as_str()
-                .ok_or(anyhow!("Cannot convert JsonValue to a string"))?
-                .eq("FORMAT_STREAM_TYPE_OTF")
-        {
-            continue;
-        }
+/** Turns the representations of a DASH manifest into streams keyed by itag*/
+async fn get_dash_streams(
+    client: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<HashMap<i32, Stream>> {
+    let mut result = HashMap::new();
+    for representation in dash::get_representations(client, url).await? {
+        let itag = match representation.get_itag() {
+            Some(itag) => itag,
+            None => continue,
+        };
+        let format = Format::from_dash_representation(itag, &representation, FORMAT_MAP.get(&itag));
+        result.insert(
+            itag,
+            Stream::with_protocol(url.to_owned(), format, Protocol::Dash),
+        );
+    }
+    Ok(result)
+}
 
-        let itag = format["itag"]
-            .as_i32()
-            .ok_or(anyhow!("Cannot convert JsonValue to an int"))?;
+/** Requests the player response from each client in turn,
+returning the streams of the first one that has any together with its response.
+The response is the first playable one if no client returned streams, or null*/
+pub async fn get_streams(
+    video_id: &str,
+    clients: &[Client],
+) -> anyhow::Result<(Vec<Stream>, JsonValue)> {
+    let client = reqwest::Client::new();
 
-        if FORMAT_MAP.contains_key(&itag) {
-            if !format["url"].is_null() {
-                let url = format["url"]
-                    .as_str()
-                    .ok_or(anyhow!("Cannot convert JsonValue to a string"))?
-                    .replace("\\u0026", "&");
-                result.insert(itag, (url, FORMAT_MAP.get(&itag).unwrap()));
-            } else if !format["signatureCipher"].is_null() {
-                let cipher = format["signatureCipher"]
-                    .as_str()
-                    .ok_or(anyhow!("Cannot convert JsonValue to a string"))?;
-
-                if sig_enc_url.is_match(cipher) && signature.is_match(cipher) {
-                    let url = sig_enc_url
-                        .captures(&cipher)
-                        .ok_or(anyhow!("No captures were found on cipher"))?
-                        .get(1)
-                        .ok_or(anyhow!("No captures were found on html cipher"))?
-                        .as_str();
-                    let url = urlencoding::decode(url)?.to_string();
-
-                    let signature = signature
-                        .captures(&cipher)
-                        .ok_or(anyhow!("No captures were found on cipher"))?
-                        .get(1)
-                        .ok_or(anyhow!("No captures were found on html cipher"))?
-                        .as_str();
-                    let _signature = urlencoding::decode(signature)?.to_string(); //todo
-
-                    result.insert(itag, (url, FORMAT_MAP.get(&itag).unwrap()));
+    // Without the player only the clients returning plain urls can succeed
+    let mut player = None;
+    if clients.iter().any(Client::requires_player) {
+        player = Player::load(&client).await.ok();
+    }
+    let signature_timestamp = player.as_ref().and_then(Player::get_signature_timestamp);
+
+    let mut last_error = None;
+    let mut responded = None;
+    for yt_client in clients {
+        let result = innertube::player(&client, video_id, *yt_client, signature_timestamp)
+            .await
+            .and_then(|value| {
+                let status = value["playabilityStatus"]["status"].as_str().unwrap_or("");
+                if status != "OK" {
+                    let reason = value["playabilityStatus"]["reason"]
+                        .as_str()
+                        .unwrap_or(status);
+                    return Err(anyhow!("{} client: {}", yt_client, reason));
+                }
+                Ok((parse_streaming_data(&value, player.as_mut())?, value))
+            });
+        // Live broadcasts are only served through the HLS manifest
+        let result = match result {
+            Ok((mut streams, value)) => {
+                if let Some(url) = value["streamingData"]["hlsManifestUrl"].as_str() {
+                    if let Ok(hls) = get_hls_streams(&client, url).await {
+                        streams.extend(hls);
+                    }
                 }
+                // Manifest representations only fill in formats without a direct url
+                if let Some(url) = value["streamingData"]["dashManifestUrl"].as_str() {
+                    if let Ok(dash) = get_dash_streams(&client, url).await {
+                        for (itag, stream) in dash {
+                            streams.entry(itag).or_insert(stream);
+                        }
+                    }
+                }
+                Ok((streams, value))
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((streams, value)) if !streams.is_empty() => {
+                return Ok((streams.into_values().collect(), value))
+            }
+            Ok((_, value)) => {
+                responded.get_or_insert(value);
             }
+            Err(e) => last_error = Some(e),
         }
     }
-    Ok(result)
+    match (responded, last_error) {
+        (Some(value), _) => Ok((Vec::new(), value)),
+        (None, Some(e)) => Err(e),
+        (None, None) => Ok((Vec::new(), JsonValue::Null)),
+    }
 }
@@ -0,0 +1,325 @@
+use crate::download::Progress;
+use crate::video::Stream;
+use anyhow::anyhow;
+use regex::Regex;
+use reqwest::Url;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+lazy_static! {
+    static ref ITAG: Regex = Regex::new("/itag/(\\d+)/").unwrap();
+}
+
+/** A rendition listed by #EXT-X-STREAM-INF in a master playlist*/
+#[derive(Debug, Clone)]
+pub(crate) struct Variant {
+    pub(crate) url: String,
+    pub(crate) itag: Option<i32>,
+    pub(crate) bandwidth: Option<u64>,
+    pub(crate) resolution: Option<(i32, i32)>,
+    pub(crate) frame_rate: Option<f64>,
+    pub(crate) codecs: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    sequence: u64,
+    url: String,
+}
+
+/** A media playlist, which for live streams is a sliding window of the latest segments*/
+#[derive(Debug, Clone)]
+struct MediaPlaylist {
+    target_duration: f64,
+    init: Option<String>,
+    segments: Vec<Segment>,
+    ended: bool,
+}
+
+/** Splits an attribute list like `BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2"`*/
+fn parse_attributes(list: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = list.trim();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_owned();
+        rest = &rest[eq + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let value = &quoted[..end];
+            rest = quoted.get(end + 1..).unwrap_or("");
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        attributes.push((name, value.to_owned()));
+        rest = rest.trim_start_matches(',');
+    }
+    attributes
+}
+
+fn resolve(base: &Url, uri: &str) -> anyhow::Result<String> {
+    Ok(base.join(uri.trim())?.to_string())
+}
+
+fn parse_master(text: &str, base: &Url) -> anyhow::Result<Vec<Variant>> {
+    let mut variants = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let attributes = match line.strip_prefix("#EXT-X-STREAM-INF:") {
+            Some(attributes) => parse_attributes(attributes),
+            None => continue,
+        };
+        let uri = lines
+            .by_ref()
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .ok_or(anyhow!("#EXT-X-STREAM-INF without uri"))?;
+        let url = resolve(base, uri)?;
+        let get = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        variants.push(Variant {
+            itag: ITAG.captures(&url).and_then(|c| c[1].parse().ok()),
+            bandwidth: get("BANDWIDTH").and_then(|b| b.parse().ok()),
+            resolution: get("RESOLUTION").and_then(|r| {
+                let (width, height) = r.split_once('x')?;
+                Some((width.parse().ok()?, height.parse().ok()?))
+            }),
+            frame_rate: get("FRAME-RATE").and_then(|f| f.parse().ok()),
+            codecs: get("CODECS")
+                .map(|c| c.split(',').map(|c| c.trim().to_owned()).collect())
+                .unwrap_or_default(),
+            url,
+        });
+    }
+    if variants.is_empty() {
+        return Err(anyhow!("Master playlist has no variants"));
+    }
+    Ok(variants)
+}
+
+fn parse_media(text: &str, base: &Url) -> anyhow::Result<MediaPlaylist> {
+    if !text.trim_start().starts_with("#EXTM3U") {
+        return Err(anyhow!("Not an m3u8 playlist"));
+    }
+    let mut playlist = MediaPlaylist {
+        target_duration: 5.0,
+        init: None,
+        segments: Vec::new(),
+        ended: false,
+    };
+    let mut sequence = 0;
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(duration) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            playlist.target_duration = duration.parse().unwrap_or(playlist.target_duration);
+        } else if let Some(first) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = first.parse()?;
+        } else if let Some(map) = line.strip_prefix("#EXT-X-MAP:") {
+            if let Some((_, uri)) = parse_attributes(map).into_iter().find(|(n, _)| n == "URI") {
+                playlist.init = Some(resolve(base, &uri)?);
+            }
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if !line.starts_with('#') {
+            playlist.segments.push(Segment {
+                sequence,
+                url: resolve(base, line)?,
+            });
+            sequence += 1;
+        }
+    }
+    Ok(playlist)
+}
+
+/** Requests and parses the master playlist of `hlsManifestUrl`*/
+pub(crate) async fn get_variants(
+    client: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<Vec<Variant>> {
+    let text = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_master(&text, &Url::parse(url)?)
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec())
+}
+
+/** Appends every segment of the stream's media playlist to the file, polling it for new
+segments until #EXT-X-ENDLIST. The total of the reported progress is 0 as it is unknown*/
+pub(crate) async fn download(
+    stream: &Stream,
+    path: impl AsRef<Path>,
+    mut progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let base = Url::parse(stream.get_url())?;
+    let mut file = File::create(path)?;
+    let mut downloaded = 0;
+    let mut next_sequence = 0;
+    let mut init_written = false;
+    progress(Progress::new(0, 0));
+
+    loop {
+        let text = client
+            .get(stream.get_url())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let playlist = parse_media(&text, &base)?;
+
+        if let Some(init) = playlist.init.as_ref().filter(|_| !init_written) {
+            let data = fetch(&client, init).await?;
+            file.write_all(&data)?;
+            downloaded += data.len() as u64;
+            init_written = true;
+        }
+        for segment in &playlist.segments {
+            if segment.sequence < next_sequence {
+                continue;
+            }
+            let data = fetch(&client, &segment.url).await?;
+            // Segments are written whole so a stopped download leaves a playable file
+            file.write_all(&data)?;
+            downloaded += data.len() as u64;
+            next_sequence = segment.sequence + 1;
+            progress(Progress::new(downloaded, 0));
+        }
+
+        if playlist.ended {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs_f64(playlist.target_duration / 2.0)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse(
+            "https://manifest.googlevideo.com/api/manifest/hls_variant/id/abc/file/index.m3u8",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn keeps_commas_inside_quoted_values() {
+        let attributes = parse_attributes(
+            "BANDWIDTH=1280000,CODECS=\"avc1.4d401f,mp4a.40.2\", RESOLUTION=1280x720,NAME=\"\"",
+        );
+        assert_eq!(
+            attributes,
+            [
+                ("BANDWIDTH".to_owned(), "1280000".to_owned()),
+                ("CODECS".to_owned(), "avc1.4d401f,mp4a.40.2".to_owned()),
+                ("RESOLUTION".to_owned(), "1280x720".to_owned()),
+                ("NAME".to_owned(), "".to_owned()),
+            ]
+        );
+        // An unclosed quote runs to the end of the line
+        assert_eq!(
+            parse_attributes("URI=\"init.mp4"),
+            [("URI".to_owned(), "init.mp4".to_owned())]
+        );
+    }
+
+    #[test]
+    fn parses_master_playlists() {
+        let text = "#EXTM3U\n\
+            #EXT-X-INDEPENDENT-SEGMENTS\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=1280000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=1280x720,FRAME-RATE=30\n\
+            https://manifest.googlevideo.com/api/manifest/hls_playlist/itag/95/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=300000,CODECS=\"avc1.42c00d, mp4a.40.5\"\n\
+            \n\
+            ../low/itag/91/playlist.m3u8\n";
+        let variants = parse_master(text, &base()).unwrap();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].itag, Some(95));
+        assert_eq!(variants[0].bandwidth, Some(1280000));
+        assert_eq!(variants[0].resolution, Some((1280, 720)));
+        assert_eq!(variants[0].frame_rate, Some(30.0));
+        assert_eq!(variants[0].codecs, ["avc1.4d401f", "mp4a.40.2"]);
+        // Relative uris are resolved against the master playlist
+        assert_eq!(
+            variants[1].url,
+            "https://manifest.googlevideo.com/api/manifest/hls_variant/id/abc/low/itag/91/playlist.m3u8"
+        );
+        assert_eq!(variants[1].itag, Some(91));
+        assert_eq!(variants[1].resolution, None);
+        assert_eq!(variants[1].codecs, ["avc1.42c00d", "mp4a.40.5"]);
+
+        assert!(parse_master("#EXTM3U\n", &base()).is_err());
+        assert!(parse_master("#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1\n", &base()).is_err());
+    }
+
+    #[test]
+    fn parses_media_playlists() {
+        let text = "#EXTM3U\n\
+            #EXT-X-VERSION:7\n\
+            #EXT-X-TARGETDURATION:2\n\
+            #EXT-X-MEDIA-SEQUENCE:4170\n\
+            #EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"\n\
+            #EXTINF:2.0,\n\
+            sq/4170/seg.ts\n\
+            #EXTINF:2.0,\n\
+            https://other.googlevideo.com/sq/4171/seg.ts\n";
+        let playlist = parse_media(text, &base()).unwrap();
+        assert_eq!(playlist.target_duration, 2.0);
+        assert_eq!(
+            playlist.init.as_deref(),
+            Some("https://manifest.googlevideo.com/api/manifest/hls_variant/id/abc/file/init.mp4")
+        );
+        let segments: Vec<(u64, &str)> = playlist
+            .segments
+            .iter()
+            .map(|s| (s.sequence, s.url.as_str()))
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                (
+                    4170,
+                    "https://manifest.googlevideo.com/api/manifest/hls_variant/id/abc/file/sq/4170/seg.ts"
+                ),
+                (4171, "https://other.googlevideo.com/sq/4171/seg.ts"),
+            ]
+        );
+        // Live playlists keep growing until they end
+        assert!(!playlist.ended);
+        let ended = parse_media(&format!("{}#EXT-X-ENDLIST\n", text), &base()).unwrap();
+        assert!(ended.ended);
+        assert_eq!(ended.segments.len(), 2);
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(parse_media("<html></html>", &base()).is_err());
+        let playlist = parse_media("#EXTM3U\nseg.ts\n", &base()).unwrap();
+        assert_eq!(playlist.target_duration, 5.0);
+        assert_eq!(playlist.init, None);
+        assert_eq!(playlist.segments[0].sequence, 0);
+    }
+}
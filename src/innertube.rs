@@ -0,0 +1,252 @@
+use anyhow::anyhow;
+use json::{object, JsonValue};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+const PLAYER_ENDPOINT: &str = "https://www.youtube.com/youtubei/v1/player?prettyPrint=false";
+const BROWSE_ENDPOINT: &str = "https://www.youtube.com/youtubei/v1/browse?prettyPrint=false";
+const RESOLVE_URL_ENDPOINT: &str =
+    "https://www.youtube.com/youtubei/v1/navigation/resolve_url?prettyPrint=false";
+
+/** A client whose context is sent to the InnerTube API.
+Different clients receive different sets of formats and restrictions*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Client {
+    Web,
+    Android,
+    Ios,
+    TvEmbedded,
+}
+
+impl Client {
+    /** The order in which clients are tried when none was chosen*/
+    pub const DEFAULT_ORDER: [Client; 4] = [
+        Client::Ios,
+        Client::Android,
+        Client::Web,
+        Client::TvEmbedded,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Client::Web => "WEB",
+            Client::Android => "ANDROID",
+            Client::Ios => "IOS",
+            Client::TvEmbedded => "TVHTML5_SIMPLY_EMBEDDED_PLAYER",
+        }
+    }
+
+    fn id(&self) -> u32 {
+        match self {
+            Client::Web => 1,
+            Client::Android => 3,
+            Client::Ios => 5,
+            Client::TvEmbedded => 85,
+        }
+    }
+
+    fn version(&self) -> &'static str {
+        match self {
+            Client::Web => "2.20240726.00.00",
+            Client::Android => "19.29.37",
+            Client::Ios => "19.29.1",
+            Client::TvEmbedded => "2.0",
+        }
+    }
+
+    fn user_agent(&self) -> &'static str {
+        match self {
+            Client::Web | Client::TvEmbedded => {
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36"
+            }
+            Client::Android => {
+                "com.google.android.youtube/19.29.37 (Linux; U; Android 11) gzip"
+            }
+            Client::Ios => {
+                "com.google.ios.youtube/19.29.1 (iPhone16,2; U; CPU iOS 17_5_1 like Mac OS X;)"
+            }
+        }
+    }
+
+    /** Whether urls of this client may be ciphered and need the player script*/
+    pub fn requires_player(&self) -> bool {
+        matches!(self, Client::Web | Client::TvEmbedded)
+    }
+
+    fn context(&self) -> JsonValue {
+        let mut client = object! {
+            clientName: self.name(),
+            clientVersion: self.version(),
+            hl: "en",
+            gl: "US",
+        };
+        let mut context = JsonValue::new_object();
+        match self {
+            Client::Android => {
+                client["androidSdkVersion"] = 30.into();
+                client["osName"] = "Android".into();
+                client["osVersion"] = "11".into();
+            }
+            Client::Ios => {
+                client["deviceMake"] = "Apple".into();
+                client["deviceModel"] = "iPhone16,2".into();
+                client["osName"] = "iPhone".into();
+                client["osVersion"] = "17.5.1.21F90".into();
+            }
+            Client::TvEmbedded => {
+                context["thirdParty"] = object! { embedUrl: "https://www.youtube.com/" };
+            }
+            Client::Web => {}
+        }
+        context["client"] = client;
+        context
+    }
+}
+
+impl Display for Client {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Client::Web => "web",
+            Client::Android => "android",
+            Client::Ios => "ios",
+            Client::TvEmbedded => "tv-embedded",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Client {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "web" => Ok(Client::Web),
+            "android" => Ok(Client::Android),
+            "ios" => Ok(Client::Ios),
+            "tv-embedded" | "tv_embedded" => Ok(Client::TvEmbedded),
+            _ => Err(anyhow!("Unknown InnerTube client \"{}\"", s)),
+        }
+    }
+}
+
+/** Requests the player response of a video, which has the same layout as ytInitialPlayerResponse*/
+pub async fn player(
+    http: &reqwest::Client,
+    video_id: &str,
+    client: Client,
+    signature_timestamp: Option<u32>,
+) -> anyhow::Result<JsonValue> {
+    let mut body = object! {
+        context: client.context(),
+        videoId: video_id,
+        playbackContext: {
+            contentPlaybackContext: {
+                html5Preference: "HTML5_PREF_WANTS",
+            },
+        },
+        contentCheckOk: true,
+        racyCheckOk: true,
+    };
+    if let Some(sts) = signature_timestamp {
+        body["playbackContext"]["contentPlaybackContext"]["signatureTimestamp"] = sts.into();
+    }
+    post(http, PLAYER_ENDPOINT, client, body).await
+}
+
+/** Requests a page of a playlist or channel, or its next items when given a continuation token.
+Browsing is done with the web client, whose responses are the ytInitialData of the page*/
+pub async fn browse(
+    http: &reqwest::Client,
+    browse_id: &str,
+    params: Option<&str>,
+    continuation: Option<&str>,
+) -> anyhow::Result<JsonValue> {
+    let client = Client::Web;
+    let mut body = object! { context: client.context() };
+    match continuation {
+        Some(token) => body["continuation"] = token.into(),
+        None => body["browseId"] = browse_id.into(),
+    }
+    if let Some(params) = params.filter(|_| continuation.is_none()) {
+        body["params"] = params.into();
+    }
+    post(http, BROWSE_ENDPOINT, client, body).await
+}
+
+/** Resolves a YouTube url like a channel's "https://www.youtube.com/@handle" into the endpoint
+it navigates to, whose browseEndpoint has the id to browse*/
+pub async fn resolve_url(http: &reqwest::Client, url: &str) -> anyhow::Result<JsonValue> {
+    let client = Client::Web;
+    let body = object! {
+        context: client.context(),
+        url: url,
+    };
+    post(http, RESOLVE_URL_ENDPOINT, client, body).await
+}
+
+async fn post(
+    http: &reqwest::Client,
+    endpoint: &str,
+    client: Client,
+    body: JsonValue,
+) -> anyhow::Result<JsonValue> {
+    let response = http
+        .post(endpoint)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", client.user_agent())
+        .header("Origin", "https://www.youtube.com")
+        .header("X-YouTube-Client-Name", client.id())
+        .header("X-YouTube-Client-Version", client.version())
+        .body(body.dump())
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "InnerTube {} client responded with {}",
+            client,
+            response.status()
+        ));
+    }
+    Ok(json::parse(&response.text().await?)?)
+}
+
+/** Collects every value of the given renderer key in a response, in document order.
+Renderers are searched for instead of followed by path, as the layouts change often*/
+pub(crate) fn find_renderers<'a>(value: &'a JsonValue, name: &str, found: &mut Vec<&'a JsonValue>) {
+    if value.is_object() {
+        for (key, child) in value.entries() {
+            if key == name {
+                found.push(child);
+            } else {
+                find_renderers(child, name, found);
+            }
+        }
+    } else if value.is_array() {
+        for child in value.members() {
+            find_renderers(child, name, found);
+        }
+    }
+}
+
+/** The token of the next page, from the continuationItemRenderer ending a list*/
+pub(crate) fn find_continuation(value: &JsonValue) -> Option<String> {
+    let mut found = Vec::new();
+    find_renderers(value, "continuationItemRenderer", &mut found);
+    found.iter().rev().find_map(|renderer| {
+        renderer["continuationEndpoint"]["continuationCommand"]["token"]
+            .as_str()
+            .map(str::to_owned)
+    })
+}
+
+/** The text of a runs or simpleText object*/
+pub(crate) fn text(value: &JsonValue) -> Option<String> {
+    if let Some(text) = value["simpleText"].as_str() {
+        return Some(text.to_owned());
+    }
+    let runs: String = value["runs"]
+        .members()
+        .filter_map(|run| run["text"].as_str())
+        .collect();
+    (!runs.is_empty()).then_some(runs)
+}
@@ -0,0 +1,41 @@
+//! Extraction and download of YouTube media.
+//!
+//! Urls and ids are classified by [`link::Link`], [`get_video_info`] resolves a video id into
+//! its [`Stream`]s, the [`select`] module picks among them and [`download`] saves one to disk
+//! while reporting [`Progress`].
+//! Separate video and audio tracks are combined with [`download_merged`], which also embeds
+//! subtitles and [`thumbnail`]s given as [`mux::Embeds`], and [`template`] names the downloaded
+//! files after the video. Videos of a playlist are listed with [`playlist::get_playlist`] and
+//! those of a channel with [`channel::get_channel`].
+
+#[macro_use]
+extern crate lazy_static;
+
+mod cache;
+pub mod channel;
+mod cipher;
+pub mod codec;
+mod dash;
+pub mod download;
+pub mod format;
+mod help;
+mod hls;
+pub mod innertube;
+pub mod link;
+pub mod mux;
+mod nsig;
+mod otf;
+mod player;
+pub mod playlist;
+pub mod select;
+pub mod subtitles;
+pub mod template;
+pub mod thumbnail;
+mod video;
+
+pub use download::{
+    download, download_embedded, download_merged, download_parallel, Probe, Progress,
+};
+pub use format::Format;
+pub use innertube::Client;
+pub use video::{get_video_info, Protocol, Stream, VideoInfo};
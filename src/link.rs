@@ -0,0 +1,517 @@
+//! Classification of the urls and ids users paste.
+//!
+//! Every YouTube host is accepted (`www.`, `m.`, `music.`, `youtube-nocookie.com`, `youtu.be`),
+//! with or without a scheme, as well as bare video ids, playlist ids, channel ids and handles.
+
+use crate::channel::Tab;
+use crate::innertube;
+use anyhow::anyhow;
+use regex::Regex;
+use reqwest::Url;
+use std::str::FromStr;
+
+lazy_static! {
+    static ref VIDEO_ID: Regex = Regex::new("^[\\w-]{11}$").unwrap();
+    static ref CHANNEL_ID: Regex = Regex::new("^UC[\\w-]{22}$").unwrap();
+    static ref PLAYLIST_ID: Regex =
+        Regex::new("^(PL|UU|OL|RD|LL|FL|UL|PU|EL)[\\w-]{10,}$").unwrap();
+    static ref LIST: Regex = Regex::new("^[\\w-]+$").unwrap();
+    static ref HANDLE: Regex = Regex::new("^@[\\w.-]+$").unwrap();
+    static ref TIME: Regex = Regex::new("^(?:(\\d+)h)?(?:(\\d+)m)?(?:(\\d+)s?)?$").unwrap();
+}
+
+/** How a channel is referred to in its url*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ChannelRef {
+    /** The "UC..." id of `/channel/`*/
+    Id(String),
+    /** A handle like "@name", including the `@`*/
+    Handle(String),
+    /** The legacy custom name of `/c/`*/
+    Custom(String),
+    /** The legacy user name of `/user/`*/
+    User(String),
+}
+
+impl ChannelRef {
+    /** The url of the channel page*/
+    pub fn get_url(&self) -> String {
+        match self {
+            ChannelRef::Id(id) => format!("https://www.youtube.com/channel/{}", id),
+            ChannelRef::Handle(handle) => format!("https://www.youtube.com/{}", handle),
+            ChannelRef::Custom(name) => format!("https://www.youtube.com/c/{}", name),
+            ChannelRef::User(name) => format!("https://www.youtube.com/user/{}", name),
+        }
+    }
+}
+
+/** What a url points to*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Link {
+    /** A video, with the start time in seconds and the playlist it was opened from*/
+    Video {
+        id: String,
+        start: Option<u64>,
+        list: Option<String>,
+        index: Option<usize>,
+    },
+    Playlist {
+        id: String,
+    },
+    /** A channel, with the tab the url was opened on*/
+    Channel {
+        channel: ChannelRef,
+        tab: Option<Tab>,
+    },
+    /** A clip, whose id is resolved into its video with [`resolve_clip`]*/
+    Clip {
+        id: String,
+    },
+    Search {
+        query: String,
+    },
+}
+
+/** Seconds of a start time like "90", "90s" or "1h2m3s"*/
+fn parse_time(time: &str) -> Option<u64> {
+    let captures = TIME.captures(time).filter(|_| !time.is_empty())?;
+    let part = |i: usize| {
+        captures
+            .get(i)
+            .map_or(0, |m| m.as_str().parse::<u64>().unwrap_or(0))
+    };
+    Some(part(1) * 3600 + part(2) * 60 + part(3))
+}
+
+fn is_youtube_host(host: &str) -> bool {
+    let host = host.trim_start_matches("www.");
+    host == "youtu.be"
+        || ["youtube.com", "youtube-nocookie.com"]
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+fn video_id(id: &str) -> anyhow::Result<String> {
+    if VIDEO_ID.is_match(id) {
+        Ok(id.to_owned())
+    } else {
+        Err(anyhow!("\"{}\" is not a video id", id))
+    }
+}
+
+/** Classifies ids given without a url*/
+fn parse_bare(input: &str) -> Option<Link> {
+    if VIDEO_ID.is_match(input) {
+        return Some(Link::Video {
+            id: input.to_owned(),
+            start: None,
+            list: None,
+            index: None,
+        });
+    }
+    if CHANNEL_ID.is_match(input) {
+        return Some(Link::Channel {
+            channel: ChannelRef::Id(input.to_owned()),
+            tab: None,
+        });
+    }
+    if HANDLE.is_match(input) {
+        return Some(Link::Channel {
+            channel: ChannelRef::Handle(input.to_owned()),
+            tab: None,
+        });
+    }
+    if PLAYLIST_ID.is_match(input) {
+        return Some(Link::Playlist {
+            id: input.to_owned(),
+        });
+    }
+    None
+}
+
+fn parse_url(url: &Url) -> anyhow::Result<Link> {
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    // Start times are also given in the fragment, like "#t=1m30s"
+    let fragment_time = url
+        .fragment()
+        .and_then(|f| f.strip_prefix("t="))
+        .map(str::to_owned);
+    let start = query("t")
+        .or_else(|| query("start"))
+        .or(fragment_time)
+        .and_then(|t| parse_time(&t));
+    let list = query("list").filter(|l| LIST.is_match(l));
+    let index = query("index").and_then(|i| i.parse().ok());
+    let video = |id: &str| -> anyhow::Result<Link> {
+        Ok(Link::Video {
+            id: video_id(id)?,
+            start,
+            list: list.clone(),
+            index,
+        })
+    };
+
+    // Handles and custom names may be percent encoded unicode
+    let decoded: Vec<String> = url
+        .path_segments()
+        .map(|s| {
+            s.filter(|s| !s.is_empty())
+                .map(|s| urlencoding::decode(s).map_or(s.to_owned(), |s| s.into_owned()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let segments: Vec<&str> = decoded.iter().map(String::as_str).collect();
+    if url.host_str() == Some("youtu.be") {
+        return match segments.first() {
+            Some(id) => video(id),
+            None => Err(anyhow!("{} has no video id", url)),
+        };
+    }
+
+    match segments.as_slice() {
+        ["watch"] => match query("v") {
+            Some(id) => video(&id),
+            None => match list.clone() {
+                Some(id) => Ok(Link::Playlist { id }),
+                None => Err(anyhow!("{} has no video id", url)),
+            },
+        },
+        ["playlist"] | ["embed", "videoseries"] => match list.clone() {
+            Some(id) => Ok(Link::Playlist { id }),
+            None => Err(anyhow!("{} has no playlist id", url)),
+        },
+        ["shorts" | "embed" | "live" | "v" | "e", id, ..] => video(id),
+        ["clip", id] => Ok(Link::Clip {
+            id: (*id).to_owned(),
+        }),
+        ["results"] => match query("search_query").or_else(|| query("q")) {
+            Some(query) => Ok(Link::Search { query }),
+            None => Err(anyhow!("{} has no search query", url)),
+        },
+        [first, rest @ ..] => {
+            let (channel, rest) = match (*first, rest) {
+                ("channel", [id, rest @ ..]) if CHANNEL_ID.is_match(id) => {
+                    (ChannelRef::Id((*id).to_owned()), rest)
+                }
+                ("c", [name, rest @ ..]) => (ChannelRef::Custom((*name).to_owned()), rest),
+                ("user", [name, rest @ ..]) => (ChannelRef::User((*name).to_owned()), rest),
+                (handle, rest) if HANDLE.is_match(handle) => {
+                    (ChannelRef::Handle(handle.to_owned()), rest)
+                }
+                _ => return Err(anyhow!("{} is not a supported YouTube link", url)),
+            };
+            // Other tabs like "about" or "playlists" do not list videos
+            let tab = rest.first().and_then(|tab| tab.parse().ok());
+            Ok(Link::Channel { channel, tab })
+        }
+        [] => Err(anyhow!("{} is not a supported YouTube link", url)),
+    }
+}
+
+impl FromStr for Link {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        if let Some(link) = parse_bare(input) {
+            return Ok(link);
+        }
+        let url = match Url::parse(input) {
+            Ok(url) => url,
+            // Links are often pasted without a scheme
+            Err(_) => Url::parse(&format!("https://{}", input))?,
+        };
+        if !matches!(url.scheme(), "http" | "https") || !url.host_str().is_some_and(is_youtube_host)
+        {
+            return Err(anyhow!("\"{}\" is not a YouTube link", input));
+        }
+        parse_url(&url)
+    }
+}
+
+/** The id of the video a clip is cut from*/
+pub async fn resolve_clip(clip_id: &str) -> anyhow::Result<String> {
+    let client = reqwest::Client::new();
+    let url = format!("https://www.youtube.com/clip/{}", clip_id);
+    let value = innertube::resolve_url(&client, &url).await?;
+    value["endpoint"]["watchEndpoint"]["videoId"]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or(anyhow!("Clip {} cannot be resolved", clip_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(id: &str) -> Link {
+        Link::Video {
+            id: id.to_owned(),
+            start: None,
+            list: None,
+            index: None,
+        }
+    }
+
+    fn video_at(id: &str, start: u64) -> Link {
+        Link::Video {
+            id: id.to_owned(),
+            start: Some(start),
+            list: None,
+            index: None,
+        }
+    }
+
+    fn video_in(id: &str, list: &str, index: Option<usize>) -> Link {
+        Link::Video {
+            id: id.to_owned(),
+            start: None,
+            list: Some(list.to_owned()),
+            index,
+        }
+    }
+
+    fn playlist(id: &str) -> Link {
+        Link::Playlist { id: id.to_owned() }
+    }
+
+    fn channel(channel: ChannelRef, tab: Option<Tab>) -> Link {
+        Link::Channel { channel, tab }
+    }
+
+    const ID: &str = "dQw4w9WgXcQ";
+    const LIST: &str = "PLBCF2DAC6FFB574DE";
+    const CHANNEL: &str = "UCuAXFkgsw1L7xaCfnd5JJOw";
+
+    #[test]
+    fn classifies_links() {
+        let cases = vec![
+            // Watch pages
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ", video(ID)),
+            ("http://youtube.com/watch?v=dQw4w9WgXcQ", video(ID)),
+            ("https://m.youtube.com/watch?v=dQw4w9WgXcQ", video(ID)),
+            ("https://music.youtube.com/watch?v=dQw4w9WgXcQ", video(ID)),
+            ("www.youtube.com/watch?v=dQw4w9WgXcQ", video(ID)),
+            ("youtube.com/watch?v=dQw4w9WgXcQ", video(ID)),
+            (
+                "https://www.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+                video(ID),
+            ),
+            (
+                "https://www.youtube.com/watch?app=desktop&v=dQw4w9WgXcQ&ab_channel=x",
+                video(ID),
+            ),
+            ("  https://www.youtube.com/watch?v=dQw4w9WgXcQ  ", video(ID)),
+            // Other video paths
+            ("https://youtu.be/dQw4w9WgXcQ", video(ID)),
+            ("https://youtu.be/dQw4w9WgXcQ?si=abcdef", video(ID)),
+            ("youtu.be/dQw4w9WgXcQ", video(ID)),
+            ("https://www.youtube.com/shorts/dQw4w9WgXcQ", video(ID)),
+            (
+                "https://youtube.com/shorts/dQw4w9WgXcQ?feature=share",
+                video(ID),
+            ),
+            ("https://www.youtube.com/embed/dQw4w9WgXcQ", video(ID)),
+            (
+                "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+                video(ID),
+            ),
+            ("https://www.youtube.com/live/dQw4w9WgXcQ", video(ID)),
+            (
+                "https://www.youtube.com/live/dQw4w9WgXcQ?feature=shared",
+                video(ID),
+            ),
+            ("https://www.youtube.com/v/dQw4w9WgXcQ", video(ID)),
+            ("https://www.youtube.com/e/dQw4w9WgXcQ", video(ID)),
+            // Bare ids
+            ("dQw4w9WgXcQ", video(ID)),
+            ("-_-_-_-_-_-", video("-_-_-_-_-_-")),
+            // Start times
+            ("https://youtu.be/dQw4w9WgXcQ?t=42", video_at(ID, 42)),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42s",
+                video_at(ID, 42),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1m30s",
+                video_at(ID, 90),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1h2m3s",
+                video_at(ID, 3723),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=2h",
+                video_at(ID, 7200),
+            ),
+            (
+                "https://www.youtube.com/embed/dQw4w9WgXcQ?start=15",
+                video_at(ID, 15),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=1m",
+                video_at(ID, 60),
+            ),
+            // Videos opened from a playlist
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLBCF2DAC6FFB574DE",
+                video_in(ID, LIST, None),
+            ),
+            (
+                "https://www.youtube.com/watch?list=PLBCF2DAC6FFB574DE&v=dQw4w9WgXcQ&index=3",
+                video_in(ID, LIST, Some(3)),
+            ),
+            (
+                "https://youtu.be/dQw4w9WgXcQ?list=PLBCF2DAC6FFB574DE",
+                video_in(ID, LIST, None),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=RDdQw4w9WgXcQ&start_radio=1",
+                video_in(ID, "RDdQw4w9WgXcQ", None),
+            ),
+            // Playlists
+            (
+                "https://www.youtube.com/playlist?list=PLBCF2DAC6FFB574DE",
+                playlist(LIST),
+            ),
+            (
+                "https://music.youtube.com/playlist?list=PLBCF2DAC6FFB574DE",
+                playlist(LIST),
+            ),
+            (
+                "https://www.youtube.com/watch?list=PLBCF2DAC6FFB574DE",
+                playlist(LIST),
+            ),
+            (
+                "https://www.youtube.com/embed/videoseries?list=PLBCF2DAC6FFB574DE",
+                playlist(LIST),
+            ),
+            ("PLBCF2DAC6FFB574DE", playlist(LIST)),
+            (
+                "UUuAXFkgsw1L7xaCfnd5JJOw",
+                playlist("UUuAXFkgsw1L7xaCfnd5JJOw"),
+            ),
+            // Channels
+            (
+                "https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw",
+                channel(ChannelRef::Id(CHANNEL.to_owned()), None),
+            ),
+            (
+                "https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw/videos",
+                channel(ChannelRef::Id(CHANNEL.to_owned()), Some(Tab::Videos)),
+            ),
+            (
+                "UCuAXFkgsw1L7xaCfnd5JJOw",
+                channel(ChannelRef::Id(CHANNEL.to_owned()), None),
+            ),
+            (
+                "https://www.youtube.com/@RickAstleyYT",
+                channel(ChannelRef::Handle("@RickAstleyYT".to_owned()), None),
+            ),
+            (
+                "https://m.youtube.com/@RickAstleyYT/shorts",
+                channel(
+                    ChannelRef::Handle("@RickAstleyYT".to_owned()),
+                    Some(Tab::Shorts),
+                ),
+            ),
+            (
+                "https://www.youtube.com/@RickAstleyYT/streams",
+                channel(
+                    ChannelRef::Handle("@RickAstleyYT".to_owned()),
+                    Some(Tab::Streams),
+                ),
+            ),
+            (
+                "https://www.youtube.com/@RickAstleyYT/about",
+                channel(ChannelRef::Handle("@RickAstleyYT".to_owned()), None),
+            ),
+            (
+                "https://www.youtube.com/@r.a-yt_1",
+                channel(ChannelRef::Handle("@r.a-yt_1".to_owned()), None),
+            ),
+            (
+                "https://www.youtube.com/@%E6%97%A5%E6%9C%AC",
+                channel(ChannelRef::Handle("@日本".to_owned()), None),
+            ),
+            (
+                "@RickAstleyYT",
+                channel(ChannelRef::Handle("@RickAstleyYT".to_owned()), None),
+            ),
+            (
+                "https://www.youtube.com/c/RickAstleyVEVO",
+                channel(ChannelRef::Custom("RickAstleyVEVO".to_owned()), None),
+            ),
+            (
+                "https://www.youtube.com/c/RickAstleyVEVO/videos",
+                channel(
+                    ChannelRef::Custom("RickAstleyVEVO".to_owned()),
+                    Some(Tab::Videos),
+                ),
+            ),
+            (
+                "https://www.youtube.com/user/RickAstleyVEVO",
+                channel(ChannelRef::User("RickAstleyVEVO".to_owned()), None),
+            ),
+            // Clips
+            (
+                "https://www.youtube.com/clip/UgkxU2HSeGL_NvmDJ-nQJrlLwllwMDBdGZFs",
+                Link::Clip {
+                    id: "UgkxU2HSeGL_NvmDJ-nQJrlLwllwMDBdGZFs".to_owned(),
+                },
+            ),
+            // Searches
+            (
+                "https://www.youtube.com/results?search_query=never+gonna+give",
+                Link::Search {
+                    query: "never gonna give".to_owned(),
+                },
+            ),
+            (
+                "https://www.youtube.com/results?search_query=rick%20%26%20roll",
+                Link::Search {
+                    query: "rick & roll".to_owned(),
+                },
+            ),
+        ];
+
+        for (input, expected) in cases {
+            match input.parse::<Link>() {
+                Ok(link) => assert_eq!(link, expected, "{}", input),
+                Err(e) => panic!("{} was rejected: {}", input, e),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_links() {
+        let cases = [
+            "",
+            "dQw4w9WgXc",
+            "dQw4w9WgXcQQ",
+            "not a link",
+            "https://vimeo.com/123456",
+            "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com.evil.com/watch?v=dQw4w9WgXcQ",
+            "ftp://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/",
+            "https://www.youtube.com/watch",
+            "https://www.youtube.com/watch?v=short",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ!!",
+            "https://youtu.be/",
+            "https://www.youtube.com/shorts/",
+            "https://www.youtube.com/playlist",
+            "https://www.youtube.com/results",
+            "https://www.youtube.com/feed/subscriptions",
+            "https://www.youtube.com/channel/notachannel",
+        ];
+
+        for input in cases {
+            assert!(input.parse::<Link>().is_err(), "{} was accepted", input);
+        }
+    }
+}
@@ -1,18 +1,25 @@
-#[macro_use]
-extern crate lazy_static;
-
-mod format;
-mod help;
-
-use crate::format::Format;
 use anyhow::anyhow;
 use clap::{Parser, Subcommand};
-use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
-use regex::Regex;
-use std::fs::File;
-use std::io::{stdin, stdout, Write};
+use std::collections::HashMap;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::Path;
 use std::process::exit;
+use yt_download::channel::{self, Tab};
+use yt_download::link::{self, ChannelRef, Link};
+use yt_download::mux::{Container, Embeds, Subtitle};
+use yt_download::playlist::{get_playlist, Entry, Items};
+use yt_download::select::{Quality, Selection, Selector, SortOrder};
+use yt_download::subtitles::{self, Cue, SubtitleFormat};
+use yt_download::template::{self, DEFAULT_TEMPLATE};
+use yt_download::thumbnail;
+use yt_download::{select, Client, Protocol, Stream, VideoInfo};
+
+/** How many formats are probed at the same time*/
+const PROBES: usize = 8;
+
+/** How often an interrupted download is resumed before giving up*/
+const RETRIES: u32 = 3;
 
 /// YouTube video downloader, written in Rust
 #[derive(Parser, Debug)]
@@ -27,38 +34,102 @@ struct Args {
 enum Commands {
     ///Downloads a media from YouTube with given link
     Download {
-        ///Link to YouTube video
+        ///Link to a YouTube video, playlist or channel, or a video id
         #[clap(required = true)]
         url: String,
+        ///InnerTube clients to request formats with, tried in order (web, android, ios, tv-embedded)
+        #[clap(long, value_delimiter = ',')]
+        client: Vec<Client>,
+        ///Itag of the format to download
+        #[clap(long, conflicts_with_all = &["quality", "audio-only"])]
+        itag: Option<i32>,
+        ///Video quality to download: best, worst or a maximal height like 720p
+        #[clap(long)]
+        quality: Option<Quality>,
+        ///Download an audio only format
+        #[clap(long)]
+        audio_only: bool,
+        ///Download a video only format together with the best matching audio and mux them
+        #[clap(long, conflicts_with_all = &["itag", "audio-only"])]
+        merge: bool,
+        ///Format selector like "bestvideo[height<=1080][vcodec^=avc1]+bestaudio[ext=m4a]/best"
+        #[clap(short, long, conflicts_with_all = &["itag", "quality", "audio-only", "merge"])]
+        format: Option<Selector>,
+        ///Fields to rank formats by, higher first unless prefixed with "+", like "height,fps,+filesize"
+        #[clap(short = 'S', long, default_value_t = SortOrder::default())]
+        format_sort: SortOrder,
+        ///Template of the file to save the media to, like "%(uploader)s/%(title)s [%(id)s].%(ext)s".
+        ///The format's extension is appended if it has none
+        #[clap(short, long)]
+        output: Option<String>,
+        ///Number of concurrent connections a file is downloaded with
+        #[clap(short = 'N', long, default_value_t = 4)]
+        connections: usize,
+        ///Indices of the playlist or channel videos to download, like "1-10,15" or "20-"
+        #[clap(long)]
+        playlist_items: Option<Items>,
+        ///Channel tabs to download the videos of: videos, shorts or streams.
+        ///Defaults to the tab of the url, or videos
+        #[clap(long, value_delimiter = ',')]
+        tab: Vec<Tab>,
+        ///Write the subtitles of the video next to the media
+        #[clap(long)]
+        write_subs: bool,
+        ///Write automatic captions when there are no subtitles, translating them if needed
+        #[clap(long)]
+        write_auto_subs: bool,
+        ///Languages of the subtitles to write or embed, like "en,de", or "all"
+        #[clap(long, value_delimiter = ',', default_value = "en")]
+        sub_langs: Vec<String>,
+        ///Format of the written or embedded subtitles: srt or vtt
+        #[clap(long, default_value_t = SubtitleFormat::Srt)]
+        sub_format: SubtitleFormat,
+        ///Embed the subtitles into MP4, WebM and Matroska files, as tx3g or S_TEXT/UTF8 tracks
+        ///for srt and as wvtt or S_TEXT/WEBVTT tracks for vtt
+        #[clap(long)]
+        embed_subs: bool,
+        ///Save the largest thumbnail of the video as JPEG next to the media
+        #[clap(long)]
+        write_thumbnail: bool,
+        ///Embed the largest thumbnail as cover art into MP4, M4A, Matroska and MP3 files
+        #[clap(long)]
+        embed_thumbnail: bool,
+        ///Never prompt, choose the best format and name the file with the default template
+        #[clap(short, long)]
+        yes: bool,
     },
 }
 
-async fn download_file(url: &str, name: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
-    let response = client.get(url).send().await?;
-
-    let total_size = response
-        .content_length()
-        .ok_or(anyhow!("No content length"))?;
-    let pb = ProgressBar::new(total_size);
+async fn download_file(
+    stream: &Stream,
+    audio: Option<&Stream>,
+    name: &Path,
+    connections: usize,
+    embeds: &Embeds,
+) -> anyhow::Result<()> {
+    let pb = ProgressBar::new(0);
     pb.set_style(ProgressStyle::default_bar()
         .template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
         .progress_chars("█ "));
 
-    let mut stream = response.bytes_stream();
-
-    let mut file = File::create(name)?;
-    let mut downloaded: u64 = 0;
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk)?;
-        let new = std::cmp::min(downloaded + (chunk.len() as u64), total_size);
-        downloaded = new;
-        pb.set_position(new);
+    if stream.get_protocol() == Protocol::Hls {
+        println!("Downloading until the stream ends, press Ctrl+C to stop earlier");
+    }
+    let update = |progress: yt_download::Progress| {
+        pb.set_length(progress.get_total().max(progress.get_downloaded()));
+        pb.set_position(progress.get_downloaded());
+    };
+    match audio {
+        Some(audio) => {
+            yt_download::download_merged(stream, audio, name, connections, embeds, update).await?
+        }
+        None if !embeds.is_empty() => {
+            yt_download::download_embedded(stream, name, connections, embeds, update).await?
+        }
+        None => yt_download::download_parallel(stream, name, connections, update).await?,
     }
-    std::io::stdout().flush()?;
-    pb.finish_with_message(format!("Downloaded to \"{}\"", name));
+    stdout().flush()?;
+    pb.finish_with_message(format!("Downloaded to \"{}\"", name.display()));
 
     Ok(())
 }
@@ -71,158 +142,570 @@ fn read_number() -> i32 {
         println!("Not a number!");
         exit(0);
     }
-    return res.unwrap();
+    res.unwrap()
+}
+
+fn describe_size(size: Option<u64>) -> String {
+    match size {
+        Some(size) => format!(", Size = {:.1} MiB", size as f64 / (1024.0 * 1024.0)),
+        None => String::new(),
+    }
+}
+
+fn describe_video(s: &Stream, size: Option<u64>) -> String {
+    let f = s.get_format();
+    format!(
+        "Format = {}, Resolution = {}p, Fps = {}, Codec = {}{}",
+        f.get_extension(),
+        f.get_height(),
+        f.get_fps(),
+        f.get_video_codec(),
+        describe_size(size)
+    )
+}
+
+fn describe_audio(s: &Stream, size: Option<u64>) -> String {
+    let f = s.get_format();
+    format!(
+        "Format = {}, AudioBitrate = {}, Codec = {}{}",
+        f.get_extension(),
+        f.get_audio_bitrate(),
+        f.get_audio_codec(),
+        describe_size(size)
+    )
+}
+
+/** Formats seconds like 1:02:03 or 2:03*/
+fn describe_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+fn print_info(info: &VideoInfo) {
+    println!("Title: {}", info.get_title());
+    println!("Channel: {} ({})", info.get_author(), info.get_channel_id());
+    if info.is_live() {
+        println!("Live now");
+    } else {
+        println!("Duration: {}", describe_duration(info.get_length_seconds()));
+    }
+    println!("Views: {}", info.get_view_count());
+    if let Some(date) = info.get_upload_date() {
+        println!("Uploaded: {}", date);
+    }
+    if let Some(category) = info.get_category() {
+        println!("Category: {}", category);
+    }
+    if !info.get_keywords().is_empty() {
+        println!("Keywords: {}", info.get_keywords().join(", "));
+    }
+    if let Some(line) = info
+        .get_description()
+        .lines()
+        .find(|l| !l.trim().is_empty())
+    {
+        println!("Description: {}", line);
+    }
+    println!();
+}
+
+/** Asks for one of the listed streams, exits if the answer is out of range*/
+fn choose_stream<'a>(streams: &[&'a Stream], describe: impl Fn(&Stream) -> String) -> &'a Stream {
+    if streams.is_empty() {
+        exit(0);
+    }
+    println!(
+        "Choose which file do you want to download (1-{}):",
+        streams.len()
+    );
+    for (i, s) in streams.iter().enumerate() {
+        println!("{}) {}", i + 1, describe(s));
+    }
+    let number = read_number();
+    if !(1..=(streams.len() as i32)).contains(&number) {
+        println!("Not again!");
+        exit(0);
+    }
+    streams[number as usize - 1]
 }
 
-#[tokio::main(flavor = "current_thread")]
+/** Probes all streams concurrently, reporting unavailable ones.
+Returns the available itags with the size the server told, if any*/
+async fn available(client: &reqwest::Client, streams: &[Stream]) -> HashMap<i32, Option<u64>> {
+    let streams: Vec<&Stream> = streams.iter().collect();
+    let mut result = HashMap::new();
+    for (stream, probe) in yt_download::download::probe_all(client, &streams, PROBES).await {
+        let format = stream.get_format();
+        if probe.is_available() {
+            let size = probe
+                .get_content_length()
+                .or_else(|| format.get_content_length());
+            result.insert(format.get_itag(), size);
+        } else {
+            println!(
+                "Format {} is unavailable: {}",
+                format.get_itag(),
+                probe.get_reason().unwrap_or("unknown reason")
+            );
+        }
+    }
+    result
+}
+
+/** What to download of each video, shared by all entries of a playlist*/
+struct Options {
+    clients: Vec<Client>,
+    itag: Option<i32>,
+    quality: Option<Quality>,
+    audio_only: bool,
+    merge: bool,
+    format: Option<Selector>,
+    format_sort: SortOrder,
+    output: Option<String>,
+    connections: usize,
+    write_subs: bool,
+    write_auto_subs: bool,
+    sub_langs: Vec<String>,
+    sub_format: SubtitleFormat,
+    embed_subs: bool,
+    write_thumbnail: bool,
+    embed_thumbnail: bool,
+    interactive: bool,
+}
+
+async fn download_video(video_id: &str, options: &Options) -> anyhow::Result<()> {
+    let clients = &options.clients;
+    let interactive = options.interactive;
+    let quality = options.quality;
+    let info = yt_download::get_video_info(video_id, clients)
+        .await
+        .map_err(|e| e.context("An error occurs during html parse!"))?;
+    print_info(&info);
+    if info.get_streams().is_empty() {
+        return Err(anyhow!(
+            "No media links were found! Maybe this video has some limitations"
+        ));
+    }
+
+    let client = reqwest::Client::new();
+    let sizes = available(&client, info.get_streams()).await;
+    let is_available = |s: &&Stream| sizes.contains_key(&s.get_format().get_itag());
+    let size = |s: &Stream| sizes.get(&s.get_format().get_itag()).copied().flatten();
+    let video: Vec<&Stream> = select::video_streams(info.get_streams())
+        .into_iter()
+        .filter(is_available)
+        .collect();
+    let audio: Vec<&Stream> = select::audio_streams(info.get_streams())
+        .into_iter()
+        .filter(is_available)
+        .collect();
+    let describe_video = |s: &Stream| describe_video(s, size(s));
+    let describe_audio = |s: &Stream| describe_audio(s, size(s));
+
+    let video_only: Vec<&Stream> = select::video_only_streams(info.get_streams())
+        .into_iter()
+        .filter(|v| is_available(v) && select::audio_for(v, &audio).is_some())
+        .collect();
+    let mut merge_audio = None;
+    let stream = if let Some(selector) = &options.format {
+        let candidates: Vec<&Stream> = info.get_streams().iter().filter(is_available).collect();
+        match selector.select(&candidates, &options.format_sort, &size) {
+            Some(Selection::Single(stream)) => stream,
+            Some(Selection::Merge(video, audio)) => {
+                merge_audio = Some(audio);
+                video
+            }
+            None => return Err(anyhow!("Requested format is not available")),
+        }
+    } else if options.merge {
+        let stream = match quality {
+            Some(quality) => select::by_quality(&video_only, quality),
+            None if interactive => Some(choose_stream(&video_only, describe_video)),
+            None => select::by_quality(&video_only, Quality::Best),
+        }
+        .ok_or(anyhow!(
+            "No video formats with matching audio are available"
+        ))?;
+        merge_audio = select::audio_for(stream, &audio);
+        stream
+    } else if let Some(itag) = options.itag {
+        video
+            .iter()
+            .chain(audio.iter())
+            .copied()
+            .find(|s| s.get_format().get_itag() == itag)
+            .ok_or(anyhow!("Format with itag {} is not available", itag))?
+    } else if options.audio_only {
+        match quality {
+            Some(quality) => select::by_quality(&audio, quality),
+            None if interactive => Some(choose_stream(&audio, describe_audio)),
+            None => select::by_quality(&audio, Quality::Best),
+        }
+        .ok_or(anyhow!("No audio formats are available"))?
+    } else if quality.is_some() || !interactive {
+        select::by_quality(&video, quality.unwrap_or(Quality::Best))
+            .ok_or(anyhow!("No video formats are available"))?
+    } else {
+        println!("Choose what do you want to download (1-3):");
+        println!("1) Video ({} formats)", video.len());
+        println!("2) Audio ({} formats)", audio.len());
+        println!("3) Video + audio ({} formats)", video_only.len());
+        match read_number() {
+            1 => choose_stream(&video, describe_video),
+            2 => choose_stream(&audio, describe_audio),
+            3 => {
+                let stream = choose_stream(&video_only, describe_video);
+                merge_audio = select::audio_for(stream, &audio);
+                stream
+            }
+            _ => {
+                println!("1, 2 or 3 you, monkey");
+                exit(0);
+            }
+        }
+    };
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None if interactive => {
+            print!("Enter file name (empty for \"{}\"):", DEFAULT_TEMPLATE);
+            stdout().flush()?;
+            let mut name = String::new();
+            stdin().read_line(&mut name)?;
+            match name.trim() {
+                "" => DEFAULT_TEMPLATE.to_owned(),
+                name => name.to_owned(),
+            }
+        }
+        None => DEFAULT_TEMPLATE.to_owned(),
+    };
+    let filename = template::prepare(&output, &info, stream.get_format())
+        .map_err(|e| e.context("Cannot name the file"))?;
+
+    let write_subs = options.write_subs || options.write_auto_subs;
+    let subtitles = if write_subs || options.embed_subs {
+        get_subtitles(&info, options).await
+    } else {
+        Vec::new()
+    };
+    let cover = if options.write_thumbnail || options.embed_thumbnail {
+        match thumbnail::get_thumbnail(&info).await {
+            Ok(jpeg) => Some(jpeg),
+            Err(e) => {
+                println!("Cannot download the thumbnail: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Live streams are written as they arrive, so they are never remuxed
+    let container =
+        Container::from_path(&filename).filter(|_| stream.get_protocol() != Protocol::Hls);
+    let mut embeds = Embeds::default();
+    if options.embed_subs {
+        if container.is_some_and(|c| c.supports_subtitles()) {
+            embeds.subtitles = subtitles
+                .iter()
+                .map(|(language, cues)| Subtitle::new(language, options.sub_format, cues.clone()))
+                .collect();
+        } else {
+            println!(
+                "Subtitles cannot be embedded into \"{}\"",
+                filename.display()
+            );
+        }
+    }
+    if options.embed_thumbnail {
+        if container.is_some_and(|c| c.supports_cover()) {
+            embeds.cover = cover.clone();
+        } else {
+            println!(
+                "Thumbnails cannot be embedded into \"{}\"",
+                filename.display()
+            );
+        }
+    }
+    let mut stream = stream.clone();
+    let mut merge_audio = merge_audio.cloned();
+    let mut attempt = 0;
+    while let Err(e) = download_file(
+        &stream,
+        merge_audio.as_ref(),
+        &filename,
+        options.connections,
+        &embeds,
+    )
+    .await
+    {
+        // Live streams restart from their current position, so only files are resumed
+        if attempt == RETRIES || stream.get_protocol() == Protocol::Hls {
+            return Err(e.context("Error occurred during downloading"));
+        }
+        attempt += 1;
+        println!("Download interrupted ({}), resuming with fresh urls", e);
+        // Stream urls expire after a few hours, a new extraction renews them
+        if let Ok(info) = yt_download::get_video_info(video_id, clients).await {
+            let itag = stream.get_format().get_itag();
+            if let Some(fresh) = select::by_itag(info.get_streams(), itag) {
+                stream = fresh.clone();
+            }
+            if let Some(audio) = merge_audio.as_mut() {
+                let itag = audio.get_format().get_itag();
+                if let Some(fresh) = select::by_itag(info.get_streams(), itag) {
+                    *audio = fresh.clone();
+                }
+            }
+        }
+    }
+    if write_subs {
+        write_subtitles(&subtitles, &filename, options.sub_format);
+    }
+    if let Some(jpeg) = cover.filter(|_| options.write_thumbnail) {
+        let path = filename.with_extension("jpg");
+        match std::fs::write(&path, jpeg) {
+            Ok(()) => println!("Thumbnail written to \"{}\"", path.display()),
+            Err(e) => println!("Cannot write the thumbnail: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/** Downloads the cues of the requested subtitles with their language. Subtitles written by the
+uploader are preferred unless only automatic captions were asked for. Missing languages and
+failed tracks are reported but do not fail the download*/
+async fn get_subtitles(info: &VideoInfo, options: &Options) -> Vec<(String, Vec<Cue>)> {
+    let tracks = subtitles::select_tracks(
+        info.get_captions(),
+        info.get_translation_languages(),
+        &options.sub_langs,
+        options.write_subs || !options.write_auto_subs,
+        options.write_auto_subs,
+    );
+    for language in &options.sub_langs {
+        if language != "all" && !tracks.iter().any(|(l, _, _)| l == language) {
+            println!("No subtitles for language {}", language);
+        }
+    }
+    let mut subtitles = Vec::new();
+    for (language, track, translate) in tracks {
+        match subtitles::get_cues(track, translate.as_deref()).await {
+            Ok(cues) => subtitles.push((language, cues)),
+            Err(e) => println!("Cannot download {} subtitles: {:#}", language, e),
+        }
+    }
+    subtitles
+}
+
+/** Writes subtitles as "<media name>.<language>.<format>"*/
+fn write_subtitles(subtitles: &[(String, Vec<Cue>)], media: &Path, format: SubtitleFormat) {
+    for (language, cues) in subtitles {
+        let path = media.with_extension(format!("{}.{}", language, format.get_extension()));
+        match std::fs::write(&path, format.write(cues)) {
+            Ok(()) => println!("Subtitles written to \"{}\"", path.display()),
+            Err(e) => println!("Cannot write {} subtitles: {}", language, e),
+        }
+    }
+}
+
+/** Downloads the selected videos of a playlist or channel one after another.
+Returns whether all of them were downloaded*/
+async fn download_entries(
+    name: &str,
+    entries: &[Entry],
+    items: Option<&Items>,
+    options: &Options,
+) -> bool {
+    let selected: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| items.is_none_or(|items| items.contains(e.get_index())))
+        .collect();
+    println!(
+        "{}: downloading {} of {} videos",
+        name,
+        selected.len(),
+        entries.len()
+    );
+    let mut failed = 0;
+    for (i, entry) in selected.iter().enumerate() {
+        println!(
+            "\n[{}/{}] {} ({})",
+            i + 1,
+            selected.len(),
+            entry.get_title(),
+            entry.get_video_id()
+        );
+        if let Err(e) = download_video(entry.get_video_id(), options).await {
+            println!("{:#}", e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        println!("\n{} of {} videos failed", failed, selected.len());
+    }
+    failed == 0
+}
+
+async fn download_playlist(playlist_id: &str, items: Option<&Items>, options: &Options) -> bool {
+    match get_playlist(playlist_id).await {
+        Ok(playlist) => {
+            let name = format!("Playlist \"{}\"", playlist.get_title());
+            download_entries(&name, playlist.get_entries(), items, options).await
+        }
+        Err(e) => {
+            println!("{:#}", e);
+            false
+        }
+    }
+}
+
+/** Downloads the videos of each tab of a channel in turn*/
+async fn download_channel(
+    channel: &ChannelRef,
+    tabs: &[Tab],
+    items: Option<&Items>,
+    options: &Options,
+) -> bool {
+    let channel_id = match channel::resolve_channel_id(channel).await {
+        Ok(channel_id) => channel_id,
+        Err(e) => {
+            println!("{:#}", e);
+            return false;
+        }
+    };
+    let mut success = true;
+    for tab in tabs {
+        match channel::get_channel(&channel_id, *tab).await {
+            Ok(channel) => {
+                let name = format!("Channel \"{}\" ({})", channel.get_title(), tab);
+                success &= download_entries(&name, channel.get_entries(), items, options).await;
+            }
+            Err(e) => {
+                println!("{:#}", e);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+/** Downloads a single video, reporting whether it succeeded*/
+async fn download_single(video_id: &str, options: &Options) -> bool {
+    match download_video(video_id, options).await {
+        Ok(()) => true,
+        Err(e) => {
+            println!("{:#}", e);
+            false
+        }
+    }
+}
+
+#[tokio::main]
 async fn main() {
-    let youtube_page_link =
-        Regex::new("(http|https)://(www\\.|m.|)youtube\\.com/watch\\?v=(.+?)( |\\z|&)").unwrap();
-    let youtube_page_short_link =
-        Regex::new("(http|https)://(www\\.|)youtu.be/(.+?)( |\\z|&)").unwrap();
     let args = Args::parse();
     match args.command {
-        Commands::Download { url } => {
-            if youtube_page_link.is_match(&url) || youtube_page_short_link.is_match(&url) {
-                let links = help::get_stream_urls(&url).await;
-                if links.is_err() {
-                    println!(
-                        "An error occurs during html parse!\n{:?}",
-                        links.err().unwrap()
-                    );
+        Commands::Download {
+            url,
+            client,
+            itag,
+            quality,
+            audio_only,
+            merge,
+            format,
+            format_sort,
+            output,
+            connections,
+            playlist_items,
+            tab,
+            write_subs,
+            write_auto_subs,
+            sub_langs,
+            sub_format,
+            embed_subs,
+            write_thumbnail,
+            embed_thumbnail,
+            yes,
+        } => {
+            let link = match url.parse::<Link>() {
+                Ok(link) => link,
+                Err(e) => {
+                    println!("It's not a YouTube video, playlist or channel link! {}", e);
+                    return;
+                }
+            };
+            let mut options = Options {
+                clients: if client.is_empty() {
+                    Client::DEFAULT_ORDER.to_vec()
                 } else {
-                    let links = links.unwrap();
-                    if links.is_empty() {
-                        println!(
-                            "No media links were found! Maybe this video has some limitations"
-                        );
-                    } else {
-                        let mut video: Vec<&(String, &Format)> = links
-                            .iter()
-                            .filter(|a| a.1 .1.get_height() != -1)
-                            .map(|a| a.1)
-                            .collect();
-                        let mut audio: Vec<&(String, &Format)> = links
-                            .iter()
-                            .filter(|a| a.1 .1.get_height() == -1)
-                            .map(|a| a.1)
-                            .collect();
-                        let client = reqwest::Client::new();
-                        let mut delete = Vec::new();
-                        for i in 0..video.len() {
-                            if client.head(&video[i].0).send().await.unwrap().status() != 200 {
-                                delete.push(i);
-                            }
-                        }
-                        let mut m = 0;
-                        for x in delete {
-                            video.remove(x - m);
-                            m += 1;
-                        }
-                        let mut delete = Vec::new();
-                        for i in 0..audio.len() {
-                            if client.head(&audio[i].0).send().await.unwrap().status() != 200 {
-                                delete.push(i);
-                            }
-                        }
-                        let mut m = 0;
-                        for x in delete {
-                            audio.remove(x - m);
-                            m += 1;
-                        }
-                        video.sort_by(|a, b| {
-                            a.1.get_height().partial_cmp(&b.1.get_height()).unwrap()
-                        });
-                        audio.sort_by(|a, b| {
-                            a.1.get_audio_bitrate()
-                                .partial_cmp(&b.1.get_audio_bitrate())
-                                .unwrap()
-                        });
-
-                        println!("Choose what do you want to download (1-2):");
-                        println!("1) Video ({} formats)", video.len());
-                        println!("2) Audio ({} formats)", audio.len());
-                        let number = read_number();
-                        if (1..=2).contains(&number) {
-                            match number {
-                                1 => {
-                                    if video.is_empty() {
-                                        exit(0);
-                                    }
-                                    println!(
-                                        "Choose which file do you want to download (1-{}):",
-                                        video.len()
-                                    );
-                                    for i in 0..video.len() {
-                                        println!("{}) Format = {}, Resolution = {}p, Fps = {}, Codec = {:?}", i + 1,
-                                                 video[i].1.get_extension(),
-                                                 video[i].1.get_height(),
-                                                 video[i].1.get_fps(),
-                                                 video[i].1.get_video_codec());
-                                    }
-
-                                    let number = read_number();
-                                    if (1..=(video.len() as i32)).contains(&number) {
-                                        let number = number as usize - 1;
-                                        print!("Enter file name:");
-                                        stdout().flush().unwrap();
-                                        let mut name = String::new();
-                                        stdin().read_line(&mut name).unwrap();
-                                        let filename = name.trim().to_owned()
-                                            + "."
-                                            + video[number].1.get_extension();
-                                        let err = download_file(&video[number].0, &filename).await;
-                                        if err.is_err() {
-                                            println!("Error occurred during downloading");
-                                        }
-                                    } else {
-                                        println!("Not again!");
-                                    }
-                                }
-                                2 => {
-                                    if audio.is_empty() {
-                                        exit(0);
-                                    }
-                                    println!(
-                                        "Choose which file do you want to download (1-{}):",
-                                        audio.len()
-                                    );
-                                    for i in 0..audio.len() {
-                                        println!(
-                                            "{}) Format = {}, AudioBitrate = {}, Codec = {:?}",
-                                            i + 1,
-                                            audio[i].1.get_extension(),
-                                            audio[i].1.get_audio_bitrate(),
-                                            audio[i].1.get_audio_codec()
-                                        );
-                                    }
-                                    let number = read_number();
-                                    if (1..=(audio.len() as i32)).contains(&number) {
-                                        let number = number as usize - 1;
-                                        print!("Enter file name:");
-                                        stdout().flush().unwrap();
-                                        let mut name = String::new();
-                                        stdin().read_line(&mut name).unwrap();
-                                        let filename = name.trim().to_owned()
-                                            + "."
-                                            + audio[number].1.get_extension();
-                                        let err = download_file(&audio[number].0, &filename).await;
-                                        if err.is_err() {
-                                            println!("Error occurred during downloading");
-                                        }
-                                    } else {
-                                        println!("Not again!");
-                                    }
-                                }
-                                _ => unreachable!(),
-                            }
-                        } else {
-                            println!("1 or 2 you, monkey");
-                        }
+                    client
+                },
+                itag,
+                quality,
+                audio_only,
+                merge,
+                format,
+                format_sort,
+                output,
+                connections: connections.max(1),
+                write_subs,
+                write_auto_subs,
+                sub_langs,
+                sub_format,
+                embed_subs,
+                write_thumbnail,
+                embed_thumbnail,
+                interactive: !yes && stdin().is_terminal(),
+            };
+            let items = playlist_items.as_ref();
+            let success = match link {
+                // Mixes are endless and generated per viewer, so only their video is downloaded
+                Link::Video {
+                    list: Some(list), ..
+                }
+                | Link::Playlist { id: list }
+                    if !list.starts_with("RD") =>
+                {
+                    // Every entry is downloaded with the same choices, so nothing is asked
+                    options.interactive = false;
+                    download_playlist(&list, items, &options).await
+                }
+                Link::Video { id, .. } => download_single(&id, &options).await,
+                Link::Playlist { id } => {
+                    println!("Mix {} cannot be downloaded as a playlist", id);
+                    false
+                }
+                Link::Channel {
+                    channel,
+                    tab: url_tab,
+                } => {
+                    // A tab in the url is used unless tabs were chosen
+                    let tabs = match url_tab {
+                        _ if !tab.is_empty() => tab,
+                        Some(url_tab) => vec![url_tab],
+                        None => vec![Tab::Videos],
+                    };
+                    options.interactive = false;
+                    download_channel(&channel, &tabs, items, &options).await
+                }
+                Link::Clip { id } => match link::resolve_clip(&id).await {
+                    Ok(video_id) => download_single(&video_id, &options).await,
+                    Err(e) => {
+                        println!("{:#}", e);
+                        false
                     }
+                },
+                Link::Search { query } => {
+                    println!("Search results for \"{}\" cannot be downloaded", query);
+                    false
                 }
-            } else {
-                println!("It's not a YouTube video link!");
+            };
+            if !success {
+                exit(1);
             }
         }
     }
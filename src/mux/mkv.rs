@@ -0,0 +1,1117 @@
+//! Matroska (MKV/WebM) remuxer combining the tracks of several files into one.
+//!
+//! Track entries are copied with their codec private data, blocks of all inputs are merged
+//! into new clusters ordered by timestamp and cues are written for video keyframes.
+//! Subtitles are added as text tracks whose blocks are merged with those of the inputs.
+
+use super::{iso639_2, Subtitle};
+use crate::subtitles::{escape_vtt, SubtitleFormat};
+use anyhow::anyhow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub(crate) const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+pub(crate) const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+pub(crate) const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+pub(crate) const TRACKS: u32 = 0x1654AE6B;
+pub(crate) const TRACK_ENTRY: u32 = 0xAE;
+pub(crate) const TRACK_NUMBER: u32 = 0xD7;
+pub(crate) const TRACK_UID: u32 = 0x73C5;
+pub(crate) const TRACK_TYPE: u32 = 0x83;
+const FLAG_DEFAULT: u32 = 0x88;
+const FLAG_LACING: u32 = 0x9C;
+const LANGUAGE: u32 = 0x22B59C;
+const LANGUAGE_BCP47: u32 = 0x22B59D;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+pub(crate) const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+pub(crate) const SIMPLE_BLOCK: u32 = 0xA3;
+pub(crate) const BLOCK_GROUP: u32 = 0xA0;
+pub(crate) const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const REFERENCE_BLOCK: u32 = 0xFB;
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+pub(crate) const ATTACHMENTS: u32 = 0x1941A469;
+pub(crate) const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MEDIA_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const FILE_UID: u32 = 0x46AE;
+
+/** Elements whose payload is a sequence of elements*/
+const MASTERS: [u32; 14] = [
+    EBML,
+    SEGMENT,
+    SEEK_HEAD,
+    SEEK,
+    INFO,
+    TRACKS,
+    TRACK_ENTRY,
+    CLUSTER,
+    BLOCK_GROUP,
+    CUES,
+    CUE_POINT,
+    CUE_TRACK_POSITIONS,
+    ATTACHMENTS,
+    ATTACHED_FILE,
+];
+
+/** Output timestamps are in milliseconds*/
+const OUTPUT_TIMECODE_SCALE: u64 = 1_000_000;
+
+/** Track type of video tracks*/
+const VIDEO_TRACK: u64 = 1;
+
+/** Track type of subtitle tracks*/
+const SUBTITLE_TRACK: u64 = 0x11;
+
+/** An EBML element, either a master of elements or a leaf with raw data*/
+#[derive(Debug, Clone)]
+pub(crate) struct Element {
+    id: u32,
+    data: Vec<u8>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    pub(crate) fn leaf(id: u32, data: Vec<u8>) -> Element {
+        Element {
+            id,
+            data,
+            children: Vec::new(),
+        }
+    }
+
+    pub(crate) fn master(id: u32, children: Vec<Element>) -> Element {
+        Element {
+            id,
+            data: Vec::new(),
+            children,
+        }
+    }
+
+    pub(crate) fn uint(id: u32, value: u64) -> Element {
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take(7).take_while(|b| **b == 0).count();
+        Element::leaf(id, bytes[skip..].to_vec())
+    }
+
+    pub(crate) fn float(id: u32, value: f64) -> Element {
+        Element::leaf(id, value.to_be_bytes().to_vec())
+    }
+
+    pub(crate) fn string(id: u32, value: &str) -> Element {
+        Element::leaf(id, value.as_bytes().to_vec())
+    }
+
+    fn parse_all(mut data: &[u8]) -> anyhow::Result<Vec<Element>> {
+        let mut elements = Vec::new();
+        while !data.is_empty() {
+            let (id, id_length) = read_id(data)?;
+            let (size, size_length) = read_size(&data[id_length..])?;
+            let start = id_length + size_length;
+            let end = match size {
+                Some(size) if start + size as usize <= data.len() => start + size as usize,
+                Some(_) => return Err(anyhow!("Malformed EBML element {:X}", id)),
+                None => data.len(),
+            };
+            let body = &data[start..end];
+            elements.push(if MASTERS.contains(&id) {
+                Element::master(id, Element::parse_all(body)?)
+            } else {
+                Element::leaf(id, body.to_vec())
+            });
+            data = &data[end..];
+        }
+        Ok(elements)
+    }
+
+    fn find(&self, id: u32) -> Option<&Element> {
+        self.children.iter().find(|e| e.id == id)
+    }
+
+    fn as_uint(&self) -> u64 {
+        self.data.iter().fold(0, |value, b| value << 8 | *b as u64)
+    }
+
+    fn as_float(&self) -> Option<f64> {
+        match self.data.len() {
+            4 => Some(f32::from_be_bytes(self.data[..].try_into().ok()?) as f64),
+            8 => Some(f64::from_be_bytes(self.data[..].try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /** Replaces the first child with the same id or appends the element*/
+    fn set(&mut self, element: Element) {
+        match self.children.iter_mut().find(|e| e.id == element.id) {
+            Some(child) => *child = element,
+            None => self.children.push(element),
+        }
+    }
+
+    fn body_size(&self) -> u64 {
+        if MASTERS.contains(&self.id) {
+            self.children.iter().map(Element::size).sum()
+        } else {
+            self.data.len() as u64
+        }
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        let body = self.body_size();
+        (id_bytes(self.id).len() + size_length(body)) as u64 + body
+    }
+
+    pub(crate) fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let body = self.body_size();
+        out.write_all(&id_bytes(self.id))?;
+        out.write_all(&encode_size(body, size_length(body)))?;
+        if MASTERS.contains(&self.id) {
+            for child in &self.children {
+                child.write(out)?;
+            }
+        } else {
+            out.write_all(&self.data)?;
+        }
+        Ok(())
+    }
+}
+
+fn id_bytes(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+/** Reads an element id, which keeps its length marker*/
+fn read_id(data: &[u8]) -> anyhow::Result<(u32, usize)> {
+    let first = *data.first().ok_or(anyhow!("Unexpected end of EBML data"))?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 4 || data.len() < length {
+        return Err(anyhow!("Invalid EBML element id"));
+    }
+    let id = data[..length].iter().fold(0, |id, b| id << 8 | *b as u32);
+    Ok((id, length))
+}
+
+/** Reads a variable size integer, None if it has all value bits set which means an unknown size*/
+fn read_size(data: &[u8]) -> anyhow::Result<(Option<u64>, usize)> {
+    let first = *data.first().ok_or(anyhow!("Unexpected end of EBML data"))?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 || data.len() < length {
+        return Err(anyhow!("Invalid EBML element size"));
+    }
+    let mut value = (first as u64) & (0xFF >> length);
+    for b in &data[1..length] {
+        value = value << 8 | *b as u64;
+    }
+    let unknown = (1u64 << (7 * length)) - 1;
+    Ok((if value == unknown { None } else { Some(value) }, length))
+}
+
+fn size_length(size: u64) -> usize {
+    (1..8).find(|l| size < (1 << (7 * l)) - 1).unwrap_or(8)
+}
+
+fn encode_size(size: u64, length: usize) -> Vec<u8> {
+    let value = size | 1 << (7 * length);
+    value.to_be_bytes()[8 - length..].to_vec()
+}
+
+/** A block of an input with its absolute time in output timecode units*/
+struct Block {
+    track: u64,
+    time: i64,
+    keyframe: bool,
+    element: Element,
+}
+
+/** Offset of the relative timecode in a Block or SimpleBlock, after its track number*/
+fn block_header(data: &[u8]) -> anyhow::Result<(u64, usize)> {
+    let (track, length) = read_size(data)?;
+    if data.len() < length + 3 {
+        return Err(anyhow!("Malformed Matroska block"));
+    }
+    Ok((track.unwrap_or(0), length))
+}
+
+/** Replaces track number and relative timecode of a Block or SimpleBlock*/
+fn rewrite_block(data: &[u8], track: u64, timecode: i16) -> anyhow::Result<Vec<u8>> {
+    let (_, length) = block_header(data)?;
+    let mut block = encode_size(track, size_length(track));
+    block.extend_from_slice(&timecode.to_be_bytes());
+    block.extend_from_slice(&data[length + 2..]);
+    Ok(block)
+}
+
+struct Input {
+    reader: BufReader<File>,
+    position: u64,
+    end: u64,
+    timecode_scale: u64,
+    duration: Option<f64>,
+    tracks: Vec<Element>,
+    blocks: VecDeque<Block>,
+}
+
+impl Input {
+    fn open(path: &Path) -> anyhow::Result<Input> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut input = Input {
+            reader: BufReader::new(file),
+            position: 0,
+            end: length,
+            timecode_scale: OUTPUT_TIMECODE_SCALE,
+            duration: None,
+            tracks: Vec::new(),
+            blocks: VecDeque::new(),
+        };
+        let (id, size) = input.read_header()?;
+        if id != EBML {
+            return Err(anyhow!("\"{}\" is not a Matroska file", path.display()));
+        }
+        input.skip(size)?;
+        let (id, size) = input.read_header()?;
+        if id != SEGMENT {
+            return Err(anyhow!("\"{}\" has no Segment", path.display()));
+        }
+        if let Some(size) = size {
+            input.end = input.end.min(input.position + size);
+        }
+
+        // Everything before the first cluster describes the segment
+        while input.position < input.end {
+            let (id, size) = input.read_header()?;
+            match id {
+                INFO | TRACKS => {
+                    let element = Element::master(id, Element::parse_all(&input.read(size)?)?);
+                    if id == INFO {
+                        if let Some(scale) = element.find(TIMECODE_SCALE) {
+                            input.timecode_scale = scale.as_uint();
+                        }
+                        input.duration = element.find(DURATION).and_then(Element::as_float);
+                    } else {
+                        input.tracks = element
+                            .children
+                            .into_iter()
+                            .filter(|e| e.id == TRACK_ENTRY)
+                            .collect();
+                    }
+                }
+                CLUSTER => {
+                    input.read_cluster(size)?;
+                    break;
+                }
+                _ => input.skip(size)?,
+            }
+        }
+        if input.tracks.is_empty() {
+            return Err(anyhow!("\"{}\" has no tracks", path.display()));
+        }
+        Ok(input)
+    }
+
+    fn read_header(&mut self) -> anyhow::Result<(u32, Option<u64>)> {
+        let mut head = [0; 12];
+        self.reader.read_exact(&mut head[..1])?;
+        let id_length = head[0].leading_zeros() as usize + 1;
+        if id_length > 4 {
+            return Err(anyhow!("Invalid EBML element id"));
+        }
+        self.reader.read_exact(&mut head[1..id_length])?;
+        self.reader
+            .read_exact(&mut head[id_length..id_length + 1])?;
+        let size_length = head[id_length].leading_zeros() as usize + 1;
+        if size_length > 8 {
+            return Err(anyhow!("Invalid EBML element size"));
+        }
+        self.reader
+            .read_exact(&mut head[id_length + 1..id_length + size_length])?;
+        self.position += (id_length + size_length) as u64;
+        let (id, _) = read_id(&head)?;
+        let (size, _) = read_size(&head[id_length..])?;
+        Ok((id, size))
+    }
+
+    fn read(&mut self, size: Option<u64>) -> anyhow::Result<Vec<u8>> {
+        let size = size.ok_or(anyhow!("Elements of unknown size are not supported"))?;
+        let mut data = vec![0; size as usize];
+        self.reader.read_exact(&mut data)?;
+        self.position += size;
+        Ok(data)
+    }
+
+    fn skip(&mut self, size: Option<u64>) -> anyhow::Result<()> {
+        let size = size.ok_or(anyhow!("Elements of unknown size are not supported"))?;
+        self.reader.seek(SeekFrom::Current(size as i64))?;
+        self.position += size;
+        Ok(())
+    }
+
+    /** Converts a timestamp in input timecode units to output ones*/
+    fn to_output(&self, time: i64) -> i64 {
+        (time as i128 * self.timecode_scale as i128 / OUTPUT_TIMECODE_SCALE as i128) as i64
+    }
+
+    fn read_cluster(&mut self, size: Option<u64>) -> anyhow::Result<()> {
+        let children = Element::parse_all(&self.read(size)?)?;
+        let timecode = children
+            .iter()
+            .find(|e| e.id == TIMECODE)
+            .map(Element::as_uint)
+            .unwrap_or(0) as i64;
+        for element in children {
+            let (data, keyframe) = match element.id {
+                SIMPLE_BLOCK => (&element.data, false),
+                BLOCK_GROUP => match element.find(BLOCK) {
+                    Some(block) => (&block.data, element.find(REFERENCE_BLOCK).is_none()),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let (track, length) = block_header(data)?;
+            let relative = i16::from_be_bytes([data[length], data[length + 1]]) as i64;
+            let keyframe = keyframe || (element.id == SIMPLE_BLOCK && data[length + 2] & 0x80 != 0);
+            let time = self.to_output(timecode + relative);
+            self.blocks.push_back(Block {
+                track,
+                time,
+                keyframe,
+                element,
+            });
+        }
+        Ok(())
+    }
+
+    /** The next block of the input, reading further clusters as needed*/
+    fn peek(&mut self) -> anyhow::Result<Option<&Block>> {
+        while self.blocks.is_empty() && self.position < self.end {
+            let (id, size) = self.read_header()?;
+            if id == CLUSTER {
+                self.read_cluster(size)?;
+            } else {
+                self.skip(size)?;
+            }
+        }
+        Ok(self.blocks.front())
+    }
+}
+
+/** Additional elements written into the output segment, and subtitles added as text tracks*/
+#[derive(Default)]
+pub(crate) struct Extras {
+    pub(crate) attachments: Option<Element>,
+    pub(crate) subtitles: Vec<Subtitle>,
+}
+
+/** Attachments holding cover art, named "cover.jpg" as players look for it*/
+pub(crate) fn cover_attachments(jpeg: &[u8]) -> Element {
+    Element::master(
+        ATTACHMENTS,
+        vec![Element::master(
+            ATTACHED_FILE,
+            vec![
+                Element::string(FILE_NAME, "cover.jpg"),
+                Element::string(FILE_MEDIA_TYPE, "image/jpeg"),
+                Element::leaf(FILE_DATA, jpeg.to_vec()),
+                Element::uint(FILE_UID, 1),
+            ],
+        )],
+    )
+}
+
+/** The track entry of a subtitle and the blocks of its cues. SRT cues become an S_TEXT/UTF8
+track, WebVTT ones an S_TEXT/WEBVTT track, or D_WEBVTT/SUBTITLES in WebM*/
+fn text_track(subtitle: &Subtitle, number: u64, webm: bool) -> (Element, Vec<Block>) {
+    let codec = match (webm, subtitle.get_format()) {
+        (true, _) => "D_WEBVTT/SUBTITLES",
+        (false, SubtitleFormat::Srt) => "S_TEXT/UTF8",
+        (false, SubtitleFormat::Vtt) => "S_TEXT/WEBVTT",
+    };
+    let mut entry = Element::master(
+        TRACK_ENTRY,
+        vec![
+            Element::uint(TRACK_NUMBER, number),
+            Element::uint(TRACK_UID, number),
+            Element::uint(TRACK_TYPE, SUBTITLE_TRACK),
+            Element::uint(FLAG_DEFAULT, 0),
+            Element::uint(FLAG_LACING, 0),
+            Element::string(CODEC_ID, codec),
+            Element::string(LANGUAGE, &iso639_2(subtitle.get_language(), true)),
+        ],
+    );
+    if !webm {
+        entry.set(Element::string(LANGUAGE_BCP47, subtitle.get_language()));
+    }
+    if codec == "S_TEXT/WEBVTT" {
+        entry.set(Element::string(CODEC_PRIVATE, "WEBVTT"));
+    }
+
+    let blocks = subtitle
+        .get_cues()
+        .iter()
+        .filter(|cue| cue.get_end() > cue.get_start())
+        .map(|cue| {
+            let text = match codec {
+                "S_TEXT/UTF8" => cue.get_text().to_owned(),
+                // WebM blocks start with the cue identifier and settings lines, both empty here
+                "D_WEBVTT/SUBTITLES" => format!("\n\n{}", escape_vtt(cue.get_text())),
+                _ => escape_vtt(cue.get_text()),
+            };
+            // Track number and timecode are set when the block is put into a cluster
+            let mut data = encode_size(number, size_length(number));
+            data.extend_from_slice(&[0, 0, 0]);
+            data.extend_from_slice(text.as_bytes());
+            Block {
+                track: number,
+                time: cue.get_start() as i64,
+                keyframe: true,
+                element: Element::master(
+                    BLOCK_GROUP,
+                    vec![
+                        Element::leaf(BLOCK, data),
+                        Element::uint(BLOCK_DURATION, cue.get_end() - cue.get_start()),
+                    ],
+                ),
+            }
+        })
+        .collect();
+    (entry, blocks)
+}
+
+/** Combines the tracks of all inputs into one Matroska file, as WebM if `webm` is set*/
+pub fn mux(inputs: &[&Path], output: &Path, webm: bool) -> anyhow::Result<()> {
+    mux_with(inputs, output, webm, Extras::default())
+}
+
+pub(crate) fn mux_with(
+    inputs: &[&Path],
+    output: &Path,
+    webm: bool,
+    extras: Extras,
+) -> anyhow::Result<()> {
+    let mut inputs = inputs
+        .iter()
+        .map(|p| Input::open(p))
+        .collect::<anyhow::Result<Vec<Input>>>()?;
+
+    // Track numbers of the output for every input's track numbers
+    let mut numbers: Vec<Vec<(u64, u64)>> = Vec::new();
+    let mut tracks = Element::master(TRACKS, Vec::new());
+    let mut video_tracks = Vec::new();
+    for input in &inputs {
+        let mut mapping = Vec::new();
+        for entry in &input.tracks {
+            let number = tracks.children.len() as u64 + 1;
+            let source = entry.find(TRACK_NUMBER).map(Element::as_uint).unwrap_or(0);
+            let mut entry = entry.clone();
+            entry.set(Element::uint(TRACK_NUMBER, number));
+            entry.set(Element::uint(TRACK_UID, number));
+            if entry.find(TRACK_TYPE).map(Element::as_uint) == Some(VIDEO_TRACK) {
+                video_tracks.push(number);
+            }
+            tracks.children.push(entry);
+            mapping.push((source, number));
+        }
+        numbers.push(mapping);
+    }
+    let mut texts = Vec::new();
+    for subtitle in &extras.subtitles {
+        let number = tracks.children.len() as u64 + 1;
+        let (entry, blocks) = text_track(subtitle, number, webm);
+        if !blocks.is_empty() {
+            tracks.children.push(entry);
+            texts.extend(blocks);
+        }
+    }
+    texts.sort_by_key(|b| b.time);
+    let mut texts = VecDeque::from(texts);
+
+    let duration = inputs
+        .iter()
+        .filter_map(|i| i.duration.map(|d| d * i.timecode_scale as f64))
+        .fold(0f64, f64::max)
+        / OUTPUT_TIMECODE_SCALE as f64;
+
+    let doc_type = if webm { "webm" } else { "matroska" };
+    let header = Element::master(
+        EBML,
+        vec![
+            Element::uint(EBML_VERSION, 1),
+            Element::uint(EBML_READ_VERSION, 1),
+            Element::uint(EBML_MAX_ID_LENGTH, 4),
+            Element::uint(EBML_MAX_SIZE_LENGTH, 8),
+            Element::string(DOC_TYPE, doc_type),
+            Element::uint(DOC_TYPE_VERSION, 4),
+            Element::uint(DOC_TYPE_READ_VERSION, 2),
+        ],
+    );
+    let app = concat!("yt_download ", env!("CARGO_PKG_VERSION"));
+    let info = Element::master(
+        INFO,
+        vec![
+            Element::uint(TIMECODE_SCALE, OUTPUT_TIMECODE_SCALE),
+            Element::string(MUXING_APP, app),
+            Element::string(WRITING_APP, app),
+            Element::float(DURATION, duration),
+        ],
+    );
+
+    let mut out = BufWriter::new(File::create(output)?);
+    header.write(&mut out)?;
+    out.write_all(&id_bytes(SEGMENT))?;
+    let segment_size_offset = header.size() + id_bytes(SEGMENT).len() as u64;
+    out.write_all(&encode_size(0, 8))?;
+    let segment_start = segment_size_offset + 8;
+
+    // Seek positions are written with eight bytes so the cues one can be patched at the end
+    let seek = |id: u32, position: u64| {
+        Element::master(
+            SEEK,
+            vec![
+                Element::leaf(SEEK_ID, id_bytes(id)),
+                Element::leaf(SEEK_POSITION, position.to_be_bytes().to_vec()),
+            ],
+        )
+    };
+    let mut level1 = vec![(INFO, &info), (TRACKS, &tracks)];
+    if let Some(attachments) = &extras.attachments {
+        level1.push((ATTACHMENTS, attachments));
+    }
+    let seek_head_size = Element::master(
+        SEEK_HEAD,
+        level1
+            .iter()
+            .map(|(id, _)| seek(*id, 0))
+            .chain(std::iter::once(seek(CUES, 0)))
+            .collect(),
+    )
+    .size();
+    let mut position = seek_head_size;
+    let mut seeks = Vec::new();
+    for (id, element) in &level1 {
+        seeks.push(seek(*id, position));
+        position += element.size();
+    }
+    let cues_seek_index = seeks.len();
+    seeks.push(seek(CUES, 0));
+    let seek_head = Element::master(SEEK_HEAD, seeks.clone());
+    seek_head.write(&mut out)?;
+    let mut written = seek_head.size();
+    let duration_offset = segment_start + written + info.size() - 8;
+    for (_, element) in &level1 {
+        element.write(&mut out)?;
+        written += element.size();
+    }
+
+    let mut cues = Element::master(CUES, Vec::new());
+    let mut cluster: Option<(i64, Element)> = None;
+    let mut last_time = 0i64;
+    loop {
+        let mut next: Option<(usize, i64)> = None;
+        for (index, input) in inputs.iter_mut().enumerate() {
+            if let Some(block) = input.peek()? {
+                if next.is_none_or(|(_, time)| block.time < time) {
+                    next = Some((index, block.time));
+                }
+            }
+        }
+        // Subtitle blocks already have their output track number
+        let is_text = texts
+            .front()
+            .is_some_and(|text| next.is_none_or(|(_, time)| text.time <= time));
+        let (block, number) = if is_text {
+            let block = texts.pop_front().unwrap();
+            let number = block.track;
+            (block, number)
+        } else {
+            let index = match next {
+                Some((index, _)) => index,
+                None => break,
+            };
+            let block = inputs[index].blocks.pop_front().unwrap();
+            let number = numbers[index]
+                .iter()
+                .find(|(source, _)| *source == block.track)
+                .map(|(_, number)| *number)
+                .ok_or(anyhow!("Block of unknown track {}", block.track))?;
+            (block, number)
+        };
+        let is_video_keyframe = block.keyframe && video_tracks.contains(&number);
+        last_time = last_time.max(block.time);
+
+        let starts_cluster = match &cluster {
+            None => true,
+            Some((start, _)) => is_video_keyframe || block.time - start > i16::MAX as i64,
+        };
+        if starts_cluster {
+            if let Some((_, finished)) = cluster.take() {
+                finished.write(&mut out)?;
+                written += finished.size();
+            }
+            if is_video_keyframe {
+                cues.children.push(Element::master(
+                    CUE_POINT,
+                    vec![
+                        Element::uint(CUE_TIME, block.time.max(0) as u64),
+                        Element::master(
+                            CUE_TRACK_POSITIONS,
+                            vec![
+                                Element::uint(CUE_TRACK, number),
+                                Element::uint(CUE_CLUSTER_POSITION, written),
+                            ],
+                        ),
+                    ],
+                ));
+            }
+            cluster = Some((
+                block.time,
+                Element::master(
+                    CLUSTER,
+                    vec![Element::uint(TIMECODE, block.time.max(0) as u64)],
+                ),
+            ));
+        }
+
+        let (start, current) = cluster.as_mut().unwrap();
+        let relative = (block.time - *start) as i16;
+        let mut element = block.element;
+        if element.id == SIMPLE_BLOCK {
+            element.data = rewrite_block(&element.data, number, relative)?;
+        } else if let Some(inner) = element.children.iter_mut().find(|e| e.id == BLOCK) {
+            inner.data = rewrite_block(&inner.data, number, relative)?;
+        }
+        current.children.push(element);
+    }
+    if let Some((_, finished)) = cluster.take() {
+        finished.write(&mut out)?;
+        written += finished.size();
+    }
+    let cues_position = written;
+    if !cues.children.is_empty() {
+        cues.write(&mut out)?;
+        written += cues.size();
+    }
+    out.flush()?;
+
+    let mut file = out.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(segment_size_offset))?;
+    file.write_all(&encode_size(written, 8))?;
+    if duration <= 0.0 {
+        file.seek(SeekFrom::Start(duration_offset))?;
+        file.write_all(&(last_time as f64).to_be_bytes())?;
+    }
+    if !cues.children.is_empty() {
+        seeks[cues_seek_index] = seek(CUES, cues_position);
+        file.seek(SeekFrom::Start(segment_start))?;
+        Element::master(SEEK_HEAD, seeks).write(&mut file)?;
+    } else {
+        // A void element of the same size hides the unused cues entry
+        let void_size = seek(CUES, 0).size();
+        file.seek(SeekFrom::Start(segment_start + seek_head_size - void_size))?;
+        file.write_all(&[0xEC])?;
+        file.write_all(&encode_size(void_size - 2, 1))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::Cue;
+
+    fn subtitle(language: &str, format: SubtitleFormat) -> Subtitle {
+        Subtitle::new(
+            language,
+            format,
+            vec![
+                Cue::new(1000, 2500, "first line\na < b"),
+                Cue::new(3000, 3000, "empty"),
+            ],
+        )
+    }
+
+    fn text(element: &Element, id: u32) -> Option<&str> {
+        element
+            .find(id)
+            .map(|e| std::str::from_utf8(&e.data).unwrap())
+    }
+
+    /** The payload of a subtitle block after track number, timecode and flags*/
+    fn payload(block: &Block) -> &[u8] {
+        &block.element.find(BLOCK).unwrap().data[4..]
+    }
+
+    #[test]
+    fn writes_srt_as_utf8_text() {
+        let (entry, blocks) = text_track(&subtitle("de", SubtitleFormat::Srt), 3, false);
+        assert_eq!(entry.find(TRACK_NUMBER).unwrap().as_uint(), 3);
+        assert_eq!(entry.find(TRACK_TYPE).unwrap().as_uint(), SUBTITLE_TRACK);
+        assert_eq!(text(&entry, CODEC_ID), Some("S_TEXT/UTF8"));
+        // Matroska uses the bibliographic code
+        assert_eq!(text(&entry, LANGUAGE), Some("ger"));
+        assert_eq!(text(&entry, LANGUAGE_BCP47), Some("de"));
+        assert!(entry.find(CODEC_PRIVATE).is_none());
+
+        // The cue without duration is left out
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].track, blocks[0].time), (3, 1000));
+        assert!(blocks[0].keyframe);
+        assert_eq!(payload(&blocks[0]), b"first line\na < b");
+        let duration = blocks[0].element.find(BLOCK_DURATION).unwrap();
+        assert_eq!(duration.as_uint(), 1500);
+    }
+
+    #[test]
+    fn writes_vtt_as_webvtt_text() {
+        let (entry, blocks) = text_track(&subtitle("pt-BR", SubtitleFormat::Vtt), 2, false);
+        assert_eq!(text(&entry, CODEC_ID), Some("S_TEXT/WEBVTT"));
+        assert_eq!(text(&entry, CODEC_PRIVATE), Some("WEBVTT"));
+        assert_eq!(text(&entry, LANGUAGE), Some("por"));
+        assert_eq!(text(&entry, LANGUAGE_BCP47), Some("pt-BR"));
+        assert_eq!(payload(&blocks[0]), b"first line\na &lt; b");
+    }
+
+    #[test]
+    fn writes_webm_cue_lines() {
+        let (entry, blocks) = text_track(&subtitle("xx", SubtitleFormat::Srt), 2, true);
+        assert_eq!(text(&entry, CODEC_ID), Some("D_WEBVTT/SUBTITLES"));
+        assert_eq!(text(&entry, LANGUAGE), Some("und"));
+        // WebM has no BCP 47 language element
+        assert!(entry.find(LANGUAGE_BCP47).is_none());
+        // Empty cue identifier and settings lines precede the text
+        assert_eq!(payload(&blocks[0]), b"\n\nfirst line\na &lt; b");
+    }
+
+    #[test]
+    fn attaches_cover() {
+        let attachments = cover_attachments(b"\xFF\xD8jpeg");
+        let file = attachments.find(ATTACHED_FILE).unwrap();
+        assert_eq!(text(file, FILE_NAME), Some("cover.jpg"));
+        assert_eq!(text(file, FILE_MEDIA_TYPE), Some("image/jpeg"));
+        assert_eq!(file.find(FILE_DATA).unwrap().data, b"\xFF\xD8jpeg");
+        assert_eq!(file.find(FILE_UID).unwrap().as_uint(), 1);
+
+        let mut written = Vec::new();
+        attachments.write(&mut written).unwrap();
+        assert_eq!(&written[..4], [0x19, 0x41, 0xA4, 0x69]);
+        let parsed = Element::parse_all(&written).unwrap();
+        let file = parsed[0].find(ATTACHED_FILE).unwrap();
+        assert_eq!(file.find(FILE_DATA).unwrap().data, b"\xFF\xD8jpeg");
+    }
+
+    #[test]
+    fn encodes_sizes_of_every_length() {
+        for (size, length) in [
+            (0, 1),
+            (126, 1),
+            // All value bits set would mean an unknown size
+            (127, 2),
+            ((1 << 14) - 2, 2),
+            ((1 << 14) - 1, 3),
+            ((1 << 21) - 1, 4),
+            ((1 << 28) - 1, 5),
+            ((1 << 35) - 1, 6),
+            ((1 << 42) - 1, 7),
+            ((1 << 49) - 1, 8),
+            ((1 << 56) - 2, 8),
+        ] {
+            assert_eq!(size_length(size), length, "{}", size);
+            let encoded = encode_size(size, length);
+            assert_eq!(encoded.len(), length);
+            assert_eq!(encoded[0].leading_zeros() as usize, length - 1);
+            assert_eq!(read_size(&encoded).unwrap(), (Some(size), length));
+        }
+        // Sizes may use more bytes than needed
+        assert_eq!(encode_size(5, 8), [1, 0, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(read_size(&encode_size(5, 8)).unwrap(), (Some(5), 8));
+        assert!(read_size(&[0]).is_err());
+        assert!(read_size(&[0x40]).is_err());
+    }
+
+    #[test]
+    fn reads_unknown_sizes() {
+        assert_eq!(read_size(&[0xFF]).unwrap(), (None, 1));
+        assert_eq!(read_size(&[0x7F, 0xFF]).unwrap(), (None, 2));
+        assert_eq!(
+            read_size(&[1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap(),
+            (None, 8)
+        );
+        // An element of unknown size extends to the end of its parent
+        let mut data = id_bytes(CLUSTER);
+        data.push(0xFF);
+        Element::uint(TIMECODE, 5).write(&mut data).unwrap();
+        Element::leaf(SIMPLE_BLOCK, vec![0x81, 0, 0, 0x80])
+            .write(&mut data)
+            .unwrap();
+        let elements = Element::parse_all(&data).unwrap();
+        assert_eq!(elements.len(), 1);
+        let children: Vec<u32> = elements[0].children.iter().map(|e| e.id).collect();
+        assert_eq!(children, [TIMECODE, SIMPLE_BLOCK]);
+        assert_eq!(elements[0].find(TIMECODE).unwrap().as_uint(), 5);
+    }
+
+    fn simple_block(track: u64, timecode: i16, keyframe: bool, payload: u8) -> Element {
+        let mut data = encode_size(track, 1);
+        data.extend_from_slice(&timecode.to_be_bytes());
+        data.push(if keyframe { 0x80 } else { 0 });
+        data.push(payload);
+        Element::leaf(SIMPLE_BLOCK, data)
+    }
+
+    /** A file with a single track in a segment of unknown size, as live streams are written*/
+    fn file(
+        track: u64,
+        track_type: u64,
+        scale: u64,
+        clusters: Vec<(u64, Vec<Element>)>,
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        Element::master(EBML, vec![Element::string(DOC_TYPE, "webm")])
+            .write(&mut data)
+            .unwrap();
+        data.extend_from_slice(&id_bytes(SEGMENT));
+        data.push(0xFF);
+        let info = Element::master(INFO, vec![Element::uint(TIMECODE_SCALE, scale)]);
+        let entry = Element::master(
+            TRACK_ENTRY,
+            vec![
+                Element::uint(TRACK_NUMBER, track),
+                Element::uint(TRACK_UID, 77),
+                Element::uint(TRACK_TYPE, track_type),
+                Element::string(CODEC_ID, "V_VP9"),
+            ],
+        );
+        info.write(&mut data).unwrap();
+        Element::master(TRACKS, vec![entry])
+            .write(&mut data)
+            .unwrap();
+        for (timecode, mut blocks) in clusters {
+            blocks.insert(0, Element::uint(TIMECODE, timecode));
+            Element::master(CLUSTER, blocks).write(&mut data).unwrap();
+        }
+        data
+    }
+
+    /** Muxes the inputs through temporary files and returns the children of the output's
+    segment with their offsets relative to its data*/
+    fn mux_files(name: &str, inputs: &[Vec<u8>]) -> Vec<(u64, Element)> {
+        let dir = std::env::temp_dir();
+        let prefix = format!("yt_download-{}-{}", std::process::id(), name);
+        let paths: Vec<_> = (0..inputs.len())
+            .map(|i| dir.join(format!("{}-{}.webm", prefix, i)))
+            .collect();
+        for (path, data) in paths.iter().zip(inputs) {
+            std::fs::write(path, data).unwrap();
+        }
+        let output = dir.join(format!("{}.webm", prefix));
+        let inputs: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+        let result = mux_with(&inputs, &output, true, Extras::default());
+        let data = std::fs::read(&output);
+        for path in paths.iter().chain([&output]) {
+            let _ = std::fs::remove_file(path);
+        }
+        result.unwrap();
+        let mut elements = Element::parse_all(&data.unwrap()).unwrap();
+        assert_eq!(elements.len(), 2);
+        let segment = elements.pop().unwrap();
+        assert_eq!(segment.id, SEGMENT);
+        let mut position = 0;
+        segment
+            .children
+            .into_iter()
+            .map(|e| {
+                let start = position;
+                position += e.size();
+                (start, e)
+            })
+            .collect()
+    }
+
+    /** Track number and relative timecode of the blocks of a cluster*/
+    fn blocks(cluster: &Element) -> Vec<(u64, i16, u8)> {
+        cluster
+            .children
+            .iter()
+            .filter(|e| e.id == SIMPLE_BLOCK)
+            .map(|e| {
+                let (track, length) = block_header(&e.data).unwrap();
+                let timecode = i16::from_be_bytes([e.data[length], e.data[length + 1]]);
+                (track, timecode, *e.data.last().unwrap())
+            })
+            .collect()
+    }
+
+    /** Positions of the level 1 elements the seek head points at*/
+    fn seeks(seek_head: &Element) -> Vec<(u32, u64)> {
+        seek_head
+            .children
+            .iter()
+            .filter(|e| e.id == SEEK)
+            .map(|seek| {
+                let id = seek.find(SEEK_ID).unwrap();
+                (
+                    read_id(&id.data).unwrap().0,
+                    seek.find(SEEK_POSITION).unwrap().as_uint(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rewrites_block_timecodes() {
+        let video = file(
+            1,
+            VIDEO_TRACK,
+            OUTPUT_TIMECODE_SCALE,
+            vec![
+                (
+                    0,
+                    vec![
+                        simple_block(1, 0, true, 1),
+                        simple_block(1, 40, false, 2),
+                        simple_block(1, 80, false, 3),
+                    ],
+                ),
+                (
+                    1000,
+                    vec![simple_block(1, 0, true, 4), simple_block(1, 40, false, 5)],
+                ),
+            ],
+        );
+        // Audio counts in half milliseconds and starts 50 milliseconds in
+        let audio = file(
+            2,
+            2,
+            OUTPUT_TIMECODE_SCALE / 2,
+            vec![(
+                100,
+                vec![
+                    simple_block(2, 0, true, 6),
+                    simple_block(2, 100, true, 7),
+                    simple_block(2, 2000, true, 8),
+                ],
+            )],
+        );
+        let elements = mux_files("timecodes", &[video, audio]);
+        let clusters: Vec<&Element> = elements
+            .iter()
+            .map(|(_, e)| e)
+            .filter(|e| e.id == CLUSTER)
+            .collect();
+        assert_eq!(clusters.len(), 2);
+        // A new cluster starts at every video keyframe
+        assert_eq!(clusters[0].find(TIMECODE).unwrap().as_uint(), 0);
+        assert_eq!(
+            blocks(clusters[0]),
+            [(1, 0, 1), (1, 40, 2), (2, 50, 6), (1, 80, 3), (2, 100, 7)]
+        );
+        assert_eq!(clusters[1].find(TIMECODE).unwrap().as_uint(), 1000);
+        assert_eq!(blocks(clusters[1]), [(1, 0, 4), (1, 40, 5), (2, 50, 8)]);
+
+        let tracks = elements.iter().find(|(_, e)| e.id == TRACKS).unwrap();
+        let numbers: Vec<(u64, u64)> = tracks
+            .1
+            .children
+            .iter()
+            .map(|t| {
+                let number = t.find(TRACK_NUMBER).unwrap().as_uint();
+                (number, t.find(TRACK_UID).unwrap().as_uint())
+            })
+            .collect();
+        assert_eq!(numbers, [(1, 1), (2, 2)]);
+
+        // Cues point at both clusters, and the seek head at every level 1 element
+        let (cues_position, cues) = elements.iter().find(|(_, e)| e.id == CUES).unwrap();
+        let cue_points: Vec<(u64, u64)> = cues
+            .children
+            .iter()
+            .map(|point| {
+                let position = point.find(CUE_TRACK_POSITIONS).unwrap();
+                (
+                    point.find(CUE_TIME).unwrap().as_uint(),
+                    position.find(CUE_CLUSTER_POSITION).unwrap().as_uint(),
+                )
+            })
+            .collect();
+        let cluster_positions: Vec<u64> = elements
+            .iter()
+            .filter(|(_, e)| e.id == CLUSTER)
+            .map(|(position, _)| *position)
+            .collect();
+        assert_eq!(
+            cue_points,
+            [(0, cluster_positions[0]), (1000, cluster_positions[1])]
+        );
+        let position = |id: u32| elements.iter().find(|(_, e)| e.id == id).unwrap().0;
+        assert_eq!(elements[0].1.id, SEEK_HEAD);
+        assert_eq!(
+            seeks(&elements[0].1),
+            [
+                (INFO, position(INFO)),
+                (TRACKS, position(TRACKS)),
+                (CUES, *cues_position)
+            ]
+        );
+    }
+
+    #[test]
+    fn hides_unused_cues_seek_behind_void() {
+        // Audio alone has no keyframes to cue
+        let audio = file(
+            1,
+            2,
+            OUTPUT_TIMECODE_SCALE,
+            vec![(
+                0,
+                vec![simple_block(1, 0, true, 1), simple_block(1, 20, true, 2)],
+            )],
+        );
+        let elements = mux_files("void", &[audio]);
+        assert!(elements.iter().all(|(_, e)| e.id != CUES));
+        let (_, seek_head) = &elements[0];
+        assert_eq!(seek_head.id, SEEK_HEAD);
+        let position = |id: u32| elements.iter().find(|(_, e)| e.id == id).unwrap().0;
+        assert_eq!(
+            seeks(seek_head),
+            [(INFO, position(INFO)), (TRACKS, position(TRACKS))]
+        );
+        // The void takes the place of the cues seek, so the elements after it do not move
+        let void = seek_head.children.last().unwrap();
+        assert_eq!(void.id, 0xEC);
+        let cues_seek = Element::master(
+            SEEK,
+            vec![
+                Element::leaf(SEEK_ID, id_bytes(CUES)),
+                Element::leaf(SEEK_POSITION, vec![0; 8]),
+            ],
+        );
+        assert_eq!(void.size(), cues_seek.size());
+        assert_eq!(position(INFO), seek_head.size());
+    }
+}
@@ -0,0 +1,274 @@
+//! Remuxing of separately downloaded tracks into a single file.
+//!
+//! Besides the tracks of the inputs, [`Embeds`] adds subtitle tracks and cover art to the output.
+
+pub mod mkv;
+pub mod mp3;
+pub mod mp4;
+
+use crate::subtitles::{Cue, SubtitleFormat};
+use anyhow::anyhow;
+use std::path::Path;
+
+/** ISO 639-1 codes with their ISO 639-2 terminology and bibliographic codes*/
+const LANGUAGES: [(&str, &str, &str); 79] = [
+    ("af", "afr", "afr"),
+    ("am", "amh", "amh"),
+    ("ar", "ara", "ara"),
+    ("az", "aze", "aze"),
+    ("be", "bel", "bel"),
+    ("bg", "bul", "bul"),
+    ("bn", "ben", "ben"),
+    ("bs", "bos", "bos"),
+    ("ca", "cat", "cat"),
+    ("cs", "ces", "cze"),
+    ("cy", "cym", "wel"),
+    ("da", "dan", "dan"),
+    ("de", "deu", "ger"),
+    ("el", "ell", "gre"),
+    ("en", "eng", "eng"),
+    ("eo", "epo", "epo"),
+    ("es", "spa", "spa"),
+    ("et", "est", "est"),
+    ("eu", "eus", "baq"),
+    ("fa", "fas", "per"),
+    ("fi", "fin", "fin"),
+    ("fr", "fra", "fre"),
+    ("ga", "gle", "gle"),
+    ("gl", "glg", "glg"),
+    ("gu", "guj", "guj"),
+    ("he", "heb", "heb"),
+    ("hi", "hin", "hin"),
+    ("hr", "hrv", "hrv"),
+    ("hu", "hun", "hun"),
+    ("hy", "hye", "arm"),
+    ("id", "ind", "ind"),
+    ("is", "isl", "ice"),
+    ("it", "ita", "ita"),
+    ("iw", "heb", "heb"),
+    ("ja", "jpn", "jpn"),
+    ("jv", "jav", "jav"),
+    ("ka", "kat", "geo"),
+    ("kk", "kaz", "kaz"),
+    ("km", "khm", "khm"),
+    ("kn", "kan", "kan"),
+    ("ko", "kor", "kor"),
+    ("ky", "kir", "kir"),
+    ("la", "lat", "lat"),
+    ("lo", "lao", "lao"),
+    ("lt", "lit", "lit"),
+    ("lv", "lav", "lav"),
+    ("mk", "mkd", "mac"),
+    ("ml", "mal", "mal"),
+    ("mn", "mon", "mon"),
+    ("mr", "mar", "mar"),
+    ("ms", "msa", "may"),
+    ("my", "mya", "bur"),
+    ("nb", "nob", "nob"),
+    ("ne", "nep", "nep"),
+    ("nl", "nld", "dut"),
+    ("no", "nor", "nor"),
+    ("pa", "pan", "pan"),
+    ("pl", "pol", "pol"),
+    ("pt", "por", "por"),
+    ("ro", "ron", "rum"),
+    ("ru", "rus", "rus"),
+    ("si", "sin", "sin"),
+    ("sk", "slk", "slo"),
+    ("sl", "slv", "slv"),
+    ("sq", "sqi", "alb"),
+    ("sr", "srp", "srp"),
+    ("sv", "swe", "swe"),
+    ("sw", "swa", "swa"),
+    ("ta", "tam", "tam"),
+    ("te", "tel", "tel"),
+    ("th", "tha", "tha"),
+    ("tl", "tgl", "tgl"),
+    ("tr", "tur", "tur"),
+    ("uk", "ukr", "ukr"),
+    ("ur", "urd", "urd"),
+    ("uz", "uzb", "uzb"),
+    ("vi", "vie", "vie"),
+    ("zh", "zho", "chi"),
+    ("zu", "zul", "zul"),
+];
+
+/** The three letter ISO 639-2 code of a language tag like "pt-BR", the bibliographic variant
+if `bibliographic` is set, or "und" for unknown languages*/
+pub(crate) fn iso639_2(language: &str, bibliographic: bool) -> String {
+    let primary = language.split('-').next().unwrap_or("").to_lowercase();
+    if primary.len() == 3 && primary.chars().all(|c| c.is_ascii_lowercase()) {
+        return primary;
+    }
+    LANGUAGES
+        .iter()
+        .find(|(code, _, _)| *code == primary)
+        .map_or("und", |(_, terminology, bibliographic_code)| {
+            if bibliographic {
+                bibliographic_code
+            } else {
+                terminology
+            }
+        })
+        .to_owned()
+}
+
+/** A subtitle track to embed into a merged file*/
+#[derive(Debug, Clone)]
+pub struct Subtitle {
+    language: String,
+    format: SubtitleFormat,
+    cues: Vec<Cue>,
+}
+
+impl Subtitle {
+    /** A track in the language of a tag like "en" or "pt-BR". The format chooses the codec:
+    SRT is embedded as tx3g into MP4 and S_TEXT/UTF8 into Matroska, WebVTT as wvtt and
+    S_TEXT/WEBVTT. WebM only allows WebVTT*/
+    pub fn new(language: &str, format: SubtitleFormat, cues: Vec<Cue>) -> Subtitle {
+        Subtitle {
+            language: language.to_owned(),
+            format,
+            cues,
+        }
+    }
+
+    pub fn get_language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn get_format(&self) -> SubtitleFormat {
+        self.format
+    }
+
+    pub fn get_cues(&self) -> &[Cue] {
+        &self.cues
+    }
+
+    /** The cues ordered by start, each ending at the latest when the next one starts,
+    as subtitle tracks show one sample at a time. Empty cues are left out*/
+    pub(crate) fn sequential_cues(&self) -> Vec<Cue> {
+        let mut cues = self.cues.clone();
+        cues.sort_by_key(Cue::get_start);
+        let mut sequential = Vec::new();
+        for (i, cue) in cues.iter().enumerate() {
+            let end = cues
+                .get(i + 1)
+                .map_or(cue.get_end(), |next| cue.get_end().min(next.get_start()));
+            if end > cue.get_start() {
+                sequential.push(Cue::new(cue.get_start(), end, cue.get_text()));
+            }
+        }
+        sequential
+    }
+}
+
+/** What is embedded into a merged file besides the tracks of its inputs*/
+#[derive(Debug, Clone, Default)]
+pub struct Embeds {
+    pub subtitles: Vec<Subtitle>,
+    /** Cover art as a JPEG image*/
+    pub cover: Option<Vec<u8>>,
+}
+
+impl Embeds {
+    pub fn is_empty(&self) -> bool {
+        self.subtitles.is_empty() && self.cover.is_none()
+    }
+}
+
+/** A container files can be muxed into*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Container {
+    Mp4,
+    Webm,
+    Matroska,
+    /** MP3 audio, which is copied with an ID3 tag as it cannot hold other tracks*/
+    Mp3,
+}
+
+impl Container {
+    /** The container of a file by its extension*/
+    pub fn from_path(path: &Path) -> Option<Container> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        match extension.as_str() {
+            "mp4" | "m4a" | "m4v" | "mov" => Some(Container::Mp4),
+            "webm" | "weba" => Some(Container::Webm),
+            "mkv" | "mka" => Some(Container::Matroska),
+            "mp3" => Some(Container::Mp3),
+            _ => None,
+        }
+    }
+
+    pub fn supports_subtitles(&self) -> bool {
+        !matches!(self, Container::Mp3)
+    }
+
+    /** Whether cover art can be embedded, which WebM does not allow*/
+    pub fn supports_cover(&self) -> bool {
+        !matches!(self, Container::Webm)
+    }
+}
+
+/** Combines the tracks of all inputs into `output`, choosing the container by its extension*/
+pub fn merge(inputs: &[&Path], output: &Path) -> anyhow::Result<()> {
+    merge_with(inputs, output, &Embeds::default())
+}
+
+/** Combines the tracks of all inputs into `output` and adds the embeds*/
+pub fn merge_with(inputs: &[&Path], output: &Path, embeds: &Embeds) -> anyhow::Result<()> {
+    let container = Container::from_path(output).ok_or(anyhow!(
+        "Cannot mux into \"{}\", its extension is not supported",
+        output.display()
+    ))?;
+    if !embeds.subtitles.is_empty() && !container.supports_subtitles() {
+        return Err(anyhow!("{:?} files cannot hold subtitles", container));
+    }
+    if embeds.cover.is_some() && !container.supports_cover() {
+        return Err(anyhow!("{:?} files cannot hold cover art", container));
+    }
+    let subtitles = embeds.subtitles.clone();
+    let cover = embeds.cover.as_deref();
+    match container {
+        Container::Mp4 => mp4::mux_with(
+            inputs,
+            output,
+            mp4::Extras {
+                udta: cover.map(mp4::cover_udta),
+                subtitles,
+            },
+        ),
+        Container::Webm | Container::Matroska => mkv::mux_with(
+            inputs,
+            output,
+            container == Container::Webm,
+            mkv::Extras {
+                attachments: cover.map(mkv::cover_attachments),
+                subtitles,
+            },
+        ),
+        Container::Mp3 => mp3::copy_with_cover(inputs, output, cover),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_language_tags() {
+        assert_eq!(iso639_2("en", false), "eng");
+        assert_eq!(iso639_2("en-GB", true), "eng");
+        assert_eq!(iso639_2("zh-Hans", false), "zho");
+        assert_eq!(iso639_2("zh-Hans", true), "chi");
+        assert_eq!(iso639_2("FR", true), "fre");
+        // Three letter codes are kept, unknown ones are undetermined
+        assert_eq!(iso639_2("fil", false), "fil");
+        assert_eq!(iso639_2("xx", false), "und");
+        assert_eq!(iso639_2("", true), "und");
+    }
+}
@@ -0,0 +1,252 @@
+//! MP3 files, whose metadata is an ID3v2 tag in front of the audio.
+//!
+//! Cover art is written as an APIC frame. The other frames of an ID3v2.3 or ID3v2.4 tag are
+//! kept, older tags and those with unsynchronisation or an extended header are replaced.
+
+use anyhow::anyhow;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/** Size of the tag and frame headers*/
+const HEADER_SIZE: usize = 10;
+
+/** Picture type of a front cover*/
+const FRONT_COVER: u8 = 3;
+
+/** Sizes in ID3v2 headers keep the top bit of every byte clear*/
+fn syncsafe(value: u32) -> [u8; 4] {
+    [
+        (value >> 21 & 0x7F) as u8,
+        (value >> 14 & 0x7F) as u8,
+        (value >> 7 & 0x7F) as u8,
+        (value & 0x7F) as u8,
+    ]
+}
+
+fn read_syncsafe(data: &[u8]) -> u32 {
+    data[..4]
+        .iter()
+        .fold(0, |value, b| value << 7 | (*b & 0x7F) as u32)
+}
+
+/** The major version and the frames to keep of the tag at the start of a file,
+and the offset of the audio following it*/
+fn read_tag(file: &mut File) -> anyhow::Result<(u8, Vec<u8>, u64)> {
+    let mut header = [0; HEADER_SIZE];
+    let read = file.read(&mut header)?;
+    if read < HEADER_SIZE || &header[..3] != b"ID3" {
+        return Ok((3, Vec::new(), 0));
+    }
+    let (version, flags) = (header[3], header[5]);
+    let size = read_syncsafe(&header[6..]) as usize;
+    let footer = if version == 4 && flags & 0x10 != 0 {
+        HEADER_SIZE
+    } else {
+        0
+    };
+    let audio = (HEADER_SIZE + size + footer) as u64;
+    if !matches!(version, 3 | 4) || flags & 0xC0 != 0 {
+        return Ok((3, Vec::new(), audio));
+    }
+
+    let mut body = vec![0; size];
+    file.read_exact(&mut body)?;
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    // Frames end at the padding, whose bytes are zero
+    while offset + HEADER_SIZE <= body.len() && body[offset] != 0 {
+        let frame = &body[offset..];
+        let size = if version == 4 {
+            read_syncsafe(&frame[4..])
+        } else {
+            u32::from_be_bytes(frame[4..8].try_into()?)
+        } as usize;
+        let end = (HEADER_SIZE + size).min(frame.len());
+        if &frame[..4] != b"APIC" {
+            frames.extend_from_slice(&frame[..end]);
+        }
+        offset += end;
+    }
+    Ok((version, frames, audio))
+}
+
+/** Whether the audio starts with the sync word of an MPEG audio frame*/
+fn starts_with_frame(file: &mut File, offset: u64) -> anyhow::Result<bool> {
+    let mut sync = [0; 2];
+    file.seek(SeekFrom::Start(offset))?;
+    let read = file.read(&mut sync)?;
+    Ok(read == sync.len() && sync[0] == 0xFF && sync[1] & 0xE0 == 0xE0)
+}
+
+/** An APIC frame holding a JPEG front cover*/
+fn cover_frame(version: u8, jpeg: &[u8]) -> Vec<u8> {
+    // Latin-1 text, the mime type, the picture type and an empty description
+    let mut body = vec![0];
+    body.extend_from_slice(b"image/jpeg\0");
+    body.push(FRONT_COVER);
+    body.push(0);
+    body.extend_from_slice(jpeg);
+
+    let mut frame = b"APIC".to_vec();
+    if version == 4 {
+        frame.extend_from_slice(&syncsafe(body.len() as u32));
+    } else {
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    }
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(&body);
+    frame
+}
+
+/** Copies the single MP3 input to `output`, adding the cover art to its tag. Other audio, like
+the M4A and WebM streams YouTube serves, is refused*/
+pub fn copy_with_cover(
+    inputs: &[&Path],
+    output: &Path,
+    cover: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let input = match inputs {
+        [input] => input,
+        _ => return Err(anyhow!("An MP3 file holds a single audio track")),
+    };
+    let mut file = File::open(input)?;
+    let (version, mut frames, audio) = read_tag(&mut file)?;
+    if !starts_with_frame(&mut file, audio)? {
+        return Err(anyhow!("\"{}\" is not an MP3 file", input.display()));
+    }
+    let mut out = BufWriter::new(File::create(output)?);
+    if let Some(jpeg) = cover {
+        frames.extend_from_slice(&cover_frame(version, jpeg));
+    }
+    if !frames.is_empty() {
+        out.write_all(b"ID3")?;
+        out.write_all(&[version, 0, 0])?;
+        out.write_all(&syncsafe(frames.len() as u32))?;
+        out.write_all(&frames)?;
+    }
+    file.seek(SeekFrom::Start(audio))?;
+    std::io::copy(&mut BufReader::new(file), &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** The first bytes of an MPEG-1 Layer III frame*/
+    const AUDIO: [u8; 4] = [0xFF, 0xFB, 0x90, 0x64];
+
+    fn frame(version: u8, id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        if version == 4 {
+            frame.extend_from_slice(&syncsafe(body.len() as u32));
+        } else {
+            frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        }
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    /** A file with a tag of the frames followed by some padding and the audio*/
+    fn file(version: u8, flags: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = frames.concat();
+        body.extend_from_slice(&[0; 16]);
+        let mut data = vec![b'I', b'D', b'3', version, 0, flags];
+        data.extend_from_slice(&syncsafe(body.len() as u32));
+        data.extend_from_slice(&body);
+        data.extend_from_slice(&AUDIO);
+        data
+    }
+
+    /** Runs `copy_with_cover` on the input in a temporary directory and returns the output*/
+    fn copy(name: &str, input: &[u8], cover: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("yt_download-{}-{}.in", std::process::id(), name));
+        let output_path = input_path.with_extension("mp3");
+        std::fs::write(&input_path, input)?;
+        let result = copy_with_cover(&[&input_path], &output_path, cover)
+            .and_then(|_| Ok(std::fs::read(&output_path)?));
+        let _ = std::fs::remove_file(&input_path);
+        let _ = std::fs::remove_file(&output_path);
+        result
+    }
+
+    /** A title longer than 127 bytes, whose size differs between plain and syncsafe*/
+    fn title() -> Vec<u8> {
+        let mut title = vec![3];
+        title.extend_from_slice(&[b'a'; 199]);
+        title
+    }
+
+    fn cover(version: u8) -> Vec<u8> {
+        let mut body = vec![0];
+        body.extend_from_slice(b"image/jpeg\0");
+        body.extend_from_slice(&[FRONT_COVER, 0]);
+        body.extend_from_slice(&[0xFF; 300]);
+        frame(version, b"APIC", &body)
+    }
+
+    #[test]
+    fn keeps_v3_frames_and_replaces_cover() {
+        let old = frame(3, b"APIC", b"\0image/png\0\x03\0old");
+        let input = file(3, 0, &[frame(3, b"TIT2", &title()), old]);
+        let output = copy("v3", &input, Some(&[0xFF; 300])).unwrap();
+        let expected = [frame(3, b"TIT2", &title()), cover(3)].concat();
+        assert_eq!(&output[..6], b"ID3\x03\0\0");
+        assert_eq!(read_syncsafe(&output[6..]) as usize, expected.len());
+        assert_eq!(&output[10..10 + expected.len()], &expected[..]);
+        assert_eq!(&output[10 + expected.len()..], &AUDIO);
+    }
+
+    #[test]
+    fn keeps_v4_frames_with_syncsafe_sizes() {
+        let old = frame(4, b"APIC", b"\0image/png\0\x03\0old");
+        let input = file(4, 0, &[old, frame(4, b"TIT2", &title())]);
+        let output = copy("v4", &input, Some(&[0xFF; 300])).unwrap();
+        let expected = [frame(4, b"TIT2", &title()), cover(4)].concat();
+        assert_eq!(&output[..6], b"ID3\x04\0\0");
+        assert_eq!(&output[10..10 + expected.len()], &expected[..]);
+        assert_eq!(&output[10 + expected.len()..], &AUDIO);
+    }
+
+    #[test]
+    fn replaces_unsynchronised_and_extended_tags() {
+        for flags in [0x80, 0x40] {
+            let input = file(3, flags, &[frame(3, b"TIT2", &title())]);
+            let output = copy("flags", &input, Some(&[0xFF; 300])).unwrap();
+            let expected = cover(3);
+            assert_eq!(&output[..6], b"ID3\x03\0\0");
+            assert_eq!(&output[10..10 + expected.len()], &expected[..]);
+            assert_eq!(&output[10 + expected.len()..], &AUDIO);
+        }
+        let input = file(2, 0, &[b"TT2\0\0\x02\0a".to_vec()]);
+        let output = copy("v2", &input, Some(&[0xFF; 300])).unwrap();
+        assert_eq!(&output[10..], [cover(3), AUDIO.to_vec()].concat());
+    }
+
+    #[test]
+    fn tags_untagged_audio() {
+        let output = copy("untagged", &AUDIO, Some(&[0xFF; 300])).unwrap();
+        let mut expected = b"ID3\x03\0\0".to_vec();
+        expected.extend_from_slice(&syncsafe(cover(3).len() as u32));
+        expected.extend_from_slice(&cover(3));
+        expected.extend_from_slice(&AUDIO);
+        assert_eq!(output, expected);
+        // Without cover art there is nothing to tag
+        assert_eq!(copy("plain", &AUDIO, None).unwrap(), AUDIO);
+    }
+
+    #[test]
+    fn refuses_other_audio() {
+        let m4a = b"\0\0\0\x18ftypM4A \0\0\0\0M4A mp42".to_vec();
+        let error = copy("m4a", &m4a, None).unwrap_err().to_string();
+        assert!(error.ends_with("is not an MP3 file"));
+        let mut tagged = file(3, 0, &[frame(3, b"TIT2", &title())]);
+        tagged.truncate(tagged.len() - AUDIO.len());
+        tagged.extend_from_slice(b"\x1AE\xDF\xA3");
+        assert!(copy("webm", &tagged, None).is_err());
+    }
+}
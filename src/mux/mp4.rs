@@ -0,0 +1,1453 @@
+//! ISO BMFF (MP4) remuxer combining the tracks of several files into one.
+//!
+//! Fragmented inputs, like YouTube's DASH streams, produce a fragmented output whose
+//! fragments are interleaved by decode time. Plain inputs keep their sample tables and
+//! only have their chunk offsets moved.
+//! Subtitles are added as tx3g or wvtt text tracks in milliseconds, stored in one fragment
+//! or chunk per track.
+
+use super::{iso639_2, Subtitle};
+use crate::subtitles::{escape_vtt, SubtitleFormat};
+use anyhow::anyhow;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/** Boxes whose payload is a sequence of boxes*/
+const CONTAINERS: [&[u8; 4]; 11] = [
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"mvex", b"edts", b"dinf", b"moof", b"traf",
+    b"udta",
+];
+
+/** An ISO BMFF box, either a container of boxes or a leaf with raw payload*/
+#[derive(Debug, Clone)]
+pub(crate) struct Atom {
+    kind: [u8; 4],
+    payload: Vec<u8>,
+    children: Vec<Atom>,
+}
+
+impl Atom {
+    pub(crate) fn leaf(kind: &[u8; 4], payload: Vec<u8>) -> Atom {
+        Atom {
+            kind: *kind,
+            payload,
+            children: Vec::new(),
+        }
+    }
+
+    pub(crate) fn container(kind: &[u8; 4], children: Vec<Atom>) -> Atom {
+        Atom {
+            kind: *kind,
+            payload: Vec::new(),
+            children,
+        }
+    }
+
+    fn is_container(&self) -> bool {
+        CONTAINERS.contains(&&self.kind)
+    }
+
+    fn parse_all(mut data: &[u8]) -> anyhow::Result<Vec<Atom>> {
+        let mut atoms = Vec::new();
+        while data.len() >= 8 {
+            let (header, size) = read_header(data, data.len() as u64)?;
+            let size = size as usize;
+            if size < header || size > data.len() {
+                return Err(anyhow!("Malformed MP4 box"));
+            }
+            let mut kind = [0; 4];
+            kind.copy_from_slice(&data[4..8]);
+            let body = &data[header..size];
+            let atom = if CONTAINERS.contains(&&kind) {
+                Atom::container(&kind, Atom::parse_all(body)?)
+            } else {
+                Atom::leaf(&kind, body.to_vec())
+            };
+            atoms.push(atom);
+            data = &data[size..];
+        }
+        Ok(atoms)
+    }
+
+    fn find(&self, kind: &[u8; 4]) -> Option<&Atom> {
+        self.children.iter().find(|a| &a.kind == kind)
+    }
+
+    fn find_mut(&mut self, kind: &[u8; 4]) -> Option<&mut Atom> {
+        self.children.iter_mut().find(|a| &a.kind == kind)
+    }
+
+    /** Follows a path of box types like ["mdia", "mdhd"]*/
+    fn path(&self, path: &[&[u8; 4]]) -> Option<&Atom> {
+        path.iter().try_fold(self, |atom, kind| atom.find(kind))
+    }
+
+    fn path_mut(&mut self, path: &[&[u8; 4]]) -> Option<&mut Atom> {
+        path.iter().try_fold(self, |atom, kind| atom.find_mut(kind))
+    }
+
+    fn body_size(&self) -> u64 {
+        if self.is_container() || !self.children.is_empty() {
+            self.children.iter().map(Atom::size).sum::<u64>() + self.payload.len() as u64
+        } else {
+            self.payload.len() as u64
+        }
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        let body = self.body_size();
+        if body + 8 > u32::MAX as u64 {
+            body + 16
+        } else {
+            body + 8
+        }
+    }
+
+    pub(crate) fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let size = self.size();
+        if size > u32::MAX as u64 {
+            out.write_all(&1u32.to_be_bytes())?;
+            out.write_all(&self.kind)?;
+            out.write_all(&size.to_be_bytes())?;
+        } else {
+            out.write_all(&(size as u32).to_be_bytes())?;
+            out.write_all(&self.kind)?;
+        }
+        // Full box containers like meta keep their version and flags in the payload
+        out.write_all(&self.payload)?;
+        for child in &self.children {
+            child.write(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_header(data: &[u8], available: u64) -> anyhow::Result<(usize, u64)> {
+    let size = u32::from_be_bytes(data[0..4].try_into()?) as u64;
+    match size {
+        0 => Ok((8, available)),
+        1 => {
+            if data.len() < 16 {
+                return Err(anyhow!("Malformed MP4 box"));
+            }
+            Ok((16, u64::from_be_bytes(data[8..16].try_into()?)))
+        }
+        size => Ok((8, size)),
+    }
+}
+
+pub(crate) fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn set_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_be_bytes());
+}
+
+/** Timescale and duration of an mvhd or mdhd box*/
+fn header_timing(atom: &Atom) -> (u32, u64) {
+    let p = &atom.payload;
+    if p[0] == 1 {
+        (be_u32(p, 20), be_u64(p, 24))
+    } else {
+        (be_u32(p, 12), be_u32(p, 16) as u64)
+    }
+}
+
+fn set_header_duration(atom: &mut Atom, duration: u64) {
+    let p = &mut atom.payload;
+    if p[0] == 1 {
+        set_u64(p, 24, duration);
+    } else {
+        set_u32(p, 16, duration.min(u32::MAX as u64) as u32);
+    }
+}
+
+fn rescale(value: u64, from: u32, to: u32) -> u64 {
+    if from == 0 {
+        return value;
+    }
+    (value as u128 * to as u128 / from as u128) as u64
+}
+
+/** A top level box of an input file*/
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    kind: [u8; 4],
+    offset: u64,
+    size: u64,
+}
+
+fn scan(file: &mut File) -> anyhow::Result<Vec<Entry>> {
+    let length = file.metadata()?.len();
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= length {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0; 16];
+        let read = file.read(&mut header)?;
+        let (_, size) = read_header(&header[..read], length - offset)?;
+        if size < 8 || offset + size > length {
+            return Err(anyhow!("Malformed MP4 box at offset {}", offset));
+        }
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&header[4..8]);
+        entries.push(Entry { kind, offset, size });
+        offset += size;
+    }
+    Ok(entries)
+}
+
+fn read_entry(file: &mut File, entry: &Entry) -> anyhow::Result<Atom> {
+    file.seek(SeekFrom::Start(entry.offset))?;
+    let mut data = vec![0; entry.size as usize];
+    file.read_exact(&mut data)?;
+    Atom::parse_all(&data)?
+        .pop()
+        .ok_or(anyhow!("Malformed MP4 box"))
+}
+
+/** A track taken over from an input, with the id it gets in the output*/
+struct Track {
+    trak: Atom,
+    trex: Option<Atom>,
+    source_id: u32,
+    id: u32,
+    timescale: u32,
+    duration: u64,
+    /** Durations and data of the samples of a track built in memory, empty for input tracks*/
+    samples: Vec<(u32, Vec<u8>)>,
+}
+
+/** Where the media data of a fragment comes from*/
+enum FragmentData {
+    /** The bytes following the moof in an input up to and including its mdat*/
+    Input {
+        index: usize,
+        offset: u64,
+        size: u64,
+    },
+    /** An mdat built in memory*/
+    Memory(Atom),
+}
+
+/** A moof box with its media data*/
+struct Fragment {
+    moof: Atom,
+    data: FragmentData,
+    time: f64,
+}
+
+struct Input {
+    file: File,
+    entries: Vec<Entry>,
+    moov: Atom,
+    movie_timescale: u32,
+}
+
+impl Input {
+    fn open(path: &Path) -> anyhow::Result<Input> {
+        let mut file = File::open(path)?;
+        let entries = scan(&mut file)?;
+        let moov = entries
+            .iter()
+            .find(|e| &e.kind == b"moov")
+            .ok_or(anyhow!("\"{}\" has no moov box", path.display()))?;
+        let moov = read_entry(&mut file, &moov.clone())?;
+        let movie_timescale = moov
+            .find(b"mvhd")
+            .map(|mvhd| header_timing(mvhd).0)
+            .ok_or(anyhow!("\"{}\" has no mvhd box", path.display()))?;
+        Ok(Input {
+            file,
+            entries,
+            moov,
+            movie_timescale,
+        })
+    }
+
+    fn is_fragmented(&self) -> bool {
+        self.moov.find(b"mvex").is_some() || self.entries.iter().any(|e| &e.kind == b"moof")
+    }
+}
+
+fn track_id(trak: &Atom) -> u32 {
+    let tkhd = &trak.find(b"tkhd").unwrap().payload;
+    if tkhd[0] == 1 {
+        be_u32(tkhd, 20)
+    } else {
+        be_u32(tkhd, 12)
+    }
+}
+
+/** Sets the id of a track and its duration in the movie timescale*/
+fn patch_tkhd(trak: &mut Atom, id: u32, duration: u64) {
+    if let Some(tkhd) = trak.find_mut(b"tkhd") {
+        let p = &mut tkhd.payload;
+        if p[0] == 1 {
+            set_u32(p, 20, id);
+            set_u64(p, 28, duration);
+        } else {
+            set_u32(p, 12, id);
+            set_u32(p, 20, duration.min(u32::MAX as u64) as u32);
+        }
+    }
+}
+
+/** Converts edit list segment durations from one movie timescale to another*/
+fn rescale_edits(trak: &mut Atom, from: u32, to: u32) {
+    if let Some(elst) = trak.path_mut(&[b"edts", b"elst"]) {
+        let p = &mut elst.payload;
+        let version = p[0];
+        let count = be_u32(p, 4) as usize;
+        let entry = if version == 1 { 20 } else { 12 };
+        for i in 0..count {
+            let offset = 8 + i * entry;
+            if offset + entry > p.len() {
+                break;
+            }
+            if version == 1 {
+                let duration = be_u64(p, offset);
+                set_u64(p, offset, rescale(duration, from, to));
+            } else {
+                let duration = be_u32(p, offset) as u64;
+                set_u32(p, offset, rescale(duration, from, to) as u32);
+            }
+        }
+    }
+}
+
+/** Decode time and summed sample durations of a moof's track fragment*/
+fn fragment_timing(moof: &Atom, trex: Option<&Atom>) -> (u64, u64) {
+    let traf = match moof.find(b"traf") {
+        Some(traf) => traf,
+        None => return (0, 0),
+    };
+    let decode_time = traf
+        .find(b"tfdt")
+        .map(|tfdt| {
+            if tfdt.payload[0] == 1 {
+                be_u64(&tfdt.payload, 4)
+            } else {
+                be_u32(&tfdt.payload, 4) as u64
+            }
+        })
+        .unwrap_or(0);
+
+    let mut default_duration = trex.map(|t| be_u32(&t.payload, 12)).unwrap_or(0);
+    if let Some(tfhd) = traf.find(b"tfhd") {
+        let p = &tfhd.payload;
+        let flags = be_u32(p, 0) & 0xFFFFFF;
+        let mut offset = 8;
+        if flags & 0x1 != 0 {
+            offset += 8;
+        }
+        if flags & 0x2 != 0 {
+            offset += 4;
+        }
+        if flags & 0x8 != 0 {
+            default_duration = be_u32(p, offset);
+        }
+    }
+
+    let mut duration = 0;
+    for trun in traf.children.iter().filter(|a| &a.kind == b"trun") {
+        let p = &trun.payload;
+        let flags = be_u32(p, 0) & 0xFFFFFF;
+        let count = be_u32(p, 4) as usize;
+        let mut offset = 8;
+        if flags & 0x1 != 0 {
+            offset += 4;
+        }
+        if flags & 0x4 != 0 {
+            offset += 4;
+        }
+        if flags & 0x100 == 0 {
+            duration += default_duration as u64 * count as u64;
+            continue;
+        }
+        let sample = [0x100, 0x200, 0x400, 0x800]
+            .iter()
+            .filter(|f| flags & **f != 0)
+            .count()
+            * 4;
+        for i in 0..count {
+            let at = offset + i * sample;
+            if at + 4 > p.len() {
+                break;
+            }
+            duration += be_u32(p, at) as u64;
+        }
+    }
+    (decode_time, duration)
+}
+
+/** Renumbers the track of a moof and moves an explicit base data offset by `shift`*/
+fn patch_moof(moof: &mut Atom, sequence: u32, track_ids: &[(u32, u32)], shift: i64) {
+    if let Some(mfhd) = moof.find_mut(b"mfhd") {
+        set_u32(&mut mfhd.payload, 4, sequence);
+    }
+    for traf in moof.children.iter_mut().filter(|a| &a.kind == b"traf") {
+        if let Some(tfhd) = traf.find_mut(b"tfhd") {
+            let p = &mut tfhd.payload;
+            let source = be_u32(p, 4);
+            if let Some((_, id)) = track_ids.iter().find(|(s, _)| *s == source) {
+                set_u32(p, 4, *id);
+            }
+            if be_u32(p, 0) & 0x1 != 0 {
+                let base = be_u64(p, 8) as i64 + shift;
+                set_u64(p, 8, base as u64);
+            }
+        }
+    }
+}
+
+fn ftyp() -> Atom {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(b"isom");
+    payload.extend_from_slice(&512u32.to_be_bytes());
+    for brand in [b"isom", b"iso6", b"mp41"] {
+        payload.extend_from_slice(brand);
+    }
+    Atom::leaf(b"ftyp", payload)
+}
+
+/** Text tracks count in milliseconds like the cues*/
+const TEXT_TIMESCALE: u32 = 1000;
+
+/** Durations and data of the samples of a subtitle track. Text tracks cover their whole
+duration, so the gaps between cues are filled with empty samples*/
+fn text_samples(subtitle: &Subtitle) -> Vec<(u32, Vec<u8>)> {
+    let vtt = subtitle.get_format() == SubtitleFormat::Vtt;
+    let sample = |text: Option<&str>| -> Vec<u8> {
+        match (vtt, text) {
+            // A tx3g sample is the length of the text followed by it
+            (false, text) => {
+                let text = text.unwrap_or("").as_bytes();
+                let text = &text[..text.len().min(u16::MAX as usize)];
+                let mut data = (text.len() as u16).to_be_bytes().to_vec();
+                data.extend_from_slice(text);
+                data
+            }
+            (true, text) => {
+                let atom = match text {
+                    Some(text) => Atom::container(
+                        b"vttc",
+                        vec![Atom::leaf(b"payl", escape_vtt(text).into_bytes())],
+                    ),
+                    None => Atom::leaf(b"vtte", Vec::new()),
+                };
+                let mut data = Vec::new();
+                atom.write(&mut data).unwrap();
+                data
+            }
+        }
+    };
+    let mut samples = Vec::new();
+    let mut time = 0;
+    for cue in subtitle.sequential_cues() {
+        if cue.get_start() > time {
+            samples.push(((cue.get_start() - time) as u32, sample(None)));
+        }
+        let duration = cue.get_end() - cue.get_start();
+        samples.push((duration as u32, sample(Some(cue.get_text()))));
+        time = cue.get_end();
+    }
+    samples
+}
+
+/** Full box payload starting with version 0 and the given flags*/
+fn full_box(flags: u32) -> Vec<u8> {
+    flags.to_be_bytes().to_vec()
+}
+
+/** Sample tables of samples stored in one chunk at `chunk`, or empty ones if the samples
+are in fragments*/
+fn sample_tables(samples: &[(u32, Vec<u8>)], chunk: Option<u64>) -> Vec<Atom> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for (duration, _) in samples {
+        match runs.last_mut() {
+            Some((count, last)) if last == duration => *count += 1,
+            _ => runs.push((1, *duration)),
+        }
+    }
+    let mut stts = full_box(0);
+    stts.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+    for (count, duration) in runs {
+        stts.extend_from_slice(&count.to_be_bytes());
+        stts.extend_from_slice(&duration.to_be_bytes());
+    }
+
+    let mut stsz = full_box(0);
+    stsz.extend_from_slice(&0u32.to_be_bytes());
+    stsz.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    for (_, data) in samples {
+        stsz.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    }
+
+    let mut stsc = full_box(0);
+    let mut stco = full_box(0);
+    let mut stco_kind = b"stco";
+    match chunk {
+        Some(offset) if !samples.is_empty() => {
+            for value in [1, 1, samples.len() as u32, 1] {
+                stsc.extend_from_slice(&value.to_be_bytes());
+            }
+            stco.extend_from_slice(&1u32.to_be_bytes());
+            if offset > u32::MAX as u64 {
+                stco_kind = b"co64";
+                stco.extend_from_slice(&offset.to_be_bytes());
+            } else {
+                stco.extend_from_slice(&(offset as u32).to_be_bytes());
+            }
+        }
+        _ => {
+            stsc.extend_from_slice(&0u32.to_be_bytes());
+            stco.extend_from_slice(&0u32.to_be_bytes());
+        }
+    }
+    vec![
+        Atom::leaf(b"stts", stts),
+        Atom::leaf(b"stsc", stsc),
+        Atom::leaf(b"stsz", stsz),
+        Atom::leaf(stco_kind, stco),
+    ]
+}
+
+/** The trak of a subtitle track with empty sample tables, which is enabled if `first`.
+SRT cues become a 3GPP tx3g track, WebVTT ones a wvtt track*/
+fn text_trak(subtitle: &Subtitle, first: bool) -> Atom {
+    let vtt = subtitle.get_format() == SubtitleFormat::Vtt;
+
+    let mut tkhd = full_box(if first { 0x3 } else { 0x2 });
+    tkhd.extend_from_slice(&[0; 20]);
+    tkhd.extend_from_slice(&[0; 8]);
+    // Layer, and the alternate group all subtitle tracks share so only one is shown
+    tkhd.extend_from_slice(&0u16.to_be_bytes());
+    tkhd.extend_from_slice(&2u16.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 4]);
+    for value in [0x10000u32, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000] {
+        tkhd.extend_from_slice(&value.to_be_bytes());
+    }
+    tkhd.extend_from_slice(&[0; 8]);
+
+    // Languages are packed as three letters of five bits
+    let language = iso639_2(subtitle.get_language(), false)
+        .bytes()
+        .fold(0u16, |packed, c| packed << 5 | (c - 0x60) as u16 & 0x1F);
+    let mut mdhd = full_box(0);
+    mdhd.extend_from_slice(&[0; 8]);
+    mdhd.extend_from_slice(&TEXT_TIMESCALE.to_be_bytes());
+    mdhd.extend_from_slice(&[0; 4]);
+    mdhd.extend_from_slice(&language.to_be_bytes());
+    mdhd.extend_from_slice(&[0; 2]);
+
+    let mut hdlr = full_box(0);
+    hdlr.extend_from_slice(&[0; 4]);
+    hdlr.extend_from_slice(if vtt { b"text" } else { b"sbtl" });
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(b"SubtitleHandler\0");
+
+    // The extended language box keeps the full tag, like "pt-BR"
+    let mut elng = full_box(0);
+    elng.extend_from_slice(subtitle.get_language().as_bytes());
+    elng.push(0);
+
+    let mut dref = full_box(0);
+    dref.extend_from_slice(&1u32.to_be_bytes());
+    Atom::leaf(b"url ", full_box(1)).write(&mut dref).unwrap();
+
+    let mut entry = vec![0; 6];
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    let entry = if vtt {
+        Atom {
+            kind: *b"wvtt",
+            payload: entry,
+            children: vec![Atom::leaf(b"vttC", b"WEBVTT".to_vec())],
+        }
+    } else {
+        // No display flags, centered at the bottom on a transparent background, no text box
+        entry.extend_from_slice(&[0, 0, 0, 0, 0x01, 0xFF, 0, 0, 0, 0]);
+        entry.extend_from_slice(&[0; 8]);
+        // Default style: the whole text with font 1 in size 18 and opaque white
+        entry.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 18, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let mut ftab = 1u16.to_be_bytes().to_vec();
+        ftab.extend_from_slice(&1u16.to_be_bytes());
+        ftab.push(5);
+        ftab.extend_from_slice(b"Serif");
+        Atom {
+            kind: *b"tx3g",
+            payload: entry,
+            children: vec![Atom::leaf(b"ftab", ftab)],
+        }
+    };
+    let mut stsd = full_box(0);
+    stsd.extend_from_slice(&1u32.to_be_bytes());
+    entry.write(&mut stsd).unwrap();
+
+    let mut stbl = vec![Atom::leaf(b"stsd", stsd)];
+    stbl.extend(sample_tables(&[], None));
+    Atom::container(
+        b"trak",
+        vec![
+            Atom::leaf(b"tkhd", tkhd),
+            Atom::container(
+                b"mdia",
+                vec![
+                    Atom::leaf(b"mdhd", mdhd),
+                    Atom::leaf(b"hdlr", hdlr),
+                    Atom::leaf(b"elng", elng),
+                    Atom::container(
+                        b"minf",
+                        vec![
+                            Atom::leaf(b"nmhd", full_box(0)),
+                            Atom::container(b"dinf", vec![Atom::leaf(b"dref", dref)]),
+                            Atom::container(b"stbl", stbl),
+                        ],
+                    ),
+                ],
+            ),
+        ],
+    )
+}
+
+/** A fragment holding all samples of a text track, whose data follows the moof*/
+fn text_fragment(id: u32, samples: &[(u32, Vec<u8>)]) -> Fragment {
+    // Sample data offsets are relative to the moof
+    let mut tfhd = full_box(0x020000);
+    tfhd.extend_from_slice(&id.to_be_bytes());
+    let mut tfdt = vec![1, 0, 0, 0];
+    tfdt.extend_from_slice(&0u64.to_be_bytes());
+    // Data offset, sample durations and sample sizes are present
+    let mut trun = full_box(0x000301);
+    trun.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    trun.extend_from_slice(&0u32.to_be_bytes());
+    for (duration, data) in samples {
+        trun.extend_from_slice(&duration.to_be_bytes());
+        trun.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    }
+    let mut moof = Atom::container(
+        b"moof",
+        vec![
+            Atom::leaf(b"mfhd", vec![0; 8]),
+            Atom::container(
+                b"traf",
+                vec![
+                    Atom::leaf(b"tfhd", tfhd),
+                    Atom::leaf(b"tfdt", tfdt),
+                    Atom::leaf(b"trun", trun),
+                ],
+            ),
+        ],
+    );
+    let mdat = Atom::leaf(
+        b"mdat",
+        samples.iter().flat_map(|(_, s)| s.clone()).collect(),
+    );
+    let data_offset = moof.size() + mdat.size() - mdat.body_size();
+    if let Some(trun) = moof.path_mut(&[b"traf", b"trun"]) {
+        set_u32(&mut trun.payload, 8, data_offset as u32);
+    }
+    Fragment {
+        moof,
+        data: FragmentData::Memory(mdat),
+        time: 0.0,
+    }
+}
+
+fn copy_range(file: &mut File, offset: u64, size: u64, out: &mut impl Write) -> anyhow::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    let copied = std::io::copy(&mut file.take(size), out)?;
+    if copied != size {
+        return Err(anyhow!("Unexpected end of MP4 input"));
+    }
+    Ok(())
+}
+
+/** iTunes metadata holding cover art, as moov/udta/meta/ilst/covr*/
+pub(crate) fn cover_udta(jpeg: &[u8]) -> Atom {
+    let mut hdlr = full_box(0);
+    hdlr.extend_from_slice(&[0; 4]);
+    hdlr.extend_from_slice(b"mdir");
+    hdlr.extend_from_slice(b"appl");
+    hdlr.extend_from_slice(&[0; 9]);
+    // Well known type 13 marks JPEG data, followed by an empty locale
+    let mut data = 13u32.to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(jpeg);
+    let ilst = Atom::container(
+        b"ilst",
+        vec![Atom::container(b"covr", vec![Atom::leaf(b"data", data)])],
+    );
+    let meta = Atom {
+        kind: *b"meta",
+        payload: full_box(0),
+        children: vec![Atom::leaf(b"hdlr", hdlr), ilst],
+    };
+    Atom::container(b"udta", vec![meta])
+}
+
+/** Additional boxes written into the output's moov, and subtitles added as text tracks*/
+#[derive(Default)]
+pub(crate) struct Extras {
+    pub(crate) udta: Option<Atom>,
+    pub(crate) subtitles: Vec<Subtitle>,
+}
+
+/** Combines the tracks of all inputs into one MP4 file*/
+pub fn mux(inputs: &[&Path], output: &Path) -> anyhow::Result<()> {
+    mux_with(inputs, output, Extras::default())
+}
+
+pub(crate) fn mux_with(inputs: &[&Path], output: &Path, extras: Extras) -> anyhow::Result<()> {
+    let mut inputs = inputs
+        .iter()
+        .map(|p| Input::open(p))
+        .collect::<anyhow::Result<Vec<Input>>>()?;
+    let fragmented = inputs[0].is_fragmented();
+    if inputs.iter().any(|i| i.is_fragmented() != fragmented) {
+        return Err(anyhow!(
+            "Cannot mux fragmented and plain MP4 files together"
+        ));
+    }
+    let movie_timescale = inputs[0].movie_timescale;
+
+    let mut tracks = Vec::new();
+    let mut fragments = Vec::new();
+    for (index, input) in inputs.iter_mut().enumerate() {
+        let trex: Vec<Atom> = input
+            .moov
+            .find(b"mvex")
+            .map(|mvex| {
+                mvex.children
+                    .iter()
+                    .filter(|a| &a.kind == b"trex")
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let first = tracks.len();
+        for trak in input.moov.children.iter().filter(|a| &a.kind == b"trak") {
+            let source_id = track_id(trak);
+            let (timescale, duration) = trak
+                .path(&[b"mdia", b"mdhd"])
+                .map(header_timing)
+                .ok_or(anyhow!("Track {} has no mdhd box", source_id))?;
+            let mut trak = trak.clone();
+            rescale_edits(&mut trak, input.movie_timescale, movie_timescale);
+            tracks.push(Track {
+                trak,
+                trex: trex
+                    .iter()
+                    .find(|t| be_u32(&t.payload, 4) == source_id)
+                    .cloned(),
+                source_id,
+                id: tracks.len() as u32 + 1,
+                timescale,
+                duration: if fragmented { 0 } else { duration },
+                samples: Vec::new(),
+            });
+        }
+
+        if !fragmented {
+            continue;
+        }
+        let entries = input.entries.clone();
+        let mut i = 0;
+        while i < entries.len() {
+            if &entries[i].kind != b"moof" {
+                i += 1;
+                continue;
+            }
+            let moof = read_entry(&mut input.file, &entries[i])?;
+            let mdat = entries[i + 1..]
+                .iter()
+                .position(|e| &e.kind == b"mdat")
+                .map(|p| i + 1 + p)
+                .ok_or(anyhow!("Fragment without mdat box"))?;
+            let source = moof
+                .path(&[b"traf", b"tfhd"])
+                .map(|tfhd| be_u32(&tfhd.payload, 4))
+                .unwrap_or(0);
+            let track = tracks[first..]
+                .iter_mut()
+                .find(|t| t.source_id == source)
+                .ok_or(anyhow!("Fragment of unknown track {}", source))?;
+            let (decode_time, duration) = fragment_timing(&moof, track.trex.as_ref());
+            track.duration = track.duration.max(decode_time + duration);
+            let data_offset = entries[i].offset + entries[i].size;
+            fragments.push(Fragment {
+                moof,
+                data: FragmentData::Input {
+                    index,
+                    offset: data_offset,
+                    size: entries[mdat].offset + entries[mdat].size - data_offset,
+                },
+                time: decode_time as f64 / track.timescale.max(1) as f64,
+            });
+            i = mdat + 1;
+        }
+    }
+    if tracks.is_empty() {
+        return Err(anyhow!("Inputs have no tracks"));
+    }
+
+    for (index, subtitle) in extras.subtitles.iter().enumerate() {
+        let samples = text_samples(subtitle);
+        if samples.is_empty() {
+            continue;
+        }
+        let id = tracks.len() as u32 + 1;
+        let duration = samples.iter().map(|(d, _)| *d as u64).sum();
+        if fragmented {
+            fragments.push(text_fragment(id, &samples));
+        }
+        tracks.push(Track {
+            trak: text_trak(subtitle, index == 0),
+            trex: None,
+            source_id: 0,
+            id,
+            timescale: TEXT_TIMESCALE,
+            duration,
+            samples,
+        });
+    }
+
+    let movie_duration = tracks
+        .iter()
+        .map(|t| rescale(t.duration, t.timescale, movie_timescale))
+        .max()
+        .unwrap_or(0);
+
+    let mut mvhd = inputs[0].moov.find(b"mvhd").unwrap().clone();
+    set_header_duration(&mut mvhd, movie_duration);
+    let next_id = tracks.len() as u32 + 1;
+    let end = mvhd.payload.len() - 4;
+    set_u32(&mut mvhd.payload, end, next_id);
+
+    let mut moov = Atom::container(b"moov", vec![mvhd]);
+    let mut mvex = Atom::container(b"mvex", Vec::new());
+    if fragmented {
+        let mut mehd = vec![1, 0, 0, 0];
+        mehd.extend_from_slice(&movie_duration.to_be_bytes());
+        mvex.children.push(Atom::leaf(b"mehd", mehd));
+    }
+    for track in &mut tracks {
+        let duration = rescale(track.duration, track.timescale, movie_timescale);
+        patch_tkhd(&mut track.trak, track.id, duration);
+        if fragmented {
+            if let Some(mdhd) = track.trak.path_mut(&[b"mdia", b"mdhd"]) {
+                set_header_duration(mdhd, track.duration);
+            }
+            let mut trex = track.trex.clone().unwrap_or_else(|| {
+                let mut payload = vec![0; 24];
+                set_u32(&mut payload, 8, 1);
+                Atom::leaf(b"trex", payload)
+            });
+            set_u32(&mut trex.payload, 4, track.id);
+            mvex.children.push(trex);
+        }
+    }
+
+    let mut out = BufWriter::new(File::create(output)?);
+    let ftyp = ftyp();
+    ftyp.write(&mut out)?;
+
+    if fragmented {
+        for track in &tracks {
+            moov.children.push(track.trak.clone());
+        }
+        moov.children.push(mvex);
+        if let Some(udta) = extras.udta {
+            moov.children.push(udta);
+        }
+        moov.write(&mut out)?;
+
+        let ids: Vec<Vec<(u32, u32)>> = (0..inputs.len())
+            .map(|index| {
+                let mut first = 0;
+                for input in &inputs[..index] {
+                    first += input
+                        .moov
+                        .children
+                        .iter()
+                        .filter(|a| &a.kind == b"trak")
+                        .count();
+                }
+                let count = inputs[index]
+                    .moov
+                    .children
+                    .iter()
+                    .filter(|a| &a.kind == b"trak")
+                    .count();
+                tracks[first..first + count]
+                    .iter()
+                    .map(|t| (t.source_id, t.id))
+                    .collect()
+            })
+            .collect();
+
+        fragments.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let mut position = ftyp.size() + moov.size();
+        for (sequence, mut fragment) in fragments.into_iter().enumerate() {
+            let sequence = sequence as u32 + 1;
+            match fragment.data {
+                FragmentData::Input {
+                    index,
+                    offset,
+                    size,
+                } => {
+                    let source = inputs[index]
+                        .entries
+                        .iter()
+                        .find(|e| e.offset + e.size == offset)
+                        .map(|e| e.offset)
+                        .unwrap_or(0);
+                    let shift = position as i64 - source as i64;
+                    patch_moof(&mut fragment.moof, sequence, &ids[index], shift);
+                    fragment.moof.write(&mut out)?;
+                    copy_range(&mut inputs[index].file, offset, size, &mut out)?;
+                    position += fragment.moof.size() + size;
+                }
+                FragmentData::Memory(mdat) => {
+                    patch_moof(&mut fragment.moof, sequence, &[], 0);
+                    fragment.moof.write(&mut out)?;
+                    mdat.write(&mut out)?;
+                    position += fragment.moof.size() + mdat.size();
+                }
+            }
+        }
+    } else {
+        // Media data of every input is copied as is, followed by the moov with moved offsets
+        let mut position = ftyp.size();
+        let mut shifts = Vec::new();
+        for (index, input) in inputs.iter_mut().enumerate() {
+            let mdats: Vec<Entry> = input
+                .entries
+                .iter()
+                .filter(|e| &e.kind == b"mdat")
+                .copied()
+                .collect();
+            for mdat in mdats {
+                shifts.push((index, mdat, position as i64 - mdat.offset as i64));
+                copy_range(&mut input.file, mdat.offset, mdat.size, &mut out)?;
+                position += mdat.size;
+            }
+        }
+        let mut first = 0;
+        for (index, input) in inputs.iter().enumerate() {
+            let count = input
+                .moov
+                .children
+                .iter()
+                .filter(|a| &a.kind == b"trak")
+                .count();
+            for track in &mut tracks[first..first + count] {
+                let shifts: Vec<(Entry, i64)> = shifts
+                    .iter()
+                    .filter(|(i, _, _)| *i == index)
+                    .map(|(_, e, s)| (*e, *s))
+                    .collect();
+                move_chunk_offsets(&mut track.trak, &shifts)?;
+                moov.children.push(track.trak.clone());
+            }
+            first += count;
+        }
+        // Tracks built in memory store their samples in one chunk after the inputs' data
+        for track in &mut tracks[first..] {
+            let data = track.samples.iter().flat_map(|(_, s)| s.clone()).collect();
+            let mdat = Atom::leaf(b"mdat", data);
+            mdat.write(&mut out)?;
+            let chunk = position + mdat.size() - mdat.body_size();
+            position += mdat.size();
+            if let Some(stbl) = track.trak.path_mut(&[b"mdia", b"minf", b"stbl"]) {
+                stbl.children.retain(|a| &a.kind == b"stsd");
+                stbl.children
+                    .extend(sample_tables(&track.samples, Some(chunk)));
+            }
+            moov.children.push(track.trak.clone());
+        }
+        if let Some(udta) = extras.udta {
+            moov.children.push(udta);
+        }
+        moov.write(&mut out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/** Moves every chunk offset by the shift of the mdat containing it, widening stco to co64 if needed*/
+fn move_chunk_offsets(trak: &mut Atom, shifts: &[(Entry, i64)]) -> anyhow::Result<()> {
+    let stbl = trak
+        .path_mut(&[b"mdia", b"minf", b"stbl"])
+        .ok_or(anyhow!("Track has no sample table"))?;
+    let (offsets, is_co64) = if let Some(co64) = stbl.find(b"co64") {
+        let count = be_u32(&co64.payload, 4) as usize;
+        (
+            (0..count)
+                .map(|i| be_u64(&co64.payload, 8 + i * 8))
+                .collect::<Vec<u64>>(),
+            true,
+        )
+    } else if let Some(stco) = stbl.find(b"stco") {
+        let count = be_u32(&stco.payload, 4) as usize;
+        (
+            (0..count)
+                .map(|i| be_u32(&stco.payload, 8 + i * 4) as u64)
+                .collect(),
+            false,
+        )
+    } else {
+        return Err(anyhow!("Track has no chunk offsets"));
+    };
+
+    let moved: Vec<u64> = offsets
+        .iter()
+        .map(|o| {
+            let shift = shifts
+                .iter()
+                .find(|(e, _)| *o >= e.offset && *o < e.offset + e.size)
+                .map(|(_, s)| *s)
+                .unwrap_or(0);
+            (*o as i64 + shift) as u64
+        })
+        .collect();
+
+    stbl.children
+        .retain(|a| &a.kind != b"stco" && &a.kind != b"co64");
+    let wide = is_co64 || moved.iter().any(|o| *o > u32::MAX as u64);
+    let mut payload = vec![0, 0, 0, 0];
+    payload.extend_from_slice(&(moved.len() as u32).to_be_bytes());
+    for offset in moved {
+        if wide {
+            payload.extend_from_slice(&offset.to_be_bytes());
+        } else {
+            payload.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+    }
+    stbl.children
+        .push(Atom::leaf(if wide { b"co64" } else { b"stco" }, payload));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::Cue;
+
+    fn subtitle(language: &str, format: SubtitleFormat) -> Subtitle {
+        Subtitle::new(
+            language,
+            format,
+            vec![Cue::new(1000, 2500, "Hello"), Cue::new(2500, 4000, "a < b")],
+        )
+    }
+
+    #[test]
+    fn lays_out_tx3g_samples() {
+        let samples = text_samples(&subtitle("en", SubtitleFormat::Srt));
+        assert_eq!(
+            samples,
+            vec![
+                (1000, vec![0, 0]),
+                (1500, b"\0\x05Hello".to_vec()),
+                (1500, b"\0\x05a < b".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn lays_out_wvtt_samples() {
+        let samples = text_samples(&subtitle("en", SubtitleFormat::Vtt));
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0], (1000, b"\0\0\0\x08vtte".to_vec()));
+        let cue = Atom::parse_all(&samples[2].1).unwrap().remove(0);
+        assert_eq!(&cue.kind, b"vttc");
+        let payl = Atom::parse_all(&cue.payload).unwrap().remove(0);
+        assert_eq!(&payl.kind, b"payl");
+        assert_eq!(payl.payload, b"a &lt; b");
+        assert_eq!(samples[2].0, 1500);
+    }
+
+    #[test]
+    fn describes_text_tracks() {
+        let trak = text_trak(&subtitle("pt-BR", SubtitleFormat::Srt), true);
+        // Enabled and in movie, in the alternate group of subtitles
+        let tkhd = &trak.find(b"tkhd").unwrap().payload;
+        assert_eq!(be_u32(tkhd, 0), 0x3);
+        assert_eq!(u16::from_be_bytes([tkhd[34], tkhd[35]]), 2);
+        // "por" packed into five bits per letter
+        let mdhd = &trak.path(&[b"mdia", b"mdhd"]).unwrap().payload;
+        assert_eq!(be_u32(mdhd, 12), TEXT_TIMESCALE);
+        let packed = u16::from_be_bytes([mdhd[20], mdhd[21]]);
+        assert_eq!(packed, (16 << 10) | (15 << 5) | 18);
+        let hdlr = &trak.path(&[b"mdia", b"hdlr"]).unwrap().payload;
+        assert_eq!(&hdlr[8..12], b"sbtl");
+        let elng = &trak.path(&[b"mdia", b"elng"]).unwrap().payload;
+        assert_eq!(&elng[4..], b"pt-BR\0");
+        let stsd = &trak
+            .path(&[b"mdia", b"minf", b"stbl", b"stsd"])
+            .unwrap()
+            .payload;
+        assert_eq!(&stsd[12..16], b"tx3g");
+
+        let trak = text_trak(&subtitle("de", SubtitleFormat::Vtt), false);
+        assert_eq!(be_u32(&trak.find(b"tkhd").unwrap().payload, 0), 0x2);
+        let mdhd = &trak.path(&[b"mdia", b"mdhd"]).unwrap().payload;
+        // The terminology code "deu", not the bibliographic "ger"
+        let packed = u16::from_be_bytes([mdhd[20], mdhd[21]]);
+        assert_eq!(packed, (4 << 10) | (5 << 5) | 21);
+        let hdlr = &trak.path(&[b"mdia", b"hdlr"]).unwrap().payload;
+        assert_eq!(&hdlr[8..12], b"text");
+        let stsd = &trak
+            .path(&[b"mdia", b"minf", b"stbl", b"stsd"])
+            .unwrap()
+            .payload;
+        assert_eq!(&stsd[12..16], b"wvtt");
+        let entry = Atom::parse_all(&stsd[8..]).unwrap().remove(0);
+        let vttc = Atom::parse_all(&entry.payload[8..]).unwrap().remove(0);
+        assert_eq!(&vttc.kind, b"vttC");
+        assert_eq!(vttc.payload, b"WEBVTT");
+    }
+
+    #[test]
+    fn sizes_text_fragments() {
+        let samples = text_samples(&subtitle("en", SubtitleFormat::Srt));
+        let fragment = text_fragment(3, &samples);
+        let tfhd = &fragment.moof.path(&[b"traf", b"tfhd"]).unwrap().payload;
+        assert_eq!(be_u32(tfhd, 0), 0x020000);
+        assert_eq!(be_u32(tfhd, 4), 3);
+        let trun = &fragment.moof.path(&[b"traf", b"trun"]).unwrap().payload;
+        assert_eq!(be_u32(trun, 4), 3);
+        // The data starts right after the moof and the mdat header
+        assert_eq!(be_u32(trun, 8) as u64, fragment.moof.size() + 8);
+        assert_eq!((be_u32(trun, 12), be_u32(trun, 16)), (1000, 2));
+        assert_eq!((be_u32(trun, 20), be_u32(trun, 24)), (1500, 7));
+    }
+
+    #[test]
+    fn stores_cover_in_ilst() {
+        let udta = cover_udta(b"\xFF\xD8jpeg");
+        let meta = udta.find(b"meta").unwrap();
+        // meta is a full box holding boxes
+        assert_eq!(meta.payload, [0; 4]);
+        let hdlr = &meta.find(b"hdlr").unwrap().payload;
+        assert_eq!(&hdlr[8..16], b"mdirappl");
+        let data = meta
+            .find(b"ilst")
+            .and_then(|ilst| ilst.path(&[b"covr", b"data"]))
+            .unwrap();
+        assert_eq!(be_u32(&data.payload, 0), 13);
+        assert_eq!(&data.payload[8..], b"\xFF\xD8jpeg");
+
+        // Written and read back, the children follow the version and flags
+        let mut written = Vec::new();
+        udta.write(&mut written).unwrap();
+        assert_eq!(written.len() as u64, udta.size());
+        let parsed = Atom::parse_all(&written).unwrap().remove(0);
+        let meta = &parsed.find(b"meta").unwrap().payload;
+        assert_eq!(&meta[..4], [0; 4]);
+        assert_eq!(&meta[8..12], b"hdlr");
+    }
+
+    fn write(atom: &Atom) -> Vec<u8> {
+        let mut data = Vec::new();
+        atom.write(&mut data).unwrap();
+        data
+    }
+
+    fn mvhd(timescale: u32, duration: u32) -> Atom {
+        let mut payload = vec![0; 100];
+        set_u32(&mut payload, 12, timescale);
+        set_u32(&mut payload, 16, duration);
+        set_u32(&mut payload, 96, 2);
+        Atom::leaf(b"mvhd", payload)
+    }
+
+    /** A track whose sample table ends with the given chunk offsets box*/
+    fn trak(id: u32, timescale: u32, duration: u32, offsets: Atom) -> Atom {
+        let mut tkhd = vec![0; 84];
+        set_u32(&mut tkhd, 0, 3);
+        set_u32(&mut tkhd, 12, id);
+        set_u32(&mut tkhd, 20, duration);
+        let mut mdhd = vec![0; 24];
+        set_u32(&mut mdhd, 12, timescale);
+        set_u32(&mut mdhd, 16, duration);
+        Atom::container(
+            b"trak",
+            vec![
+                Atom::leaf(b"tkhd", tkhd),
+                Atom::container(
+                    b"mdia",
+                    vec![
+                        Atom::leaf(b"mdhd", mdhd),
+                        Atom::container(
+                            b"minf",
+                            vec![Atom::container(
+                                b"stbl",
+                                vec![Atom::leaf(b"stsd", vec![0; 8]), offsets],
+                            )],
+                        ),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    fn stco(offsets: &[u32]) -> Atom {
+        let mut payload = full_box(0);
+        payload.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+        for offset in offsets {
+            payload.extend_from_slice(&offset.to_be_bytes());
+        }
+        Atom::leaf(b"stco", payload)
+    }
+
+    fn co64(offsets: &[u64]) -> Atom {
+        let mut payload = full_box(0);
+        payload.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+        for offset in offsets {
+            payload.extend_from_slice(&offset.to_be_bytes());
+        }
+        Atom::leaf(b"co64", payload)
+    }
+
+    /** A fragmented file of a single track with id `id` and one fragment per decode time,
+    whose samples have the given duration and whose moofs set an explicit base data offset*/
+    fn fragmented(id: u32, timescale: u32, times: &[u64], duration: u32, data: u8) -> Vec<u8> {
+        let mut trex = vec![0; 24];
+        set_u32(&mut trex, 4, id);
+        set_u32(&mut trex, 8, 1);
+        set_u32(&mut trex, 12, duration);
+        let moov = Atom::container(
+            b"moov",
+            vec![
+                mvhd(1000, 0),
+                trak(id, timescale, 0, stco(&[])),
+                Atom::container(b"mvex", vec![Atom::leaf(b"trex", trex)]),
+            ],
+        );
+        let mut file = [write(&ftyp()), write(&moov)].concat();
+        for (i, time) in times.iter().enumerate() {
+            let mut tfhd = full_box(0x1);
+            tfhd.extend_from_slice(&id.to_be_bytes());
+            tfhd.extend_from_slice(&(file.len() as u64).to_be_bytes());
+            let mut tfdt = vec![1, 0, 0, 0];
+            tfdt.extend_from_slice(&time.to_be_bytes());
+            let mut trun = full_box(0x301);
+            trun.extend_from_slice(&1u32.to_be_bytes());
+            trun.extend_from_slice(&0u32.to_be_bytes());
+            trun.extend_from_slice(&duration.to_be_bytes());
+            trun.extend_from_slice(&4u32.to_be_bytes());
+            let mut moof = Atom::container(
+                b"moof",
+                vec![
+                    Atom::leaf(b"mfhd", vec![0, 0, 0, 0, 0, 0, 0, i as u8 + 1]),
+                    Atom::container(
+                        b"traf",
+                        vec![
+                            Atom::leaf(b"tfhd", tfhd),
+                            Atom::leaf(b"tfdt", tfdt),
+                            Atom::leaf(b"trun", trun),
+                        ],
+                    ),
+                ],
+            );
+            let data_offset = moof.size() as u32 + 8;
+            set_u32(
+                &mut moof.path_mut(&[b"traf", b"trun"]).unwrap().payload,
+                8,
+                data_offset,
+            );
+            file.extend(write(&moof));
+            file.extend(write(&Atom::leaf(b"mdat", vec![data, i as u8, 0, 0])));
+        }
+        file
+    }
+
+    /** Muxes the inputs through temporary files and parses the output's top level boxes*/
+    fn mux_files(name: &str, inputs: &[Vec<u8>], extras: Extras) -> (Vec<u8>, Vec<Atom>) {
+        let dir = std::env::temp_dir();
+        let prefix = format!("yt_download-{}-{}", std::process::id(), name);
+        let paths: Vec<_> = (0..inputs.len())
+            .map(|i| dir.join(format!("{}-{}.mp4", prefix, i)))
+            .collect();
+        for (path, data) in paths.iter().zip(inputs) {
+            std::fs::write(path, data).unwrap();
+        }
+        let output = dir.join(format!("{}.mp4", prefix));
+        let inputs: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+        let result = mux_with(&inputs, &output, extras);
+        let data = std::fs::read(&output);
+        for path in paths.iter().chain([&output]) {
+            let _ = std::fs::remove_file(path);
+        }
+        result.unwrap();
+        let data = data.unwrap();
+        let atoms = Atom::parse_all(&data).unwrap();
+        (data, atoms)
+    }
+
+    #[test]
+    fn interleaves_fragments_by_decode_time() {
+        let video = fragmented(1, 1000, &[0, 2000], 2000, 0xA);
+        // Audio fragments start at 0 and 1.5 seconds
+        let audio = fragmented(1, 48000, &[0, 72000], 72000, 0xB);
+        let (data, atoms) = mux_files("fragmented", &[video, audio], Extras::default());
+        let kinds: Vec<&[u8; 4]> = atoms.iter().map(|a| &a.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                b"ftyp", b"moov", b"moof", b"mdat", b"moof", b"mdat", b"moof", b"mdat", b"moof",
+                b"mdat"
+            ]
+        );
+
+        let moov = &atoms[1];
+        let traks: Vec<u32> = moov
+            .children
+            .iter()
+            .filter(|a| &a.kind == b"trak")
+            .map(track_id)
+            .collect();
+        assert_eq!(traks, [1, 2]);
+        let mvhd = &moov.find(b"mvhd").unwrap().payload;
+        assert_eq!(be_u32(mvhd, 96), 3);
+        // The longest track lasts four seconds in the movie timescale
+        assert_eq!(header_timing(moov.find(b"mvhd").unwrap()), (1000, 4000));
+        let mvex = moov.find(b"mvex").unwrap();
+        assert_eq!(be_u64(&mvex.find(b"mehd").unwrap().payload, 4), 4000);
+        let trex: Vec<(u32, u32)> = mvex
+            .children
+            .iter()
+            .filter(|a| &a.kind == b"trex")
+            .map(|t| (be_u32(&t.payload, 4), be_u32(&t.payload, 12)))
+            .collect();
+        assert_eq!(trex, [(1, 2000), (2, 72000)]);
+
+        // Video and audio start together, audio continues before the second video fragment
+        let mut position = atoms[0].size() + atoms[1].size();
+        let mut fragments = Vec::new();
+        for pair in atoms[2..].chunks(2) {
+            let (moof, mdat) = (&pair[0], &pair[1]);
+            let tfhd = &moof.path(&[b"traf", b"tfhd"]).unwrap().payload;
+            // Base data offsets point at the moof in the output
+            assert_eq!(be_u64(tfhd, 8), position);
+            let trun = &moof.path(&[b"traf", b"trun"]).unwrap().payload;
+            let start = position as usize + be_u32(trun, 8) as usize;
+            assert_eq!(&data[start..start + 4], &mdat.payload[..]);
+            let sequence = be_u32(&moof.find(b"mfhd").unwrap().payload, 4);
+            fragments.push((sequence, be_u32(tfhd, 4), mdat.payload[0], mdat.payload[1]));
+            position += moof.size() + mdat.size();
+        }
+        assert_eq!(
+            fragments,
+            [
+                (1, 1, 0xA, 0),
+                (2, 2, 0xB, 0),
+                (3, 2, 0xB, 1),
+                (4, 1, 0xA, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_chunk_offsets_of_plain_inputs() {
+        // The first input's samples start right after its ftyp and mdat header
+        let ftyp_size = ftyp().size() as u32;
+        let first = [
+            write(&ftyp()),
+            write(&Atom::leaf(b"mdat", vec![1; 8])),
+            write(&Atom::container(
+                b"moov",
+                vec![
+                    mvhd(1000, 3000),
+                    trak(1, 1000, 3000, stco(&[ftyp_size + 8, ftyp_size + 12])),
+                ],
+            )),
+        ]
+        .concat();
+        // The second one has its moov and a free box ahead of the mdat, and 64 bit offsets
+        let mut moov = Atom::container(
+            b"moov",
+            vec![mvhd(600, 1200), trak(7, 600, 1200, co64(&[0]))],
+        );
+        let mdat_offset = ftyp().size() + moov.size() + 100;
+        let offsets = moov
+            .path_mut(&[b"trak", b"mdia", b"minf", b"stbl", b"co64"])
+            .unwrap();
+        set_u64(&mut offsets.payload, 8, mdat_offset + 8);
+        let second = [
+            write(&ftyp()),
+            write(&moov),
+            write(&Atom::leaf(b"free", vec![0; 92])),
+            write(&Atom::leaf(b"mdat", vec![2; 4])),
+        ]
+        .concat();
+
+        let subtitles = vec![subtitle("en", SubtitleFormat::Srt)];
+        let extras = Extras {
+            udta: None,
+            subtitles,
+        };
+        let (data, atoms) = mux_files("plain", &[first, second], extras);
+        let kinds: Vec<&[u8; 4]> = atoms.iter().map(|a| &a.kind).collect();
+        assert_eq!(kinds, [b"ftyp", b"mdat", b"mdat", b"mdat", b"moov"]);
+        let moov = &atoms[4];
+        assert_eq!(be_u32(&moov.find(b"mvhd").unwrap().payload, 96), 4);
+        let traks: Vec<&Atom> = moov
+            .children
+            .iter()
+            .filter(|a| &a.kind == b"trak")
+            .collect();
+        assert_eq!(
+            traks.iter().map(|t| track_id(t)).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+
+        let stbl = |trak: &Atom| trak.path(&[b"mdia", b"minf", b"stbl"]).unwrap().clone();
+        // The first mdat stays where it was
+        let first = stbl(traks[0]);
+        assert_eq!(
+            first.find(b"stco").unwrap().payload,
+            stco(&[ftyp_size + 8, ftyp_size + 12]).payload
+        );
+        let offset = ftyp_size as usize + 8;
+        assert_eq!(&data[offset..offset + 8], [1; 8]);
+        // The second one follows it and keeps its 64 bit offsets
+        let second = stbl(traks[1]);
+        let offset = be_u64(&second.find(b"co64").unwrap().payload, 8) as usize;
+        assert_eq!(offset, ftyp_size as usize + 16 + 8);
+        assert_eq!(&data[offset..offset + 4], [2; 4]);
+        // The text track's samples are in the last mdat
+        let text = stbl(traks[2]);
+        let offset = be_u32(&text.find(b"stco").unwrap().payload, 8) as usize;
+        assert_eq!(&data[offset..offset + 7], b"\0\0\0\x05Hel");
+    }
+
+    #[test]
+    fn widens_chunk_offsets_beyond_four_gigabytes() {
+        let mut track = trak(1, 1000, 0, stco(&[40, 60]));
+        let mdat = Entry {
+            kind: *b"mdat",
+            offset: 32,
+            size: 40,
+        };
+        move_chunk_offsets(&mut track, &[(mdat, u32::MAX as i64)]).unwrap();
+        let stbl = track.path(&[b"mdia", b"minf", b"stbl"]).unwrap();
+        assert!(stbl.find(b"stco").is_none());
+        let co64 = &stbl.find(b"co64").unwrap().payload;
+        assert_eq!(be_u32(co64, 4), 2);
+        assert_eq!(be_u64(co64, 8), 40 + u32::MAX as u64);
+        assert_eq!(be_u64(co64, 16), 60 + u32::MAX as u64);
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::anyhow;
+use boa_engine::{Context, Source};
+use regex::Regex;
+
+lazy_static! {
+    static ref N_FUNCTION_NAME: Vec<Regex> = vec![
+        Regex::new("\\.get\\(\"n\"\\)\\)&&\\(b=([a-zA-Z0-9_$]+)(?:\\[(\\d+)\\])?\\([a-zA-Z0-9_$]\\)")
+            .unwrap(),
+        Regex::new(
+            "\\(b=String\\.fromCharCode\\(110\\),c=a\\.get\\(b\\)\\)&&\\(c=([a-zA-Z0-9_$]+)(?:\\[(\\d+)\\])?\\([a-zA-Z0-9_$]\\)"
+        )
+        .unwrap(),
+        Regex::new(
+            "[;,]c=a\\.get\\(b\\)\\)&&\\(c=([a-zA-Z0-9_$]+)(?:\\[(\\d+)\\])?\\(c\\),a\\.set\\(b,c\\)"
+        )
+        .unwrap(),
+    ];
+    static ref UNDEFINED_GUARD: Regex = Regex::new(
+        ";\\s*if\\s*\\(\\s*typeof\\s+[a-zA-Z0-9_$]+\\s*===?\\s*(?:\"undefined\"|'undefined'|[a-zA-Z0-9_$]+\\[\\d+\\])\\s*\\)\\s*return\\s+[a-zA-Z0-9_$]+;"
+    )
+    .unwrap();
+}
+
+/** Upper bound for loop iterations, so a broken transform cannot hang the download*/
+const LOOP_ITERATION_LIMIT: u64 = 1_000_000;
+
+/** The throttling parameter transform function extracted from the player's base.js*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NTransform {
+    code: String,
+}
+
+impl NTransform {
+    /** Locates the n-transform function in the player script*/
+    pub fn from_player_js(js: &str) -> anyhow::Result<NTransform> {
+        let captures = N_FUNCTION_NAME
+            .iter()
+            .find_map(|r| r.captures(js))
+            .ok_or(anyhow!(
+                "N-transform function was not found in player script"
+            ))?;
+        let mut name = captures[1].to_owned();
+        if let Some(index) = captures.get(2) {
+            let index = index.as_str().parse::<usize>()?;
+            let array = Regex::new(&format!(
+                "var {}\\s*=\\s*\\[([^\\]]+)\\]",
+                regex::escape(&name)
+            ))?;
+            name = array.captures(js).ok_or(anyhow!(
+                "N-transform function array \"{}\" was not found",
+                name
+            ))?[1]
+                .split(',')
+                .nth(index)
+                .ok_or(anyhow!("N-transform function index is out of bounds"))?
+                .trim()
+                .to_owned();
+        }
+
+        let declaration = Regex::new(&format!(
+            "(?:function\\s+{0}|[{{;,]\\s*{0}\\s*=\\s*function|(?:var|const|let)\\s+{0}\\s*=\\s*function)\\s*\\(([^)]*)\\)\\s*\\{{",
+            regex::escape(&name)
+        ))?;
+        let declaration = declaration
+            .captures(js)
+            .ok_or(anyhow!("N-transform function \"{}\" was not found", name))?;
+        let args = declaration.get(1).unwrap().as_str();
+        let body_start = declaration.get(0).unwrap().end();
+        let body_end = find_block_end(js, body_start)
+            .ok_or(anyhow!("N-transform function \"{}\" is not closed", name))?;
+        let body = UNDEFINED_GUARD.replace_all(&js[body_start..body_end], ";");
+
+        Ok(NTransform {
+            code: format!("function({}){{{}}}", args, body),
+        })
+    }
+
+    /** Restores a transform previously returned by `get_code`*/
+    pub fn from_code(code: String) -> NTransform {
+        NTransform { code }
+    }
+
+    /** The standalone JavaScript function performing the transform*/
+    pub fn get_code(&self) -> &str {
+        &self.code
+    }
+
+    /** Evaluates the transform on the value of the `n` url parameter*/
+    pub fn transform(&self, n: &str) -> anyhow::Result<String> {
+        let mut context = Context::default();
+        context
+            .runtime_limits_mut()
+            .set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+        let script = format!("({})({})", self.code, json::stringify(n));
+        let result = context
+            .eval(Source::from_bytes(script.as_bytes()))
+            .map_err(|e| anyhow!("N-transform evaluation failed: {}", e))?
+            .to_string(&mut context)
+            .map_err(|e| anyhow!("N-transform evaluation failed: {}", e))?
+            .to_std_string_escaped();
+        if result == n || result.starts_with("enhanced_except_") {
+            return Err(anyhow!("N-transform returned an invalid value"));
+        }
+        Ok(result)
+    }
+}
+
+/** Returns the index of the closing brace of the block whose body starts at `start`.
+It skips over string, template, comment and regular expression literals*/
+fn find_block_end(js: &str, start: usize) -> Option<usize> {
+    let bytes = js.as_bytes();
+    let mut depth = 1;
+    let mut last = b'{';
+    let mut i = start;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            b'"' | b'\'' | b'`' => i = skip_quoted(bytes, i, c)?,
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = js[i..].find('\n')? + i;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = js[i + 2..].find("*/")? + i + 3;
+            }
+            b'/' if b"(,=:[!&|?{};+-*%<>~^".contains(&last) => i = skip_regex(bytes, i)?,
+            _ => {}
+        }
+        if !c.is_ascii_whitespace() {
+            last = bytes[i];
+        }
+        i += 1;
+    }
+    None
+}
+
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> Option<usize> {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn skip_regex(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut in_class = false;
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            b'/' if !in_class => return Some(i),
+            b'\n' => return None,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** The end of the block opened by the first brace of `js`*/
+    fn block_end(js: &str) -> Option<usize> {
+        find_block_end(js, js.find('{')? + 1)
+    }
+
+    #[test]
+    fn matches_nested_braces() {
+        let js = "{if(a){b={c:1}}else{d()}}e()}";
+        assert_eq!(block_end(js), Some(24));
+        assert_eq!(block_end("{a={b:1};"), None);
+    }
+
+    #[test]
+    fn skips_braces_in_strings_and_comments() {
+        let js = r#"{a="}";b='{';c=`}${d}`;e="\"}";/* } */f=1;// }
+g=2}h"#;
+        assert_eq!(block_end(js), js.find("}h"));
+    }
+
+    #[test]
+    fn skips_braces_in_regular_expressions() {
+        let js = r"{a=b.replace(/}[}\/]\}/g,'');c=d/2/e;f=[/{/]}g";
+        assert_eq!(block_end(js), js.find("}g"));
+        // An unterminated regular expression ends at the line
+        assert_eq!(block_end("{a=/}\n}"), None);
+    }
+
+    /** A player script whose n-function is referenced through an array and guarded against
+    evaluation outside the player*/
+    const PLAYER_JS: &str = concat!(
+        "var Qx=[Nf];var z=1;",
+        "Nf=function(a){var b=a.split(\"\"),c=\"}\";",
+        "if(typeof Zz===\"undefined\")return a;",
+        "/* } */var r=/[}\\/]/;b.reverse();return b.join(\"\")+\"_\"+c.length};",
+        "g=function(a){(b=a.get(\"n\"))&&(b=Qx[0](b),a.set(\"n\",b))};"
+    );
+
+    #[test]
+    fn extracts_and_evaluates_transform() {
+        let transform = NTransform::from_player_js(PLAYER_JS).unwrap();
+        assert!(transform
+            .get_code()
+            .starts_with("function(a){var b=a.split"));
+        assert!(!transform.get_code().contains("typeof"));
+        assert_eq!(transform.transform("abc").unwrap(), "cba_1");
+        // The code survives the player cache
+        let cached = NTransform::from_code(transform.get_code().to_owned());
+        assert_eq!(cached.transform("xyz").unwrap(), "zyx_1");
+    }
+
+    #[test]
+    fn rejects_unchanged_values() {
+        let transform = NTransform::from_code("function(a){return a}".to_owned());
+        assert_eq!(
+            transform.transform("abc").unwrap_err().to_string(),
+            "N-transform returned an invalid value"
+        );
+        assert!(NTransform::from_code("function(a){".to_owned())
+            .transform("abc")
+            .is_err());
+    }
+}
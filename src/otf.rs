@@ -0,0 +1,89 @@
+use crate::download::Progress;
+use crate::video::Stream;
+use regex::bytes::Regex;
+use reqwest::Url;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+lazy_static! {
+    static ref SEGMENT_COUNT: Regex = Regex::new("Segment-Count: (\\d+)").unwrap();
+}
+
+/** The url of the segment with the given sequence number, 0 being the init segment*/
+pub(crate) fn segment_url(stream: &Stream, sequence: u64) -> anyhow::Result<Url> {
+    let mut url = Url::parse(stream.get_url())?;
+    url.query_pairs_mut()
+        .append_pair("sq", &sequence.to_string());
+    Ok(url)
+}
+
+/** Concatenates the init segment and all media segments of an OTF stream into the file.
+The init segment tells the number of segments, without it they are requested until one is missing.
+The total of the reported progress is 0 as it is unknown*/
+pub(crate) async fn download(
+    stream: &Stream,
+    path: impl AsRef<Path>,
+    mut progress: impl FnMut(Progress),
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let mut file = File::create(path)?;
+    progress(Progress::new(0, 0));
+
+    let init = client
+        .get(segment_url(stream, 0)?)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    file.write_all(&init)?;
+    let mut downloaded = init.len() as u64;
+    progress(Progress::new(downloaded, 0));
+    let count: Option<u64> = SEGMENT_COUNT
+        .captures(&init)
+        .and_then(|c| std::str::from_utf8(&c[1]).ok()?.parse().ok());
+
+    let mut sequence = 1;
+    while count.is_none_or(|count| sequence <= count) {
+        let response = client.get(segment_url(stream, sequence)?).send().await?;
+        if count.is_none() && response.status() == 404 {
+            break;
+        }
+        let data = response.error_for_status()?.bytes().await?;
+        if data.is_empty() {
+            break;
+        }
+        file.write_all(&data)?;
+        downloaded += data.len() as u64;
+        progress(Progress::new(downloaded, 0));
+        sequence += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use json::object;
+
+    #[test]
+    fn appends_sequence_to_query() {
+        let value = object! { itag: 136, mimeType: "video/mp4; codecs=\"avc1.4d401f\"" };
+        let format = Format::from_json(&value, true, None).unwrap();
+        let stream = Stream::new(
+            "https://rr1.googlevideo.com/videoplayback?id=a%26b&otf=1".to_owned(),
+            format,
+        );
+        assert_eq!(
+            segment_url(&stream, 0).unwrap().as_str(),
+            "https://rr1.googlevideo.com/videoplayback?id=a%26b&otf=1&sq=0"
+        );
+        let url = segment_url(&stream, 12).unwrap();
+        assert_eq!(
+            url.query_pairs().last().unwrap(),
+            ("sq".into(), "12".into())
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::cache::PlayerCache;
+use crate::cipher::Cipher;
+use crate::nsig::NTransform;
+use anyhow::anyhow;
+use regex::Regex;
+use std::collections::HashMap;
+
+const PLAYER_JS: &str = "base.js";
+const CIPHER_PROGRAM: &str = "cipher";
+const N_TRANSFORM_PROGRAM: &str = "nsig.js";
+const SIGNATURE_TIMESTAMP: &str = "sts";
+
+/** The player's base.js together with the transforms derived from it*/
+pub struct Player {
+    js: Option<String>,
+    cache: Option<PlayerCache>,
+    signature_timestamp: Option<u32>,
+    cipher: Option<Cipher>,
+    n_transform: Option<NTransform>,
+    n_values: HashMap<String, String>,
+}
+
+impl Player {
+    /** Loads the current player version announced by the iframe api.
+    The script is only downloaded if the cache lacks any of the derived programs*/
+    pub async fn load(client: &reqwest::Client) -> anyhow::Result<Player> {
+        let iframe_api = client
+            .get("https://www.youtube.com/iframe_api")
+            .send()
+            .await?
+            .text()
+            .await?;
+        let player_id = Regex::new("player\\\\?/([a-zA-Z0-9_-]+)\\\\?/")?;
+        let player_id = player_id
+            .captures(&iframe_api)
+            .ok_or(anyhow!("Player version was not found in iframe api"))?[1]
+            .to_owned();
+        let url = format!(
+            "https://www.youtube.com/s/player/{}/player_ias.vflset/en_US/base.js",
+            player_id
+        );
+        let cache = PlayerCache::open(&player_id);
+
+        let cipher = cache
+            .as_ref()
+            .and_then(|c| c.read(CIPHER_PROGRAM))
+            .and_then(|program| program.parse().ok());
+        let n_transform = cache
+            .as_ref()
+            .and_then(|c| c.read(N_TRANSFORM_PROGRAM))
+            .map(NTransform::from_code);
+
+        let signature_timestamp = cache
+            .as_ref()
+            .and_then(|c| c.read(SIGNATURE_TIMESTAMP))
+            .and_then(|sts| sts.parse().ok());
+
+        let js = if cipher.is_some() && n_transform.is_some() && signature_timestamp.is_some() {
+            None
+        } else if let Some(js) = cache.as_ref().and_then(|c| c.read(PLAYER_JS)) {
+            Some(js)
+        } else {
+            let js = client.get(url).send().await?.text().await?;
+            if let Some(cache) = &cache {
+                cache.prune();
+                cache.write(PLAYER_JS, &js);
+            }
+            Some(js)
+        };
+
+        let signature_timestamp = match (signature_timestamp, &js) {
+            (Some(sts), _) => Some(sts),
+            (None, Some(js)) => {
+                let sts = Regex::new("(?:signatureTimestamp|sts)\\s*:\\s*(\\d{5})")?
+                    .captures(js)
+                    .and_then(|c| c[1].parse::<u32>().ok());
+                if let (Some(cache), Some(sts)) = (&cache, sts) {
+                    cache.write(SIGNATURE_TIMESTAMP, &sts.to_string());
+                }
+                sts
+            }
+            (None, None) => None,
+        };
+
+        Ok(Player {
+            js,
+            cache,
+            signature_timestamp,
+            cipher,
+            n_transform,
+            n_values: HashMap::new(),
+        })
+    }
+
+    /** The player version the cipher belongs to, sent along with InnerTube requests*/
+    pub fn get_signature_timestamp(&self) -> Option<u32> {
+        self.signature_timestamp
+    }
+
+    fn get_js(&self) -> anyhow::Result<&str> {
+        self.js
+            .as_deref()
+            .ok_or(anyhow!("Player script was not loaded"))
+    }
+
+    /** Deciphers the `s` parameter of a signatureCipher*/
+    pub fn decipher(&mut self, signature: &str) -> anyhow::Result<String> {
+        if self.cipher.is_none() {
+            let cipher = Cipher::from_player_js(self.get_js()?)?;
+            if let Some(cache) = &self.cache {
+                cache.write(CIPHER_PROGRAM, &cipher.to_string());
+            }
+            self.cipher = Some(cipher);
+        }
+        Ok(self.cipher.as_ref().unwrap().decipher(signature))
+    }
+
+    /** Transforms the `n` throttling parameter of a stream url*/
+    pub fn transform_n(&mut self, n: &str) -> anyhow::Result<String> {
+        if let Some(result) = self.n_values.get(n) {
+            return Ok(result.clone());
+        }
+        if self.n_transform.is_none() {
+            let n_transform = NTransform::from_player_js(self.get_js()?)?;
+            if let Some(cache) = &self.cache {
+                cache.write(N_TRANSFORM_PROGRAM, n_transform.get_code());
+            }
+            self.n_transform = Some(n_transform);
+        }
+        let result = self.n_transform.as_ref().unwrap().transform(n)?;
+        self.n_values.insert(n.to_owned(), result.clone());
+        Ok(result)
+    }
+}
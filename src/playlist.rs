@@ -0,0 +1,273 @@
+use crate::innertube::{self, find_continuation, find_renderers};
+use anyhow::anyhow;
+use json::JsonValue;
+use std::str::FromStr;
+
+/** A video of a playlist or channel*/
+#[derive(Debug, Clone)]
+pub struct Entry {
+    index: usize,
+    video_id: String,
+    title: String,
+    length_seconds: Option<u64>,
+}
+
+impl Entry {
+    pub(crate) fn new(
+        index: usize,
+        video_id: String,
+        title: String,
+        length_seconds: Option<u64>,
+    ) -> Entry {
+        Entry {
+            index,
+            video_id,
+            title,
+            length_seconds,
+        }
+    }
+
+    /** The position in the playlist, starting at 1*/
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_video_id(&self) -> &str {
+        &self.video_id
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    /** The duration in seconds, None for live broadcasts and upcoming premieres*/
+    pub fn get_length_seconds(&self) -> Option<u64> {
+        self.length_seconds
+    }
+}
+
+/** A playlist with all of its videos*/
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    id: String,
+    title: String,
+    entries: Vec<Entry>,
+}
+
+impl Playlist {
+    /** The playlist id, usually starting with "PL"*/
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    /** The videos in playlist order. Deleted and private videos are left out*/
+    pub fn get_entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/** Appends the playlistVideoRenderers of a page to the entries*/
+fn parse_entries(value: &JsonValue, entries: &mut Vec<Entry>) {
+    let mut renderers = Vec::new();
+    find_renderers(value, "playlistVideoRenderer", &mut renderers);
+    for renderer in renderers {
+        let video_id = match renderer["videoId"].as_str() {
+            Some(video_id) => video_id.to_owned(),
+            None => continue,
+        };
+        // Unavailable videos are listed but cannot be played
+        if renderer["isPlayable"].as_bool() == Some(false) {
+            continue;
+        }
+        entries.push(Entry {
+            index: innertube::text(&renderer["index"])
+                .and_then(|i| i.parse().ok())
+                .unwrap_or(entries.len() + 1),
+            video_id,
+            title: innertube::text(&renderer["title"]).unwrap_or_default(),
+            length_seconds: renderer["lengthSeconds"]
+                .as_str()
+                .and_then(|l| l.parse().ok()),
+        });
+    }
+}
+
+/** The message YouTube shows for missing or private playlists*/
+fn alert(value: &JsonValue) -> Option<String> {
+    let mut alerts = Vec::new();
+    find_renderers(value, "alertRenderer", &mut alerts);
+    alerts.iter().find_map(|a| innertube::text(&a["text"]))
+}
+
+/** Requests a playlist and all of its continuation pages*/
+pub async fn get_playlist(playlist_id: &str) -> anyhow::Result<Playlist> {
+    let client = reqwest::Client::new();
+    let browse_id = format!("VL{}", playlist_id);
+    let value = innertube::browse(&client, &browse_id, None, None).await?;
+
+    let title = value["metadata"]["playlistMetadataRenderer"]["title"]
+        .as_str()
+        .map(str::to_owned);
+    let title = match title {
+        Some(title) => title,
+        None => {
+            return Err(anyhow!(
+                "Playlist {} cannot be read: {}",
+                playlist_id,
+                alert(&value).unwrap_or("no playlist in the response".to_owned())
+            ))
+        }
+    };
+
+    let mut entries = Vec::new();
+    parse_entries(&value, &mut entries);
+    // Pages hold 100 videos, the rest is requested with the token ending each page
+    let mut continuation = find_continuation(&value);
+    while let Some(token) = continuation {
+        let page = innertube::browse(&client, &browse_id, None, Some(&token)).await?;
+        let before = entries.len();
+        parse_entries(&page, &mut entries);
+        continuation = find_continuation(&page).filter(|_| entries.len() > before);
+    }
+
+    Ok(Playlist {
+        id: playlist_id.to_owned(),
+        title,
+        entries,
+    })
+}
+
+/** A selection of playlist indices like "1-10,15" or "20-", starting at 1*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Items {
+    ranges: Vec<(usize, Option<usize>)>,
+}
+
+impl Items {
+    pub fn contains(&self, index: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| index >= *start && end.is_none_or(|end| index <= end))
+    }
+}
+
+impl FromStr for Items {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            anyhow!(
+                "Invalid playlist items \"{}\", expected a list like 1-10,15",
+                s
+            )
+        };
+        // Indices start at 1
+        let number = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(error)
+        };
+        let ranges = s
+            .split(',')
+            .map(|item| match item.split_once('-') {
+                Some((start, "")) => Ok((number(start)?, None)),
+                Some(("", end)) => Ok((1, Some(number(end)?))),
+                Some((start, end)) => match (number(start)?, number(end)?) {
+                    (start, end) if start <= end => Ok((start, Some(end))),
+                    _ => Err(error()),
+                },
+                None => number(item).map(|n| (n, Some(n))),
+            })
+            .collect::<anyhow::Result<Vec<(usize, Option<usize>)>>>()?;
+        Ok(Items { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+
+    #[test]
+    fn parses_items() {
+        let items: Items = "1-3, 7,10-".parse().unwrap();
+        let selected: Vec<usize> = (1..=12).filter(|i| items.contains(*i)).collect();
+        assert_eq!(selected, [1, 2, 3, 7, 10, 11, 12]);
+        let items: Items = "-2".parse().unwrap();
+        assert!(items.contains(1) && items.contains(2) && !items.contains(3));
+        assert_eq!("4-4".parse::<Items>().unwrap(), "4".parse().unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_items() {
+        for items in ["0", "0-5", "10-5", "", "1,,2", "a-3", "-", "1-2-3"] {
+            assert_eq!(
+                items.parse::<Items>().unwrap_err().to_string(),
+                format!(
+                    "Invalid playlist items \"{}\", expected a list like 1-10,15",
+                    items
+                )
+            );
+        }
+    }
+
+    fn video(video_id: &str, index: Option<&str>, playable: bool) -> JsonValue {
+        let mut renderer = object! {
+            videoId: video_id,
+            title: { runs: [{ text: format!("Title of {}", video_id) }] },
+            lengthSeconds: "212",
+            isPlayable: playable,
+        };
+        if let Some(index) = index {
+            renderer["index"] = object! { simpleText: index };
+        }
+        object! { playlistVideoRenderer: renderer }
+    }
+
+    #[test]
+    fn parses_playable_entries() {
+        let page = object! {
+            contents: {
+                playlistVideoListRenderer: {
+                    contents: [
+                        video("aaaaaaaaaaa", Some("1"), true),
+                        video("bbbbbbbbbbb", Some("2"), false),
+                        video("ccccccccccc", Some("3"), true),
+                        { continuationItemRenderer: {} },
+                    ],
+                },
+            },
+        };
+        let mut entries = Vec::new();
+        parse_entries(&page, &mut entries);
+        // The unplayable second video is skipped, the others keep their index
+        let parsed: Vec<(usize, &str)> = entries
+            .iter()
+            .map(|e| (e.get_index(), e.get_video_id()))
+            .collect();
+        assert_eq!(parsed, [(1, "aaaaaaaaaaa"), (3, "ccccccccccc")]);
+        assert_eq!(entries[1].get_title(), "Title of ccccccccccc");
+        assert_eq!(entries[1].get_length_seconds(), Some(212));
+    }
+
+    #[test]
+    fn falls_back_to_position() {
+        let mut entries = Vec::new();
+        parse_entries(&video("aaaaaaaaaaa", None, true), &mut entries);
+        let page = object! {
+            contents: [video("bbbbbbbbbbb", None, true), { playlistVideoRenderer: {} }],
+        };
+        parse_entries(&page, &mut entries);
+        let parsed: Vec<(usize, &str)> = entries
+            .iter()
+            .map(|e| (e.get_index(), e.get_video_id()))
+            .collect();
+        assert_eq!(parsed, [(1, "aaaaaaaaaaa"), (2, "bbbbbbbbbbb")]);
+    }
+}
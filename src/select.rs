@@ -0,0 +1,115 @@
+use crate::format::ACodec;
+use crate::video::Stream;
+use anyhow::anyhow;
+use std::str::FromStr;
+
+pub mod expression;
+
+pub use expression::{Selection, Selector, SortOrder};
+
+/** Streams having a video track, from the lowest to the highest resolution and frame rate*/
+pub fn video_streams(streams: &[Stream]) -> Vec<&Stream> {
+    let mut video: Vec<&Stream> = streams
+        .iter()
+        .filter(|s| s.get_format().get_height() != -1)
+        .collect();
+    video.sort_by_key(|s| (s.get_format().get_height(), s.get_format().get_fps()));
+    video
+}
+
+/** Audio only streams, from the lowest to the highest bitrate*/
+pub fn audio_streams(streams: &[Stream]) -> Vec<&Stream> {
+    let mut audio: Vec<&Stream> = streams
+        .iter()
+        .filter(|s| s.get_format().get_height() == -1)
+        .collect();
+    audio.sort_by_key(|s| s.get_format().get_audio_bitrate());
+    audio
+}
+
+/** Video streams without an audio track, which need a separate audio stream*/
+pub fn video_only_streams(streams: &[Stream]) -> Vec<&Stream> {
+    video_streams(streams)
+        .into_iter()
+        .filter(|s| *s.get_format().get_audio_codec().get_kind() == ACodec::NONE)
+        .collect()
+}
+
+/** Whether the audio stream can be muxed together with the video into the video's container*/
+pub fn can_merge(video: &Stream, audio: &Stream) -> bool {
+    video.get_format().is_dash_container()
+        && audio.get_format().is_dash_container()
+        && matches!(
+            (
+                video.get_format().get_extension(),
+                audio.get_format().get_extension()
+            ),
+            ("mp4", "m4a") | ("webm", "webm")
+        )
+}
+
+/** The best of audio streams ordered from the worst to the best one that can be muxed with the video*/
+pub fn audio_for<'a>(video: &Stream, audio: &[&'a Stream]) -> Option<&'a Stream> {
+    audio.iter().rev().find(|a| can_merge(video, a)).copied()
+}
+
+/** The stream with the highest resolution, preferring higher frame rates*/
+pub fn best_video(streams: &[Stream]) -> Option<&Stream> {
+    video_streams(streams)
+        .into_iter()
+        .max_by_key(|s| (s.get_format().get_height(), s.get_format().get_fps()))
+}
+
+/** The audio only stream with the highest bitrate*/
+pub fn best_audio(streams: &[Stream]) -> Option<&Stream> {
+    audio_streams(streams).into_iter().last()
+}
+
+/** The stream of the given itag*/
+pub fn by_itag(streams: &[Stream], itag: i32) -> Option<&Stream> {
+    streams.iter().find(|s| s.get_format().get_itag() == itag)
+}
+
+/** A requested quality, either relative or a maximal video height*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Quality {
+    Best,
+    Worst,
+    Height(i32),
+}
+
+impl FromStr for Quality {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "best" => Ok(Quality::Best),
+            "worst" => Ok(Quality::Worst),
+            height => height
+                .trim_end_matches('p')
+                .parse::<i32>()
+                .map(Quality::Height)
+                .map_err(|_| {
+                    anyhow!(
+                        "Unknown quality \"{}\", expected best, worst or a height like 720p",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+/** Picks from streams ordered from the worst to the best one.
+A height picks the best stream not higher than it, or the lowest one if all are higher*/
+pub fn by_quality<'a>(streams: &[&'a Stream], quality: Quality) -> Option<&'a Stream> {
+    match quality {
+        Quality::Best => streams.last().copied(),
+        Quality::Worst => streams.first().copied(),
+        Quality::Height(height) => streams
+            .iter()
+            .rev()
+            .find(|s| s.get_format().get_height() <= height)
+            .or_else(|| streams.first())
+            .copied(),
+    }
+}
@@ -0,0 +1,883 @@
+//! Format selection expressions like `bestvideo[height<=1080][vcodec^=avc1]+bestaudio[ext=m4a]/best`.
+//!
+//! A [`Selector`] is a list of alternatives separated by `/`, the first one matching any stream
+//! wins. An alternative is a single format or two formats joined by `+` to be merged. A format
+//! is one of `best`, `worst`, `bestvideo`, `worstvideo`, `bestaudio`, `worstaudio` (or their
+//! short forms `b`, `w`, `bv`, `wv`, `ba`, `wa`), an itag or an extension, followed by filters
+//! like `[height<=720]`. A `?` after the operator also accepts formats lacking the field.
+//! Candidates are ranked by a [`SortOrder`] like `height,fps,+filesize`.
+
+use super::can_merge;
+use crate::format::{ACodec, AudioCodec, Format, VCodec, VideoCodec};
+use crate::video::{Protocol, Stream};
+use anyhow::anyhow;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/** A field of [`Format`] usable in filters and sort keys*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Field {
+    Itag,
+    Ext,
+    MimeType,
+    Codecs,
+    VCodec,
+    ACodec,
+    Width,
+    Height,
+    Fps,
+    Hdr,
+    BitDepth,
+    Abr,
+    Bitrate,
+    AverageBitrate,
+    Filesize,
+    QualityLabel,
+    Asr,
+    AudioChannels,
+    Dash,
+    Hls,
+    Protocol,
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "itag" | "format_id" => Field::Itag,
+            "ext" => Field::Ext,
+            "mime_type" | "mime" => Field::MimeType,
+            "codecs" => Field::Codecs,
+            "vcodec" => Field::VCodec,
+            "acodec" => Field::ACodec,
+            "width" => Field::Width,
+            "height" => Field::Height,
+            "fps" => Field::Fps,
+            "hdr" => Field::Hdr,
+            "bit_depth" => Field::BitDepth,
+            "abr" | "audio_bitrate" => Field::Abr,
+            "tbr" | "bitrate" => Field::Bitrate,
+            "average_bitrate" => Field::AverageBitrate,
+            "filesize" | "content_length" => Field::Filesize,
+            "quality_label" | "format_note" => Field::QualityLabel,
+            "asr" | "audio_sample_rate" => Field::Asr,
+            "audio_channels" => Field::AudioChannels,
+            "dash" => Field::Dash,
+            "hls" => Field::Hls,
+            "protocol" => Field::Protocol,
+            field => return Err(anyhow!("Unknown format field \"{}\"", field)),
+        })
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Field::Itag => "itag",
+            Field::Ext => "ext",
+            Field::MimeType => "mime_type",
+            Field::Codecs => "codecs",
+            Field::VCodec => "vcodec",
+            Field::ACodec => "acodec",
+            Field::Width => "width",
+            Field::Height => "height",
+            Field::Fps => "fps",
+            Field::Hdr => "hdr",
+            Field::BitDepth => "bit_depth",
+            Field::Abr => "abr",
+            Field::Bitrate => "bitrate",
+            Field::AverageBitrate => "average_bitrate",
+            Field::Filesize => "filesize",
+            Field::QualityLabel => "quality_label",
+            Field::Asr => "asr",
+            Field::AudioChannels => "audio_channels",
+            Field::Dash => "dash",
+            Field::Hls => "hls",
+            Field::Protocol => "protocol",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/** The value of a field*/
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+/** The codec family as written in codec strings, so filters like `vcodec^=avc1` work
+even for formats only known from the itag table*/
+fn video_codec_name(format: &Format) -> String {
+    let codecs = format.get_codecs();
+    if let Some(codec) = codecs.split(',').find(|c| VideoCodec::parse(c).is_some()) {
+        return codec.trim().to_owned();
+    }
+    match format.get_video_codec().get_kind() {
+        VCodec::H263 => "h263",
+        VCodec::H264 => "avc1",
+        VCodec::H265 => "hvc1",
+        VCodec::MPEG4 => "mp4v",
+        VCodec::VP8 => "vp8",
+        VCodec::VP9 => "vp9",
+        VCodec::AV1 => "av01",
+        VCodec::NONE => "none",
+    }
+    .to_owned()
+}
+
+fn audio_codec_name(format: &Format) -> String {
+    let codecs = format.get_codecs();
+    if let Some(codec) = codecs.split(',').find(|c| AudioCodec::parse(c).is_some()) {
+        return codec.trim().to_owned();
+    }
+    match format.get_audio_codec().get_kind() {
+        ACodec::MP3 => "mp3",
+        ACodec::AAC => "mp4a",
+        ACodec::VORBIS => "vorbis",
+        ACodec::OPUS => "opus",
+        ACodec::AC3 => "ac-3",
+        ACodec::EAC3 => "ec-3",
+        ACodec::NONE => "none",
+    }
+    .to_owned()
+}
+
+/** Ranks codecs by compression efficiency for sorting*/
+fn video_codec_rank(kind: &VCodec) -> f64 {
+    match kind {
+        VCodec::AV1 => 6.0,
+        VCodec::VP9 => 5.0,
+        VCodec::H265 => 4.0,
+        VCodec::H264 => 3.0,
+        VCodec::VP8 => 2.0,
+        VCodec::MPEG4 | VCodec::H263 => 1.0,
+        VCodec::NONE => 0.0,
+    }
+}
+
+fn audio_codec_rank(kind: &ACodec) -> f64 {
+    match kind {
+        ACodec::OPUS => 6.0,
+        ACodec::EAC3 => 5.0,
+        ACodec::AC3 => 4.0,
+        ACodec::AAC => 3.0,
+        ACodec::VORBIS => 2.0,
+        ACodec::MP3 => 1.0,
+        ACodec::NONE => 0.0,
+    }
+}
+
+fn bool_value(value: bool) -> Value {
+    Value::Text(value.to_string())
+}
+
+impl Field {
+    fn value(&self, stream: &Stream, size: Option<u64>) -> Option<Value> {
+        let format = stream.get_format();
+        let number = |n: i32| (n >= 0).then_some(Value::Number(n as f64));
+        let optional = |n: Option<u64>| n.map(|n| Value::Number(n as f64));
+        Some(match self {
+            Field::Itag => Value::Number(format.get_itag() as f64),
+            Field::Ext => Value::Text(format.get_extension().to_owned()),
+            Field::MimeType => Value::Text(format.get_mime_type().to_owned()),
+            Field::Codecs => Value::Text(format.get_codecs().to_owned()),
+            Field::VCodec => Value::Text(video_codec_name(format)),
+            Field::ACodec => Value::Text(audio_codec_name(format)),
+            Field::Width => return number(format.get_width()),
+            Field::Height => return number(format.get_height()),
+            Field::Fps => Value::Number(format.get_fps() as f64),
+            Field::Hdr => bool_value(format.get_video_codec().is_hdr()),
+            Field::BitDepth => Value::Number(format.get_video_codec().get_bit_depth() as f64),
+            Field::Abr => return number(format.get_audio_bitrate()),
+            Field::Bitrate => return optional(format.get_bitrate()),
+            Field::AverageBitrate => return optional(format.get_average_bitrate()),
+            Field::Filesize => return optional(size.or_else(|| format.get_content_length())),
+            Field::QualityLabel => Value::Text(format.get_quality_label()?.to_owned()),
+            Field::Asr => return optional(format.get_audio_sample_rate().map(u64::from)),
+            Field::AudioChannels => return optional(format.get_audio_channels().map(u64::from)),
+            Field::Dash => bool_value(format.is_dash_container()),
+            Field::Hls => bool_value(format.is_hls_content()),
+            Field::Protocol => Value::Text(
+                match stream.get_protocol() {
+                    Protocol::Https => "https",
+                    Protocol::Hls => "hls",
+                    Protocol::Otf => "otf",
+                    Protocol::Dash => "dash",
+                }
+                .to_owned(),
+            ),
+        })
+    }
+
+    /** A number to order formats by, higher is better*/
+    fn rank(&self, stream: &Stream, size: Option<u64>) -> Option<f64> {
+        let format = stream.get_format();
+        match self {
+            Field::VCodec => Some(video_codec_rank(format.get_video_codec().get_kind())),
+            Field::ACodec => Some(audio_codec_rank(format.get_audio_codec().get_kind())),
+            _ => match self.value(stream, size)? {
+                Value::Number(n) => Some(n),
+                Value::Text(text) => match text.as_str() {
+                    "true" => Some(1.0),
+                    "false" => Some(0.0),
+                    _ => None,
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+/** Operators ordered so that longer ones are matched first*/
+const OPERATORS: [(&str, Operator); 9] = [
+    ("!=", Operator::Ne),
+    ("<=", Operator::Le),
+    (">=", Operator::Ge),
+    ("^=", Operator::StartsWith),
+    ("$=", Operator::EndsWith),
+    ("*=", Operator::Contains),
+    ("=", Operator::Eq),
+    ("<", Operator::Lt),
+    (">", Operator::Gt),
+];
+
+/** Parses numbers with an optional unit like "720", "1.5M" or "100MiB"*/
+fn parse_number(value: &str) -> Option<f64> {
+    let value = value.trim().trim_end_matches(['B', 'b']);
+    let (value, base) = match value.strip_suffix('i') {
+        Some(value) => (value, 1024f64),
+        None => (value, 1000f64),
+    };
+    let (number, power) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1),
+        'M' => (&value[..value.len() - 1], 2),
+        'G' => (&value[..value.len() - 1], 3),
+        'T' => (&value[..value.len() - 1], 4),
+        _ => (value, 0),
+    };
+    Some(number.parse::<f64>().ok()? * base.powi(power))
+}
+
+/** A bracketed condition like `[height<=?1080]`*/
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    field: Field,
+    operator: Operator,
+    value: String,
+    optional: bool,
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (position, symbol, operator) = OPERATORS
+            .iter()
+            .filter_map(|(symbol, operator)| s.find(symbol).map(|p| (p, *symbol, *operator)))
+            .min_by_key(|(position, symbol, _)| (*position, std::cmp::Reverse(symbol.len())))
+            .ok_or(anyhow!("Filter \"{}\" has no operator", s))?;
+        let field = s[..position].parse()?;
+        let value = &s[position + symbol.len()..];
+        let (value, optional) = match value.strip_prefix('?') {
+            Some(value) => (value, true),
+            None => (value, false),
+        };
+        Ok(Filter {
+            field,
+            operator,
+            value: value.trim().to_owned(),
+            optional,
+        })
+    }
+}
+
+impl Filter {
+    fn matches(&self, stream: &Stream, size: Option<u64>) -> bool {
+        let value = match self.field.value(stream, size) {
+            Some(value) => value,
+            None => return self.optional,
+        };
+        let text = match &value {
+            Value::Number(n) => n.to_string(),
+            Value::Text(t) => t.clone(),
+        };
+        match self.operator {
+            Operator::StartsWith => return text.starts_with(&self.value),
+            Operator::EndsWith => return text.ends_with(&self.value),
+            Operator::Contains => return text.contains(&self.value),
+            _ => {}
+        }
+        let ordering = match (&value, parse_number(&self.value)) {
+            (Value::Number(n), Some(expected)) => n.partial_cmp(&expected),
+            _ => Some(text.as_str().cmp(self.value.as_str())),
+        };
+        let ordering = match ordering {
+            Some(ordering) => ordering,
+            None => return false,
+        };
+        match self.operator {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::Ne => ordering != Ordering::Equal,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Ge => ordering != Ordering::Less,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/** Which streams a format expression starts from*/
+#[derive(Debug, Clone, PartialEq)]
+enum Base {
+    /** Streams with both video and audio, or any stream if `all`*/
+    Combined {
+        best: bool,
+        all: bool,
+    },
+    VideoOnly {
+        best: bool,
+    },
+    AudioOnly {
+        best: bool,
+    },
+    Itag(i32),
+    Extension(String),
+}
+
+impl Base {
+    fn accepts(&self, stream: &Stream) -> bool {
+        let format = stream.get_format();
+        let video = format.get_height() != -1;
+        let audio = *format.get_audio_codec().get_kind() != ACodec::NONE;
+        match self {
+            Base::Combined { all: true, .. } => true,
+            Base::Combined { .. } => video && audio,
+            Base::VideoOnly { .. } => video && !audio,
+            Base::AudioOnly { .. } => audio && !video,
+            Base::Itag(itag) => format.get_itag() == *itag,
+            Base::Extension(ext) => format.get_extension() == ext,
+        }
+    }
+
+    fn prefers_best(&self) -> bool {
+        match self {
+            Base::Combined { best, .. } | Base::VideoOnly { best } | Base::AudioOnly { best } => {
+                *best
+            }
+            _ => true,
+        }
+    }
+}
+
+/** A single format: a base and its filters*/
+#[derive(Debug, Clone, PartialEq)]
+struct Single {
+    base: Base,
+    filters: Vec<Filter>,
+}
+
+impl FromStr for Single {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let name_end = s.find('[').unwrap_or(s.len());
+        let base = match &s[..name_end] {
+            "" => Base::Combined {
+                best: true,
+                all: true,
+            },
+            "best" | "b" => Base::Combined {
+                best: true,
+                all: false,
+            },
+            "worst" | "w" => Base::Combined {
+                best: false,
+                all: false,
+            },
+            "bestvideo" | "bv" => Base::VideoOnly { best: true },
+            "worstvideo" | "wv" => Base::VideoOnly { best: false },
+            "bestaudio" | "ba" => Base::AudioOnly { best: true },
+            "worstaudio" | "wa" => Base::AudioOnly { best: false },
+            name => match name.parse::<i32>() {
+                Ok(itag) => Base::Itag(itag),
+                Err(_) if name.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                    Base::Extension(name.to_owned())
+                }
+                Err(_) => return Err(anyhow!("Unknown format \"{}\"", name)),
+            },
+        };
+
+        let mut filters = Vec::new();
+        let mut rest = &s[name_end..];
+        while !rest.is_empty() {
+            let inner = rest
+                .strip_prefix('[')
+                .ok_or(anyhow!("Expected \"[\" in \"{}\"", rest))?;
+            let end = inner
+                .find(']')
+                .ok_or(anyhow!("Unclosed filter in \"{}\"", s))?;
+            filters.push(inner[..end].parse()?);
+            rest = &inner[end + 1..];
+        }
+        Ok(Single { base, filters })
+    }
+}
+
+impl Single {
+    fn select<'a>(
+        &self,
+        streams: &[&'a Stream],
+        order: &SortOrder,
+        sizes: &dyn Fn(&Stream) -> Option<u64>,
+    ) -> Option<&'a Stream> {
+        let candidates = streams.iter().copied().filter(|s| {
+            self.base.accepts(s) && self.filters.iter().all(|f| f.matches(s, sizes(s)))
+        });
+        let compare = |a: &&Stream, b: &&Stream| order.compare(a, b, sizes);
+        if self.base.prefers_best() {
+            candidates.max_by(compare)
+        } else {
+            candidates.min_by(compare)
+        }
+    }
+}
+
+/** The streams chosen by a [`Selector`]*/
+#[derive(Debug, Clone, Copy)]
+pub enum Selection<'a> {
+    Single(&'a Stream),
+    /** A video and an audio stream to be merged*/
+    Merge(&'a Stream, &'a Stream),
+}
+
+/** A parsed format selection expression*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    alternatives: Vec<(Single, Option<Single>)>,
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut alternatives = Vec::new();
+        for alternative in split_outside_brackets(s, '/') {
+            let parts = split_outside_brackets(alternative, '+');
+            let alternative = match parts.as_slice() {
+                [single] => (single.parse()?, None),
+                [video, audio] => (video.parse()?, Some(audio.parse()?)),
+                _ => {
+                    return Err(anyhow!(
+                        "Only two formats can be merged in \"{}\"",
+                        alternative
+                    ))
+                }
+            };
+            alternatives.push(alternative);
+        }
+        Ok(Selector { alternatives })
+    }
+}
+
+/** Splits at a separator unless it is part of a filter like `[vcodec*=a+b]`*/
+fn split_outside_brackets(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+impl Selector {
+    /** Evaluates the alternatives in order, returning the first that matches.
+    `sizes` gives the probed size of a stream, used by `filesize` before the format's own*/
+    pub fn select<'a>(
+        &self,
+        streams: &[&'a Stream],
+        order: &SortOrder,
+        sizes: &dyn Fn(&Stream) -> Option<u64>,
+    ) -> Option<Selection<'a>> {
+        self.alternatives.iter().find_map(|(first, second)| {
+            let first_stream = first.select(streams, order, sizes)?;
+            match second {
+                None => Some(Selection::Single(first_stream)),
+                Some(second) => {
+                    // Only streams that can be muxed with the first one are considered
+                    let mergeable: Vec<&Stream> = streams
+                        .iter()
+                        .copied()
+                        .filter(|s| can_merge(first_stream, s))
+                        .collect();
+                    let second_stream = second.select(&mergeable, order, sizes)?;
+                    Some(Selection::Merge(first_stream, second_stream))
+                }
+            }
+        })
+    }
+}
+
+/** Fields formats are ranked by, each preferring higher values unless prefixed with `+`*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortOrder {
+    keys: Vec<(Field, bool)>,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder {
+            keys: vec![
+                (Field::Height, false),
+                (Field::Fps, false),
+                (Field::Abr, false),
+                (Field::Bitrate, false),
+            ],
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split(',')
+            .filter(|k| !k.trim().is_empty())
+            .map(|key| {
+                let key = key.trim();
+                match key.strip_prefix('+') {
+                    Some(field) => Ok((field.parse()?, true)),
+                    None => Ok((key.trim_start_matches('-').parse()?, false)),
+                }
+            })
+            .collect::<anyhow::Result<Vec<(Field, bool)>>>()?;
+        if let Some((field, _)) = keys.iter().find(|(f, _)| {
+            matches!(
+                f,
+                Field::Ext
+                    | Field::MimeType
+                    | Field::Codecs
+                    | Field::QualityLabel
+                    | Field::Protocol
+            )
+        }) {
+            return Err(anyhow!("Cannot sort by {}", field));
+        }
+        Ok(SortOrder { keys })
+    }
+}
+
+impl Display for SortOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .map(|(field, ascending)| format!("{}{}", if *ascending { "+" } else { "" }, field))
+            .collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+impl SortOrder {
+    /** Orders two streams so that the preferred one is greater. Missing values rank lowest*/
+    fn compare(&self, a: &Stream, b: &Stream, sizes: &dyn Fn(&Stream) -> Option<u64>) -> Ordering {
+        for (field, ascending) in &self.keys {
+            let ordering = match (field.rank(a, sizes(a)), field.rank(b, sizes(b))) {
+                (Some(x), Some(y)) if *ascending => y.partial_cmp(&x).unwrap_or(Ordering::Equal),
+                (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Greater,
+                (None, Some(_)) => Ordering::Less,
+                (None, None) => Ordering::Equal,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+
+    fn stream(value: json::JsonValue, adaptive: bool) -> Stream {
+        let format = Format::from_json(&value, adaptive, None).unwrap();
+        Stream::new(format!("https://example.com/{}", format.get_itag()), format)
+    }
+
+    fn video(itag: i32, mime: &str, height: i32, fps: u32, size: Option<&str>) -> Stream {
+        let mut value = object! {
+            itag: itag,
+            mimeType: mime,
+            width: height * 16 / 9,
+            height: height,
+            fps: fps,
+            bitrate: height * 2000,
+        };
+        if let Some(size) = size {
+            value["contentLength"] = size.into();
+        }
+        stream(value, true)
+    }
+
+    fn audio(itag: i32, mime: &str, bitrate: u32) -> Stream {
+        stream(
+            object! {
+                itag: itag,
+                mimeType: mime,
+                bitrate: bitrate,
+                averageBitrate: bitrate,
+                contentLength: "3000000",
+                audioSampleRate: "48000",
+            },
+            true,
+        )
+    }
+
+    fn streams() -> Vec<Stream> {
+        vec![
+            stream(
+                object! {
+                    itag: 18,
+                    mimeType: "video/mp4; codecs=\"avc1.42001E, mp4a.40.2\"",
+                    width: 640,
+                    height: 360,
+                    fps: 30,
+                    bitrate: 500000,
+                },
+                false,
+            ),
+            video(
+                134,
+                "video/mp4; codecs=\"avc1.4d401e\"",
+                360,
+                30,
+                Some("8000000"),
+            ),
+            video(
+                136,
+                "video/mp4; codecs=\"avc1.4d401f\"",
+                720,
+                30,
+                Some("40000000"),
+            ),
+            video(298, "video/mp4; codecs=\"avc1.4d4020\"", 720, 60, None),
+            video(
+                137,
+                "video/mp4; codecs=\"avc1.640028\"",
+                1080,
+                30,
+                Some("90000000"),
+            ),
+            video(
+                248,
+                "video/webm; codecs=\"vp9\"",
+                1080,
+                25,
+                Some("70000000"),
+            ),
+            audio(140, "audio/mp4; codecs=\"mp4a.40.2\"", 130000),
+            audio(139, "audio/mp4; codecs=\"mp4a.40.5\"", 49000),
+            audio(251, "audio/webm; codecs=\"opus\"", 160000),
+        ]
+    }
+
+    fn select(expression: &str, streams: &[Stream]) -> Option<Vec<i32>> {
+        select_sorted(expression, &SortOrder::default(), streams)
+    }
+
+    fn select_sorted(expression: &str, order: &SortOrder, streams: &[Stream]) -> Option<Vec<i32>> {
+        let selector: Selector = expression.parse().unwrap();
+        let streams: Vec<&Stream> = streams.iter().collect();
+        let itag = |s: &Stream| s.get_format().get_itag();
+        Some(match selector.select(&streams, order, &|_| None)? {
+            Selection::Single(stream) => vec![itag(stream)],
+            Selection::Merge(video, audio) => vec![itag(video), itag(audio)],
+        })
+    }
+
+    fn parse_error(expression: &str) -> String {
+        expression.parse::<Selector>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_short_forms() {
+        let short: Selector = "bv[height<=720]+ba/b".parse().unwrap();
+        let long: Selector = "bestvideo[height<=720]+bestaudio/best".parse().unwrap();
+        assert_eq!(short, long);
+        let spaced: Selector = " bestvideo[ height <= 720 ] + bestaudio / best"
+            .parse()
+            .unwrap();
+        assert_eq!(spaced, long);
+    }
+
+    #[test]
+    fn keeps_separators_inside_filters() {
+        let streams = streams();
+        assert_eq!(
+            select("b[codecs*=avc1.42001E, mp4a]", &streams),
+            Some(vec![18])
+        );
+        assert_eq!(
+            select("bv[codecs!=a+b/c]+ba", &streams),
+            Some(vec![137, 140])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert_eq!(
+            parse_error("bv[height<=720"),
+            "Unclosed filter in \"bv[height<=720\""
+        );
+        assert_eq!(
+            parse_error("bv[height]"),
+            "Filter \"height\" has no operator"
+        );
+        assert_eq!(parse_error("bv[size<10M]"), "Unknown format field \"size\"");
+        assert_eq!(parse_error("bv[fps>30]x"), "Expected \"[\" in \"x\"");
+        assert_eq!(parse_error("best-video"), "Unknown format \"best-video\"");
+        assert_eq!(
+            parse_error("bv+ba+ba"),
+            "Only two formats can be merged in \"bv+ba+ba\""
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_sort_orders() {
+        assert_eq!(
+            "height,ext".parse::<SortOrder>().unwrap_err().to_string(),
+            "Cannot sort by ext"
+        );
+        assert_eq!(
+            "+size".parse::<SortOrder>().unwrap_err().to_string(),
+            "Unknown format field \"size\""
+        );
+        let order: SortOrder = "height, +filesize,-fps".parse().unwrap();
+        assert_eq!(order.to_string(), "height,+filesize,fps");
+    }
+
+    #[test]
+    fn selects_by_base() {
+        let streams = streams();
+        assert_eq!(select("best", &streams), Some(vec![18]));
+        assert_eq!(select("bestvideo", &streams), Some(vec![137]));
+        assert_eq!(select("worstvideo", &streams), Some(vec![134]));
+        assert_eq!(select("bestaudio", &streams), Some(vec![251]));
+        assert_eq!(select("worstaudio", &streams), Some(vec![139]));
+        assert_eq!(select("136", &streams), Some(vec![136]));
+        assert_eq!(select("webm", &streams), Some(vec![248]));
+    }
+
+    #[test]
+    fn matches_filters() {
+        let streams = streams();
+        assert_eq!(select("bv[height<=720]", &streams), Some(vec![298]));
+        assert_eq!(select("bv[height=720][fps<60]", &streams), Some(vec![136]));
+        assert_eq!(
+            select("bv[height>=1080][vcodec^=vp9]", &streams),
+            Some(vec![248])
+        );
+        assert_eq!(select("bv[ext!=mp4]", &streams), Some(vec![248]));
+        assert_eq!(select("ba[acodec=opus]", &streams), Some(vec![251]));
+        assert_eq!(select("ba[abr<100]", &streams), Some(vec![139]));
+        assert_eq!(select("ba[asr=48k]", &streams), Some(vec![251]));
+        assert_eq!(select("bv[height>1080]", &streams), None);
+    }
+
+    #[test]
+    fn matches_unit_suffixes() {
+        let streams = streams();
+        assert_eq!(select("bv[filesize<50M]", &streams), Some(vec![136]));
+        assert_eq!(select("bv[filesize<10MB]", &streams), Some(vec![134]));
+        assert_eq!(select("bv[filesize>80MiB]", &streams), Some(vec![137]));
+        assert_eq!(select("bv[filesize<=8000k]", &streams), Some(vec![134]));
+        assert_eq!(select("bv[tbr>=1.5M]", &streams), Some(vec![137]));
+    }
+
+    #[test]
+    fn optional_filters_accept_missing_fields() {
+        let streams = streams();
+        // Itag 298 has no content length
+        assert_eq!(
+            select("bv[height=720][filesize<1G]", &streams),
+            Some(vec![136])
+        );
+        assert_eq!(
+            select("bv[height=720][filesize<?1G]", &streams),
+            Some(vec![298])
+        );
+        assert_eq!(select("bv[fps=60][filesize>0]", &streams), None);
+        assert_eq!(select("bv[fps=60][filesize>?0]", &streams), Some(vec![298]));
+    }
+
+    #[test]
+    fn falls_back_to_later_alternatives() {
+        let streams = streams();
+        assert_eq!(
+            select("bv[height>1080]/bv[height<=720]", &streams),
+            Some(vec![298])
+        );
+        assert_eq!(select("bv[height>1080]+ba/best", &streams), Some(vec![18]));
+        assert_eq!(select("299/298/136", &streams), Some(vec![298]));
+        assert_eq!(select("bv[height>1080]/ba[abr>500]", &streams), None);
+    }
+
+    #[test]
+    fn merges_compatible_streams() {
+        let streams = streams();
+        assert_eq!(select("bv+ba", &streams), Some(vec![137, 140]));
+        assert_eq!(select("bv[ext=webm]+ba", &streams), Some(vec![248, 251]));
+        assert_eq!(select("bv+wa", &streams), Some(vec![137, 139]));
+        // The opus audio cannot be muxed into MP4, so the second format finds nothing
+        assert_eq!(select("bv[ext=mp4]+ba[acodec=opus]", &streams), None);
+        assert_eq!(
+            select("bv[ext=mp4]+ba[acodec=opus]/bv+ba[acodec=opus]", &streams),
+            None
+        );
+        assert_eq!(
+            select("bv[ext=mp4]+ba[acodec=opus]/bv[ext=webm]+ba", &streams),
+            Some(vec![248, 251])
+        );
+    }
+
+    #[test]
+    fn ranks_by_sort_order() {
+        let streams = streams();
+        let smallest: SortOrder = "+filesize".parse().unwrap();
+        assert_eq!(select_sorted("bv", &smallest, &streams), Some(vec![134]));
+        let codec: SortOrder = "height,vcodec".parse().unwrap();
+        assert_eq!(select_sorted("bv", &codec, &streams), Some(vec![248]));
+        let fps: SortOrder = "fps,height".parse().unwrap();
+        assert_eq!(select_sorted("bv", &fps, &streams), Some(vec![298]));
+    }
+}
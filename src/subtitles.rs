@@ -0,0 +1,470 @@
+//! Subtitles and automatic captions served by the timedtext api.
+//!
+//! Tracks are listed in the player response's captions, fetched as json3 (falling back to srv3)
+//! and written as SRT or WebVTT. Translatable tracks are machine translated with `tlang`.
+
+use anyhow::anyhow;
+use json::JsonValue;
+use reqwest::Url;
+use roxmltree::Document;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/** A subtitle track listed by captionTracks*/
+#[derive(Debug, Clone)]
+pub struct CaptionTrack {
+    url: String,
+    language_code: String,
+    name: String,
+    automatic: bool,
+    translatable: bool,
+}
+
+impl CaptionTrack {
+    /** The timedtext url of the track*/
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    /** The language like "en" or "pt-BR"*/
+    pub fn get_language_code(&self) -> &str {
+        &self.language_code
+    }
+
+    /** The display name like "English (auto-generated)"*/
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /** Whether the track was generated by speech recognition*/
+    pub fn is_automatic(&self) -> bool {
+        self.automatic
+    }
+
+    /** Whether the track can be translated into the video's translation languages*/
+    pub fn is_translatable(&self) -> bool {
+        self.translatable
+    }
+}
+
+/** Reads the tracks and the codes of the translation languages of a player response*/
+pub(crate) fn parse_captions(value: &JsonValue) -> (Vec<CaptionTrack>, Vec<String>) {
+    let renderer = &value["captions"]["playerCaptionsTracklistRenderer"];
+    let tracks = renderer["captionTracks"]
+        .members()
+        .filter_map(|track| {
+            Some(CaptionTrack {
+                url: track["baseUrl"].as_str()?.to_owned(),
+                language_code: track["languageCode"].as_str()?.to_owned(),
+                name: crate::innertube::text(&track["name"]).unwrap_or_default(),
+                automatic: track["kind"].as_str() == Some("asr"),
+                translatable: track["isTranslatable"].as_bool().unwrap_or(false),
+            })
+        })
+        .collect();
+    let languages = renderer["translationLanguages"]
+        .members()
+        .filter_map(|language| language["languageCode"].as_str())
+        .map(str::to_owned)
+        .collect();
+    (tracks, languages)
+}
+
+/** A line of text shown between two times in milliseconds*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cue {
+    start: u64,
+    end: u64,
+    text: String,
+}
+
+impl Cue {
+    pub(crate) fn new(start: u64, end: u64, text: &str) -> Cue {
+        Cue {
+            start,
+            end,
+            text: text.to_owned(),
+        }
+    }
+
+    /** The time it appears in milliseconds*/
+    pub fn get_start(&self) -> u64 {
+        self.start
+    }
+
+    /** The time it disappears in milliseconds*/
+    pub fn get_end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+}
+
+/** Adds a cue unless it only holds whitespace, as automatic captions have events
+that just move the previous lines up. Blank lines are dropped since they end a cue*/
+fn push_cue(cues: &mut Vec<Cue>, start: u64, duration: u64, text: &str) {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.is_empty() {
+        return;
+    }
+    cues.push(Cue {
+        start,
+        end: start + duration,
+        text: lines.join("\n"),
+    });
+}
+
+/** Parses the json3 format, events with utf8 segments*/
+pub(crate) fn parse_json3(text: &str) -> anyhow::Result<Vec<Cue>> {
+    let value = json::parse(text)?;
+    if !value["events"].is_array() {
+        return Err(anyhow!("Not a json3 timedtext document"));
+    }
+    let mut cues = Vec::new();
+    for event in value["events"].members() {
+        let text: String = event["segs"]
+            .members()
+            .filter_map(|s| s["utf8"].as_str())
+            .collect();
+        push_cue(
+            &mut cues,
+            event["tStartMs"].as_u64().unwrap_or(0),
+            event["dDurationMs"].as_u64().unwrap_or(0),
+            &text,
+        );
+    }
+    Ok(cues)
+}
+
+/** Parses the srv3 format, `<p t="start" d="duration">` paragraphs whose words
+may be split into `<s>` elements*/
+pub(crate) fn parse_srv3(text: &str) -> anyhow::Result<Vec<Cue>> {
+    let document = Document::parse(text)?;
+    let root = document.root_element();
+    if !root.has_tag_name("timedtext") {
+        return Err(anyhow!("Not a srv3 timedtext document"));
+    }
+    let mut cues = Vec::new();
+    for p in root.descendants().filter(|n| n.has_tag_name("p")) {
+        let number = |name: &str| p.attribute(name).and_then(|v| v.parse().ok());
+        let text: String = p
+            .descendants()
+            .filter_map(|n| {
+                if n.has_tag_name("br") {
+                    Some("\n")
+                } else {
+                    n.is_text().then(|| n.text()).flatten()
+                }
+            })
+            .collect();
+        push_cue(
+            &mut cues,
+            number("t").unwrap_or(0),
+            number("d").unwrap_or(0),
+            &text,
+        );
+    }
+    Ok(cues)
+}
+
+/** A subtitle file format to write*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+        }
+    }
+
+    /** Writes the cues in this format*/
+    pub fn write(&self, cues: &[Cue]) -> String {
+        match self {
+            SubtitleFormat::Srt => to_srt(cues),
+            SubtitleFormat::Vtt => to_vtt(cues),
+        }
+    }
+}
+
+impl Display for SubtitleFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.get_extension())
+    }
+}
+
+impl FromStr for SubtitleFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "srt" => Ok(SubtitleFormat::Srt),
+            "vtt" | "webvtt" => Ok(SubtitleFormat::Vtt),
+            _ => Err(anyhow!(
+                "Unknown subtitle format \"{}\", expected srt or vtt",
+                s
+            )),
+        }
+    }
+}
+
+/** Formats milliseconds like 01:02:03,456 with the given separator before the milliseconds*/
+fn timestamp(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut srt = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            timestamp(cue.start, ','),
+            timestamp(cue.end, ','),
+            cue.text
+        ));
+    }
+    srt
+}
+
+/** Escapes text for WebVTT, whose cue text is markup*/
+pub(crate) fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn to_vtt(cues: &[Cue]) -> String {
+    let mut vtt = "WEBVTT\n\n".to_owned();
+    for cue in cues {
+        vtt.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            timestamp(cue.start, '.'),
+            timestamp(cue.end, '.'),
+            escape_vtt(&cue.text)
+        ));
+    }
+    vtt
+}
+
+/** The track url with the given format and translation language*/
+fn track_url(track: &CaptionTrack, format: &str, translate: Option<&str>) -> anyhow::Result<Url> {
+    let mut url = Url::parse(&track.url)?;
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "fmt" && key != "tlang")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    {
+        let mut query = url.query_pairs_mut();
+        query.clear().extend_pairs(pairs).append_pair("fmt", format);
+        if let Some(language) = translate {
+            query.append_pair("tlang", language);
+        }
+    }
+    Ok(url)
+}
+
+/** Downloads a track, machine translated if a language is given*/
+pub async fn get_cues(track: &CaptionTrack, translate: Option<&str>) -> anyhow::Result<Vec<Cue>> {
+    let client = reqwest::Client::new();
+    let fetch = |format: &'static str| {
+        let client = &client;
+        async move {
+            let url = track_url(track, format, translate)?;
+            let text = client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            Ok::<String, anyhow::Error>(text)
+        }
+    };
+    match parse_json3(&fetch("json3").await?) {
+        Ok(cues) => Ok(cues),
+        Err(_) => parse_srv3(&fetch("srv3").await?),
+    }
+}
+
+/** Whether a track's language is the requested one, "en" also matching "en-GB"*/
+fn matches_language(code: &str, language: &str) -> bool {
+    code == language
+        || code
+            .strip_prefix(language)
+            .is_some_and(|rest| rest.starts_with('-'))
+}
+
+/** Picks a track for each requested language, "all" requesting the language of every track.
+Subtitles written by the uploader are used if `manual`, automatic captions if `automatic`,
+in that order. Without either, a translatable track is translated if `automatic` and the
+language is offered. Returns the language, the track and the language to translate into*/
+pub fn select_tracks<'a>(
+    tracks: &'a [CaptionTrack],
+    translation_languages: &[String],
+    languages: &[String],
+    manual: bool,
+    automatic: bool,
+) -> Vec<(String, &'a CaptionTrack, Option<String>)> {
+    let mut languages: Vec<String> = languages.to_vec();
+    if languages.iter().any(|l| l == "all") {
+        languages = tracks
+            .iter()
+            .filter(|t| (manual && !t.automatic) || (automatic && t.automatic))
+            .map(|t| t.language_code.clone())
+            .collect();
+        languages.sort();
+        languages.dedup();
+    }
+
+    let mut selected = Vec::new();
+    for language in languages {
+        let find = |auto: bool| {
+            tracks
+                .iter()
+                .find(|t| t.automatic == auto && matches_language(&t.language_code, &language))
+        };
+        let track = find(false)
+            .filter(|_| manual)
+            .or_else(|| find(true).filter(|_| automatic));
+        if let Some(track) = track {
+            selected.push((language, track, None));
+        } else if automatic && translation_languages.contains(&language) {
+            let source = tracks
+                .iter()
+                .filter(|t| t.translatable)
+                .min_by_key(|t| t.automatic);
+            if let Some(source) = source {
+                selected.push((language.clone(), source, Some(language)));
+            }
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start: u64, end: u64, text: &str) -> Cue {
+        Cue {
+            start,
+            end,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_json3() {
+        let text = r#"{"wireMagic": "pb3", "events": [
+            {"tStartMs": 0, "dDurationMs": 5000, "id": 1, "wpWinPosId": 1},
+            {"tStartMs": 1200, "dDurationMs": 2300, "segs": [{"utf8": "Never gonna "}, {"utf8": "give", "tOffsetMs": 400}]},
+            {"tStartMs": 3500, "dAppend": 1, "segs": [{"utf8": "\n"}]},
+            {"tStartMs": 3500, "dDurationMs": 1500, "segs": [{"utf8": "you up\n\nnever"}]}
+        ]}"#;
+        assert_eq!(
+            parse_json3(text).unwrap(),
+            vec![
+                cue(1200, 3500, "Never gonna give"),
+                cue(3500, 5000, "you up\nnever"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_json() {
+        assert_eq!(
+            parse_json3(r#"{"error": 404}"#).unwrap_err().to_string(),
+            "Not a json3 timedtext document"
+        );
+        assert!(parse_json3("<timedtext/>").is_err());
+    }
+
+    #[test]
+    fn parses_srv3() {
+        let text = r#"<?xml version="1.0" encoding="utf-8" ?>
+<timedtext format="3">
+<head><ws id="0"/></head>
+<body>
+<p t="1200" d="2300" w="1"><s ac="0">Never</s><s t="400" ac="0"> gonna</s></p>
+<p t="3500" d="1" w="1" a="1">
+</p>
+<p t="3500" d="1500">Tom &amp; Jerry<br/>&lt;3</p>
+</body>
+</timedtext>"#;
+        assert_eq!(
+            parse_srv3(text).unwrap(),
+            vec![
+                cue(1200, 3500, "Never gonna"),
+                cue(3500, 5000, "Tom & Jerry\n<3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_xml() {
+        assert_eq!(
+            parse_srv3("<transcript/>").unwrap_err().to_string(),
+            "Not a srv3 timedtext document"
+        );
+        assert!(parse_srv3("<timedtext>").is_err());
+    }
+
+    fn cues() -> Vec<Cue> {
+        vec![
+            cue(1200, 3500, "Tom & Jerry"),
+            cue(3_723_004, 3_725_000, "<i>line</i>\nsecond"),
+        ]
+    }
+
+    #[test]
+    fn writes_srt() {
+        assert_eq!(
+            to_srt(&cues()),
+            "1\n00:00:01,200 --> 00:00:03,500\nTom & Jerry\n\n\
+            2\n01:02:03,004 --> 01:02:05,000\n<i>line</i>\nsecond\n\n"
+        );
+        assert_eq!(to_srt(&[]), "");
+    }
+
+    #[test]
+    fn writes_vtt() {
+        assert_eq!(
+            to_vtt(&cues()),
+            "WEBVTT\n\n\
+            00:00:01.200 --> 00:00:03.500\nTom &amp; Jerry\n\n\
+            01:02:03.004 --> 01:02:05.000\n&lt;i&gt;line&lt;/i&gt;\nsecond\n\n"
+        );
+        assert_eq!(SubtitleFormat::Vtt.write(&[]), "WEBVTT\n\n");
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!(
+            "SRT".parse::<SubtitleFormat>().unwrap(),
+            SubtitleFormat::Srt
+        );
+        assert_eq!(
+            "webvtt".parse::<SubtitleFormat>().unwrap(),
+            SubtitleFormat::Vtt
+        );
+        assert_eq!(
+            "ass".parse::<SubtitleFormat>().unwrap_err().to_string(),
+            "Unknown subtitle format \"ass\", expected srt or vtt"
+        );
+    }
+}
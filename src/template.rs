@@ -0,0 +1,245 @@
+//! Output filename templates like `%(uploader)s/%(upload_date)s - %(title)s.%(ext)s`.
+//!
+//! A field is written as `%(name)s`, or `%(name)d` for numbers optionally padded like
+//! `%(height)05d`, and `%%` is a literal percent sign. Values are made safe for file names,
+//! so only the `/` of the template itself separates directories. Unknown values are `NA`.
+
+use crate::format::Format;
+use crate::video::VideoInfo;
+use anyhow::anyhow;
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::PathBuf;
+
+lazy_static! {
+    static ref FIELD: Regex = Regex::new("%%|%\\((\\w+)\\)(0?)(\\d*)([sd])").unwrap();
+}
+
+/** The template used when no output is given*/
+pub const DEFAULT_TEMPLATE: &str = "%(title)s [%(id)s].%(ext)s";
+
+/** Replaces characters that are invalid in file names on common filesystems.
+Path separators and `:` become similar looking characters, control characters are dropped*/
+pub fn sanitize(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            '/' | '\\' => '⧸',
+            ':' => '：',
+            '*' => '＊',
+            '?' => '？',
+            '"' => '＂',
+            '<' => '＜',
+            '>' => '＞',
+            '|' => '｜',
+            c => c,
+        })
+        .collect();
+    // Windows ignores trailing dots and spaces, and "." or ".." would change the directory
+    let sanitized = sanitized
+        .trim()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+    if sanitized.is_empty() {
+        "_".to_owned()
+    } else {
+        sanitized.to_owned()
+    }
+}
+
+/** The value of a template field, None if it is unknown*/
+fn field(name: &str, info: &VideoInfo, format: &Format) -> anyhow::Result<Option<String>> {
+    let known = |value: i64| (value >= 0).then(|| value.to_string());
+    let text = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+    // Dates are written as YYYYMMDD
+    let date = |date: Option<&str>| date.and_then(|d| d.get(..10)).map(|d| d.replace('-', ""));
+    Ok(match name {
+        "id" => Some(info.get_id().to_owned()),
+        "title" => text(info.get_title()),
+        "uploader" | "channel" | "author" => text(info.get_author()),
+        "channel_id" | "uploader_id" => text(info.get_channel_id()),
+        "upload_date" => date(info.get_upload_date()),
+        "release_date" | "publish_date" => date(info.get_publish_date()),
+        "duration" => (!info.is_live()).then(|| info.get_length_seconds().to_string()),
+        "view_count" => Some(info.get_view_count().to_string()),
+        "category" => info.get_category().map(str::to_owned),
+        "ext" => Some(format.get_extension().to_owned()),
+        "itag" | "format_id" => Some(format.get_itag().to_string()),
+        "width" => known(format.get_width() as i64),
+        "height" => known(format.get_height() as i64),
+        "resolution" => match (format.get_width(), format.get_height()) {
+            (-1, -1) => Some("audio only".to_owned()),
+            (-1, height) => Some(format!("{}p", height)),
+            (width, height) => Some(format!("{}x{}", width, height)),
+        },
+        "fps" => (format.get_fps() > 0).then(|| format.get_fps().to_string()),
+        "abr" => known(format.get_audio_bitrate() as i64),
+        "vcodec" => Some(format.get_video_codec().to_string()),
+        "acodec" => Some(format.get_audio_codec().to_string()),
+        "quality_label" | "format_note" => format.get_quality_label().map(str::to_owned),
+        name => return Err(anyhow!("Unknown template field \"{}\"", name)),
+    })
+}
+
+fn render_field(captures: &Captures, info: &VideoInfo, format: &Format) -> anyhow::Result<String> {
+    let name = match captures.get(1) {
+        Some(name) => name.as_str(),
+        None => return Ok("%".to_owned()),
+    };
+    let value = match field(name, info, format)? {
+        Some(value) => value,
+        None => return Ok("NA".to_owned()),
+    };
+    let width = captures[3].parse::<usize>().unwrap_or(0);
+    let value = match (&captures[4], &captures[2]) {
+        ("d", "0") => match value.parse::<u64>() {
+            Ok(number) => format!("{:0width$}", number, width = width),
+            Err(_) => return Err(anyhow!("Template field \"{}\" is not a number", name)),
+        },
+        _ => format!("{:>width$}", sanitize(&value), width = width),
+    };
+    Ok(value)
+}
+
+/** Whether a file name ends in an extension written in the template itself, like the ".mkv" of
+"%(title)s.mkv", rather than in a dot of some value like the title "v1.2"*/
+fn has_literal_extension(tail: &str) -> bool {
+    let name = tail.rsplit('/').next().unwrap_or("");
+    matches!(name.rsplit_once('.'), Some((_, extension)) if !extension.is_empty())
+}
+
+/** Fills the template with the metadata of the video and the format to be downloaded.
+The extension of the format is appended unless the result already ends with it or the template
+ends with an extension of its own*/
+pub fn render(template: &str, info: &VideoInfo, format: &Format) -> anyhow::Result<PathBuf> {
+    let mut result = String::new();
+    let mut last = 0;
+    for captures in FIELD.captures_iter(template) {
+        let whole = captures.get(0).unwrap();
+        result.push_str(&template[last..whole.start()]);
+        result.push_str(&render_field(&captures, info, format)?);
+        last = whole.end();
+    }
+    let tail = &template[last..];
+    result.push_str(tail);
+
+    if PathBuf::from(&result).file_name().is_none() {
+        return Err(anyhow!("Template \"{}\" names no file", template));
+    }
+    let extension = format!(".{}", format.get_extension());
+    if !result.ends_with(&extension) && !has_literal_extension(tail) {
+        result.push_str(&extension);
+    }
+    Ok(PathBuf::from(result))
+}
+
+/** Renders the template and creates the directories of the resulting path*/
+pub fn prepare(template: &str, info: &VideoInfo, format: &Format) -> anyhow::Result<PathBuf> {
+    let path = render(template, info, format)?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+
+    fn info(title: &str) -> VideoInfo {
+        let response = object! {
+            videoDetails: {
+                title: title,
+                author: "Rick Astley",
+                channelId: "UCuAXFkgsw1L7xaCfnd5JJOw",
+                lengthSeconds: "212",
+                viewCount: "1500000000",
+            },
+            microformat: {
+                playerMicroformatRenderer: {
+                    uploadDate: "2009-10-24T23:57:33-07:00",
+                    category: "Music",
+                },
+            },
+        };
+        VideoInfo::from_player_response("dQw4w9WgXcQ", &response, Vec::new())
+    }
+
+    fn format() -> Format {
+        let value = object! {
+            itag: 137,
+            mimeType: "video/mp4; codecs=\"avc1.640028\"",
+            width: 1920,
+            height: 1080,
+            fps: 30,
+        };
+        Format::from_json(&value, true, None).unwrap()
+    }
+
+    fn render_title(template: &str, title: &str) -> String {
+        render(template, &info(title), &format())
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn sanitizes_values() {
+        assert_eq!(sanitize("AC/DC: Live?"), "AC⧸DC： Live？");
+        assert_eq!(sanitize("a\\b*c\"d<e>f|g"), "a⧸b＊c＂d＜e＞f｜g");
+        assert_eq!(sanitize("line\nbreak\u{7}"), "linebreak");
+        assert_eq!(sanitize("  Title. . "), "Title");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize(""), "_");
+    }
+
+    #[test]
+    fn renders_fields() {
+        assert_eq!(
+            render_title(DEFAULT_TEMPLATE, "Never Gonna Give You Up"),
+            "Never Gonna Give You Up [dQw4w9WgXcQ].mp4"
+        );
+        assert_eq!(
+            render_title("%(uploader)s/%(upload_date)s - %(title)s", "AC/DC"),
+            "Rick Astley/20091024 - AC⧸DC.mp4"
+        );
+        assert_eq!(
+            render_title("%(resolution)s %(height)05d %(fps)d %(vcodec)s", "t"),
+            "1920x1080 01080 30 H.264 High@4.0.mp4"
+        );
+        assert_eq!(
+            render_title("%(category)s %(publish_date)s 100%%", "t"),
+            "Music NA 100%.mp4"
+        );
+        assert_eq!(render_title("%(title)6s", "abc"), "   abc.mp4");
+    }
+
+    #[test]
+    fn appends_the_extension() {
+        assert_eq!(render_title("%(title)s", "v1.2"), "v1.2.mp4");
+        assert_eq!(render_title("%(title)s.%(ext)s", "v1.2"), "v1.2.mp4");
+        assert_eq!(render_title("%(title)s.mp4", "v1.2"), "v1.2.mp4");
+        assert_eq!(render_title("%(title)s.mkv", "v1.2"), "v1.2.mkv");
+        assert_eq!(render_title("out", "t"), "out.mp4");
+        assert_eq!(render_title("v1.2/%(title)s", "t"), "v1.2/t.mp4");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let error = |template: &str| {
+            render(template, &info("t"), &format())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("%(size)s"), "Unknown template field \"size\"");
+        assert_eq!(
+            error("%(title)05d"),
+            "Template field \"title\" is not a number"
+        );
+        assert_eq!(
+            error("%(title)s/.."),
+            "Template \"%(title)s/..\" names no file"
+        );
+    }
+}
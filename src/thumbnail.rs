@@ -0,0 +1,196 @@
+//! Thumbnails of videos, saved and embedded as JPEG.
+//!
+//! The largest image is found by trying the well known names on i.ytimg.com from maxresdefault
+//! down, together with the thumbnails the player response lists. WebP images are converted.
+
+use crate::VideoInfo;
+use anyhow::anyhow;
+use image::codecs::jpeg::JpegEncoder;
+use image::ImageFormat;
+use json::JsonValue;
+
+/** Quality of JPEG images converted from other formats*/
+const JPEG_QUALITY: u8 = 90;
+
+/** Names of the thumbnails every video has, from the largest down, with their width.
+maxresdefault and sddefault only exist for videos uploaded in a high enough resolution*/
+const NAMES: [(&str, u64); 5] = [
+    ("maxresdefault", 1280),
+    ("sddefault", 640),
+    ("hqdefault", 480),
+    ("mqdefault", 320),
+    ("default", 120),
+];
+
+/** An image listed by videoDetails.thumbnail*/
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    url: String,
+    width: u64,
+    height: u64,
+}
+
+impl Thumbnail {
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn get_width(&self) -> u64 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+}
+
+/** Reads the thumbnails of a player response's videoDetails*/
+pub(crate) fn parse_thumbnails(value: &JsonValue) -> Vec<Thumbnail> {
+    value["videoDetails"]["thumbnail"]["thumbnails"]
+        .members()
+        .filter_map(|thumbnail| {
+            Some(Thumbnail {
+                url: thumbnail["url"].as_str()?.to_owned(),
+                width: thumbnail["width"].as_u64().unwrap_or(0),
+                height: thumbnail["height"].as_u64().unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+/** Urls to try from the largest image down. The listed thumbnails are put between the well
+known names by their width, WebP versions of those names are tried before the JPEG ones*/
+fn candidates(video_id: &str, thumbnails: &[Thumbnail]) -> Vec<String> {
+    let mut candidates: Vec<(u64, String)> = Vec::new();
+    for (name, width) in NAMES {
+        candidates.push((
+            width,
+            format!("https://i.ytimg.com/vi_webp/{}/{}.webp", video_id, name),
+        ));
+        candidates.push((
+            width,
+            format!("https://i.ytimg.com/vi/{}/{}.jpg", video_id, name),
+        ));
+    }
+    for thumbnail in thumbnails {
+        candidates.push((thumbnail.width, thumbnail.url.clone()));
+    }
+    // The sort is stable, so names stay ahead of listed thumbnails of the same width
+    candidates.sort_by_key(|(width, _)| std::cmp::Reverse(*width));
+    let mut urls: Vec<String> = Vec::new();
+    for (_, url) in candidates {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/** Converts an image to JPEG, JPEG images are kept as they are*/
+pub fn to_jpeg(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let format = image::guess_format(data)?;
+    if format == ImageFormat::Jpeg {
+        return Ok(data.to_vec());
+    }
+    // JPEG has no alpha channel
+    let image = image::load_from_memory_with_format(data, format)?.to_rgb8();
+    let mut jpeg = Vec::new();
+    image.write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+    Ok(jpeg)
+}
+
+/** Downloads the largest thumbnail of a video as JPEG*/
+pub async fn get_thumbnail(info: &VideoInfo) -> anyhow::Result<Vec<u8>> {
+    let client = reqwest::Client::new();
+    for url in candidates(info.get_id(), info.get_thumbnails()) {
+        // Missing sizes answer 404 with a placeholder image
+        let response = match client.get(&url).send().await {
+            Ok(response) if response.status().is_success() => response,
+            _ => continue,
+        };
+        let data = response.bytes().await?;
+        return to_jpeg(&data);
+    }
+    Err(anyhow!("Video {} has no thumbnail", info.get_id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::webp::WebPEncoder;
+    use image::{ExtendedColorType, ImageEncoder};
+    use json::object;
+
+    #[test]
+    fn tries_webp_before_jpeg() {
+        let urls = candidates("dQw4w9WgXcQ", &[]);
+        assert_eq!(urls.len(), 10);
+        assert_eq!(
+            urls[..4],
+            [
+                "https://i.ytimg.com/vi_webp/dQw4w9WgXcQ/maxresdefault.webp",
+                "https://i.ytimg.com/vi/dQw4w9WgXcQ/maxresdefault.jpg",
+                "https://i.ytimg.com/vi_webp/dQw4w9WgXcQ/sddefault.webp",
+                "https://i.ytimg.com/vi/dQw4w9WgXcQ/sddefault.jpg",
+            ]
+        );
+        assert_eq!(urls[9], "https://i.ytimg.com/vi/dQw4w9WgXcQ/default.jpg");
+    }
+
+    #[test]
+    fn places_listed_thumbnails_by_width() {
+        let response = object! {
+            videoDetails: {
+                thumbnail: {
+                    thumbnails: [
+                        {
+                            url: "https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg",
+                            width: 480,
+                            height: 360,
+                        },
+                        {
+                            url: "https://i.ytimg.com/vi/dQw4w9WgXcQ/custom.jpg",
+                            width: 1920,
+                            height: 1080,
+                        },
+                        {
+                            url: "https://i.ytimg.com/vi/dQw4w9WgXcQ/wide.jpg",
+                            width: 640,
+                            height: 360,
+                        },
+                        { height: 90 },
+                    ],
+                },
+            },
+        };
+        let thumbnails = parse_thumbnails(&response);
+        assert_eq!(thumbnails.len(), 3);
+        assert_eq!(thumbnails[1].get_height(), 1080);
+        let urls = candidates("dQw4w9WgXcQ", &thumbnails);
+        // Listed duplicates of the well known names are only tried once
+        assert_eq!(urls.len(), 12);
+        assert_eq!(urls[0], "https://i.ytimg.com/vi/dQw4w9WgXcQ/custom.jpg");
+        assert_eq!(urls[5], "https://i.ytimg.com/vi/dQw4w9WgXcQ/wide.jpg");
+        assert_eq!(
+            urls[6],
+            "https://i.ytimg.com/vi_webp/dQw4w9WgXcQ/hqdefault.webp"
+        );
+        assert_eq!(urls[7], "https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg");
+    }
+
+    #[test]
+    fn converts_webp_to_jpeg() {
+        let pixels: Vec<u8> = (0..4 * 4 * 4).map(|i| (i * 7) as u8).collect();
+        let mut webp = Vec::new();
+        WebPEncoder::new_lossless(&mut webp)
+            .write_image(&pixels, 4, 4, ExtendedColorType::Rgba8)
+            .unwrap();
+        let jpeg = to_jpeg(&webp).unwrap();
+        assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+        let image = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 4));
+        // JPEG images are kept as they are
+        assert_eq!(to_jpeg(&jpeg).unwrap(), jpeg);
+        assert!(to_jpeg(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::format::Format;
+use crate::help;
+use crate::innertube::Client;
+use crate::subtitles::{self, CaptionTrack};
+use crate::thumbnail::{self, Thumbnail};
+use json::JsonValue;
+
+/** How the media of a stream is delivered*/
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Protocol {
+    /** A single file fetched with one request*/
+    Https,
+    /** Segments listed by an m3u8 media playlist*/
+    Hls,
+    /** Numbered segments requested with the `sq` parameter, used by new uploads and premieres*/
+    Otf,
+    /** Segments listed by a representation of an MPD manifest, the url of the stream*/
+    Dash,
+}
+
+/** A format of a video together with the url it can be downloaded from*/
+#[derive(Debug, Clone)]
+pub struct Stream {
+    url: String,
+    format: Format,
+    protocol: Protocol,
+}
+
+impl Stream {
+    pub fn new(url: String, format: Format) -> Stream {
+        Stream::with_protocol(url, format, Protocol::Https)
+    }
+
+    pub fn with_protocol(url: String, format: Format, protocol: Protocol) -> Stream {
+        Stream {
+            url,
+            format,
+            protocol,
+        }
+    }
+
+    /** The direct media url, or the playlist or manifest url of segmented streams*/
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    /** How the media behind the url is delivered*/
+    pub fn get_protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /** The format of the media behind the url*/
+    pub fn get_format(&self) -> &Format {
+        &self.format
+    }
+}
+
+/** Everything extracted about a video*/
+#[derive(Debug, Clone)]
+pub struct VideoInfo {
+    id: String,
+    title: String,
+    author: String,
+    channel_id: String,
+    length_seconds: u64,
+    view_count: u64,
+    keywords: Vec<String>,
+    description: String,
+    is_live: bool,
+    upload_date: Option<String>,
+    publish_date: Option<String>,
+    category: Option<String>,
+    captions: Vec<CaptionTrack>,
+    translation_languages: Vec<String>,
+    thumbnails: Vec<Thumbnail>,
+    streams: Vec<Stream>,
+}
+
+impl VideoInfo {
+    /** Reads the videoDetails and microformat of a player response.
+    Clients omitting the microformat leave its dates and category unknown*/
+    pub(crate) fn from_player_response(
+        id: &str,
+        value: &JsonValue,
+        streams: Vec<Stream>,
+    ) -> VideoInfo {
+        let details = &value["videoDetails"];
+        let microformat = &value["microformat"]["playerMicroformatRenderer"];
+        let text = |value: &JsonValue| value.as_str().unwrap_or("").to_owned();
+        // Numbers are sent as strings
+        let number = |value: &JsonValue| {
+            value
+                .as_str()
+                .and_then(|n| n.parse().ok())
+                .or_else(|| value.as_u64())
+                .unwrap_or(0)
+        };
+        let (captions, translation_languages) = subtitles::parse_captions(value);
+        VideoInfo {
+            id: id.to_owned(),
+            title: text(&details["title"]),
+            author: text(&details["author"]),
+            channel_id: text(&details["channelId"]),
+            length_seconds: number(&details["lengthSeconds"]),
+            view_count: number(&details["viewCount"]),
+            keywords: details["keywords"]
+                .members()
+                .filter_map(|k| k.as_str())
+                .map(str::to_owned)
+                .collect(),
+            description: text(&details["shortDescription"]),
+            is_live: details["isLive"].as_bool().unwrap_or(false),
+            upload_date: microformat["uploadDate"].as_str().map(str::to_owned),
+            publish_date: microformat["publishDate"].as_str().map(str::to_owned),
+            category: microformat["category"].as_str().map(str::to_owned),
+            captions,
+            translation_languages,
+            thumbnails: thumbnail::parse_thumbnails(value),
+            streams,
+        }
+    }
+
+    /** The 11 character video id*/
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    /** The name of the channel*/
+    pub fn get_author(&self) -> &str {
+        &self.author
+    }
+
+    /** The id of the channel, starting with "UC"*/
+    pub fn get_channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    /** The duration in seconds, 0 for live broadcasts*/
+    pub fn get_length_seconds(&self) -> u64 {
+        self.length_seconds
+    }
+
+    pub fn get_view_count(&self) -> u64 {
+        self.view_count
+    }
+
+    /** The tags of the video*/
+    pub fn get_keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    /** The full description*/
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    /** Whether the video is a broadcast that is live right now*/
+    pub fn is_live(&self) -> bool {
+        self.is_live
+    }
+
+    /** The upload date like "2009-10-24", or a timestamp with newer responses*/
+    pub fn get_upload_date(&self) -> Option<&str> {
+        self.upload_date.as_deref()
+    }
+
+    pub fn get_publish_date(&self) -> Option<&str> {
+        self.publish_date.as_deref()
+    }
+
+    /** The category like "Music" or "Gaming"*/
+    pub fn get_category(&self) -> Option<&str> {
+        self.category.as_deref()
+    }
+
+    /** Subtitles and automatic captions of the video*/
+    pub fn get_captions(&self) -> &[CaptionTrack] {
+        &self.captions
+    }
+
+    /** Languages translatable tracks can be translated into*/
+    pub fn get_translation_languages(&self) -> &[String] {
+        &self.translation_languages
+    }
+
+    /** Thumbnails listed by the player response, from the smallest up*/
+    pub fn get_thumbnails(&self) -> &[Thumbnail] {
+        &self.thumbnails
+    }
+
+    /** All streams of the video, ordered by itag*/
+    pub fn get_streams(&self) -> &[Stream] {
+        &self.streams
+    }
+}
+
+/** Extracts the metadata and streams of a video, trying the InnerTube clients in order*/
+pub async fn get_video_info(video_id: &str, clients: &[Client]) -> anyhow::Result<VideoInfo> {
+    let (mut streams, response) = help::get_streams(video_id, clients).await?;
+    streams.sort_by_key(|s| s.get_format().get_itag());
+    Ok(VideoInfo::from_player_response(
+        video_id, &response, streams,
+    ))
+}